@@ -1,33 +1,102 @@
 use dotenv::dotenv;
-use std::{result::Result, sync::Arc, time::Duration};
-use tokio::{signal, sync::oneshot, task, time};
+use serde::Deserialize;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_transaction_status::UiTransactionEncoding;
+use std::{collections::HashMap, result::Result, sync::Arc, time::Duration};
+use tokio::{
+    signal,
+    sync::{broadcast, oneshot, Notify},
+    task, time,
+};
 
-use solana_indexer::{log_events::EventLoader, rpc::RpcClientWrapper};
+use solana_indexer::{
+    checkpoint::{Checkpoint, CheckpointStore, FileCheckpointStore},
+    idl::IdlDecoder,
+    log_events::{EventLoader, ProgramConfig, ProgramFilter},
+    log_subscriber::{LogSubscriber, SubscriberStatus},
+    rate_limit::RateLimit,
+    rpc::{EndpointConfig, RpcClientWrapper},
+    sink::{EventSink, FileSink, StdoutSink},
+    storage::{postgres::PostgresSink, sqlite::SqliteSink},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
 
-    let rpc_url = get_env("SOL_RPC", "http://127.0.0.1:8899");
-    let program_addr = get_env("SOL_PROGRAM", format!("{:0>64x}", 0).as_str());
+    let rpc_urls = get_env("SOL_RPC", "http://127.0.0.1:8899");
+    let rpc_weights = get_env("SOL_RPC_WEIGHTS", "");
+    let mode = get_env("SOL_MODE", "poll");
+    let ws_url = get_env("SOL_WS", "ws://127.0.0.1:8900");
+    let program_addrs = get_env("SOL_PROGRAM", format!("{:0>64x}", 0).as_str());
     let txs_batch_size = get_env("SOL_BATCH_SIZE", "100").parse::<usize>()?;
+    let fetch_concurrency = get_env("SOL_FETCH_CONCURRENCY", "8").parse::<usize>()?;
+    let max_batch_size = get_env("SOL_RPC_MAX_BATCH", "20").parse::<usize>()?;
+    let tx_encoding = get_env("SOL_TX_ENCODING", "json");
     let block_time = get_env("SOL_BLOCK_TIME", "5000").parse::<u64>()?; // ms
     let head_slot = get_env("SOL_HEAD_SLOT", "0").parse::<u64>()?;
     let head_sig = get_env("SOL_HEAD_SIG", format!("{:0>44x}", 0).as_str());
     let tail_slot = get_env("SOL_TAIL_SLOT", "0").parse::<u64>()?;
     let tail_sig = get_env("SOL_TAIL_SIG", format!("{:0>44x}", 0).as_str());
+    let retraction_window = get_env("SOL_RETRACTION_WINDOW", "150").parse::<u64>()?; // slots
+    let recover_truncated = get_env("SOL_RECOVER_TRUNCATED", "false").parse::<bool>()?;
+    let idl_paths = get_env("SOL_IDL", "");
+    let programs_config = get_env("SOL_PROGRAMS_CONFIG", "");
+    let sink_type = get_env("SOL_SINK", "stdout");
+    let sink_url = get_env("SOL_SINK_URL", "events.jsonl");
+    let checkpoint_type = get_env("SOL_CHECKPOINT", "sink");
+    let checkpoint_path = get_env("SOL_CHECKPOINT_PATH", "checkpoints.json");
 
-    let client = RpcClientWrapper::new(rpc_url.clone());
+    let endpoint_limit = rate_limit_from_env("SOL_RPC_ENDPOINT")?;
+    let endpoints = endpoints_from_env(rpc_urls.as_str(), rpc_weights.as_str())?
+        .into_iter()
+        .map(|endpoint| endpoint.with_rate_limit(endpoint_limit))
+        .collect();
+    let client = RpcClientWrapper::with_endpoints(endpoints)?
+        .with_rate_limit(rate_limit_from_env("SOL_RPC")?)
+        .with_method_rate_limit("get_tx", rate_limit_from_env("SOL_RPC_TX")?)
+        .with_max_batch_size(max_batch_size)
+        .with_tx_encoding(tx_encoding_from_env(tx_encoding.as_str())?);
+    // database sinks can also store the checkpoints, in the same transaction as the events
+    let (sink, sink_checkpoints): (Arc<dyn EventSink>, Option<Arc<dyn CheckpointStore>>) =
+        match sink_type.as_str() {
+            "stdout" => (Arc::new(StdoutSink::new()), None),
+            "file" => (Arc::new(FileSink::open(sink_url.as_str()).await?), None),
+            "sqlite" => {
+                let sink = Arc::new(SqliteSink::open(sink_url.as_str())?);
+                (sink.clone(), Some(sink))
+            }
+            "postgres" => {
+                let sink = Arc::new(PostgresSink::connect(sink_url.as_str()).await?);
+                (sink.clone(), Some(sink))
+            }
+            _ => return Err(format!("unknown sink type {}", sink_type).into()),
+        };
+    let checkpoints: Arc<dyn CheckpointStore> = match (checkpoint_type.as_str(), sink_checkpoints) {
+        ("sink", Some(store)) => store,
+        ("sink", None) | ("file", _) => {
+            Arc::new(FileCheckpointStore::open(checkpoint_path.as_str()).await?)
+        }
+        _ => return Err(format!("unknown checkpoint store {}", checkpoint_type).into()),
+    };
 
-    let loader = Arc::new(EventLoader::new(
-        program_addr,
-        txs_batch_size,
-        client,
-        head_slot,
-        head_sig,
-        tail_slot,
-        tail_sig,
-    ));
+    let initial = Checkpoint::new(head_slot, head_sig, tail_slot, tail_sig);
+    let programs = if programs_config.is_empty() {
+        programs_from_env(program_addrs.as_str(), idl_paths.as_str(), &initial)?
+    } else {
+        programs_from_file(programs_config.as_str(), &initial)?
+    };
+    let addrs = programs
+        .iter()
+        .map(|program| program.addr.clone())
+        .collect::<Vec<_>>();
+    let loader = Arc::new(
+        EventLoader::new(programs, txs_batch_size, client, sink, checkpoints)
+            .await?
+            .with_retraction_window(retraction_window)
+            .with_fetch_concurrency(fetch_concurrency)
+            .with_truncation_recovery(recover_truncated),
+    );
 
     let (tx, shutdown) = oneshot::channel();
     tokio::spawn(async move {
@@ -36,6 +105,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    // poll_now triggers a poll ahead of the interval, e.g. to fill the gap after a websocket reconnect
+    let poll_now = Arc::new(Notify::new());
+    let subscriber = match mode.as_str() {
+        "poll" => None,
+        // hybrid: txs are written as confirmed when their websocket notification arrives,
+        // the poll fills the gaps and writes them as finalized
+        "hybrid" => {
+            let subscriber = LogSubscriber::new(ws_url.as_str(), addrs)
+                .with_commitment(CommitmentConfig::confirmed());
+            let mut statuses = subscriber.status();
+            let notifications = subscriber.run().await?;
+            let r_loader = loader.clone();
+            tokio::spawn(async move { r_loader.process_notifications(notifications).await });
+            let r_poll_now = poll_now.clone();
+            tokio::spawn(async move {
+                loop {
+                    match statuses.recv().await {
+                        Ok(SubscriberStatus::Connected) => r_poll_now.notify_one(),
+                        Ok(status) => println!("subscriber {:?}", status),
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+            Some(subscriber)
+        }
+        _ => return Err(format!("unknown mode {}", mode).into()),
+    };
+
     let e_loader = loader.clone();
     let main_handle = task::spawn(async move {
         let mut interval = time::interval(Duration::from_millis(block_time));
@@ -43,10 +141,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tokio::select! {
             _ = async {
                 loop {
-                    interval.tick().await;
+                    tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = poll_now.notified() => {},
+                    }
                     match e_loader.poll().await {
                         Ok(_) => {
                             println!("polled");
+                            for stats in e_loader.client().rate_limit_stats() {
+                                println!("rate limit {:?}", stats);
+                            }
                         },
                         Err(e) => {
                             eprintln!("failed to poll: {}", e);
@@ -63,10 +167,143 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     main_handle.await?;
+    if let Some(subscriber) = subscriber {
+        subscriber.close().await;
+    }
 
     Ok(())
 }
 
+// endpoints_from_env builds the rpc endpoints from the comma separated SOL_RPC list,
+// SOL_RPC_WEIGHTS optionally gives the weight of each endpoint (default 1)
+fn endpoints_from_env(
+    urls: &str,
+    weights: &str,
+) -> Result<Vec<EndpointConfig>, Box<dyn std::error::Error>> {
+    let urls = urls
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .collect::<Vec<_>>();
+    let weights = weights
+        .split(',')
+        .map(str::trim)
+        .filter(|weight| !weight.is_empty())
+        .map(|weight| weight.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if !weights.is_empty() && weights.len() != urls.len() {
+        return Err(format!(
+            "SOL_RPC_WEIGHTS has {} weights for {} endpoints",
+            weights.len(),
+            urls.len()
+        )
+        .into());
+    }
+    Ok(urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| EndpointConfig::new(url.to_string(), weights.get(i).copied().unwrap_or(1)))
+        .collect())
+}
+
+// rate_limit_from_env reads the <prefix>_RPS, <prefix>_BURST and <prefix>_CONCURRENCY limits,
+// missing variables are unlimited
+fn rate_limit_from_env(prefix: &str) -> Result<RateLimit, Box<dyn std::error::Error>> {
+    let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+    Ok(RateLimit {
+        requests_per_sec: var("RPS").map(|rps| rps.parse()).transpose()?,
+        burst: var("BURST").map(|burst| burst.parse()).transpose()?,
+        max_concurrent: var("CONCURRENCY").map(|max| max.parse()).transpose()?,
+    })
+}
+
+// ProgramEntry is a program of the SOL_PROGRAMS_CONFIG file
+#[derive(Deserialize)]
+struct ProgramEntry {
+    addr: String,
+    idl: Option<String>,
+    #[serde(default)]
+    filter: ProgramFilter,
+}
+
+// tx_encoding_from_env parses the encoding txs are fetched with (json, jsonParsed, base64, base58)
+fn tx_encoding_from_env(
+    tx_encoding: &str,
+) -> Result<UiTransactionEncoding, Box<dyn std::error::Error>> {
+    match tx_encoding {
+        "json" => Ok(UiTransactionEncoding::Json),
+        "jsonParsed" => Ok(UiTransactionEncoding::JsonParsed),
+        "base64" => Ok(UiTransactionEncoding::Base64),
+        "base58" => Ok(UiTransactionEncoding::Base58),
+        other => Err(format!("unsupported tx encoding {}", other).into()),
+    }
+}
+
+// programs_from_env builds the tracked programs from the comma separated SOL_PROGRAM list.
+// SOL_IDL is either a single IDL path (only with a single program) or a list of addr=path pairs.
+fn programs_from_env(
+    addrs: &str,
+    idl_paths: &str,
+    initial: &Checkpoint,
+) -> Result<Vec<ProgramConfig>, Box<dyn std::error::Error>> {
+    let addrs = addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .collect::<Vec<_>>();
+    let mut idls = HashMap::new();
+    for entry in idl_paths
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+    {
+        match entry.split_once('=') {
+            Some((addr, path)) => {
+                idls.insert(addr.trim().to_string(), path.trim().to_string());
+            }
+            None if addrs.len() == 1 => {
+                idls.insert(addrs[0].to_string(), entry.to_string());
+            }
+            None => {
+                return Err(format!(
+                    "SOL_IDL {} must be given as addr=path when several programs are tracked",
+                    entry
+                )
+                .into())
+            }
+        }
+    }
+    let mut programs = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let mut program = ProgramConfig::new(addr.to_string(), initial.clone());
+        if let Some(path) = idls.remove(addr) {
+            program = program.with_idl(IdlDecoder::from_file(path.as_str())?);
+        }
+        programs.push(program);
+    }
+    if let Some(addr) = idls.keys().next() {
+        return Err(format!("SOL_IDL references untracked program {}", addr).into());
+    }
+    Ok(programs)
+}
+
+// programs_from_file builds the tracked programs from a json array of {addr, idl, filter}
+fn programs_from_file(
+    path: &str,
+    initial: &Checkpoint,
+) -> Result<Vec<ProgramConfig>, Box<dyn std::error::Error>> {
+    let entries: Vec<ProgramEntry> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut programs = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let mut program = ProgramConfig::new(entry.addr, initial.clone()).with_filter(entry.filter);
+        if let Some(idl) = entry.idl {
+            program = program.with_idl(IdlDecoder::from_file(idl.as_str())?);
+        }
+        programs.push(program);
+    }
+    Ok(programs)
+}
+
 fn get_env(key: &str, default: &str) -> String {
     std::env::var(key).unwrap_or_else(|_| default.to_string())
 }
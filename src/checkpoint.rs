@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum CheckpointError {
+    #[error("failed to load checkpoint for {0}: {1}")]
+    LoadError(String, String),
+    #[error("failed to save checkpoint for {0}: {1}")]
+    SaveError(String, String),
+}
+
+// Checkpoint is the persisted state of the head and tail cursors of a program
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub head_slot: u64,
+    pub head_sig: String,
+    pub tail_slot: u64,
+    pub tail_sig: String,
+}
+
+impl Checkpoint {
+    pub fn new(head_slot: u64, head_sig: String, tail_slot: u64, tail_sig: String) -> Self {
+        Self {
+            head_slot,
+            head_sig,
+            tail_slot,
+            tail_sig,
+        }
+    }
+}
+
+// CheckpointStore persists the cursors of the EventLoader so it can resume after a restart.
+// Stores that are also sinks write the checkpoint of a batch in the same transaction as its events.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    async fn load(&self, program_addr: &str) -> Result<Option<Checkpoint>, CheckpointError>;
+    async fn save(
+        &self,
+        program_addr: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError>;
+}
+
+// FileCheckpointStore keeps the checkpoints of all programs in a json file.
+// The file is replaced atomically on every save, but not together with the events: a crash between
+// the write of a batch and the save of its checkpoint writes the batch again on restart.
+// Only the sqlite and postgres sinks store both in one transaction.
+pub struct FileCheckpointStore {
+    path: String,
+    checkpoints: Mutex<HashMap<String, Checkpoint>>,
+}
+
+impl FileCheckpointStore {
+    pub async fn open(path: &str) -> Result<Self, CheckpointError> {
+        let checkpoints = match tokio::fs::read(path).await {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|e| CheckpointError::LoadError(path.to_string(), e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(CheckpointError::LoadError(path.to_string(), e.to_string())),
+        };
+        Ok(Self {
+            path: path.to_string(),
+            checkpoints: Mutex::new(checkpoints),
+        })
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn load(&self, program_addr: &str) -> Result<Option<Checkpoint>, CheckpointError> {
+        Ok(self.checkpoints.lock().await.get(program_addr).cloned())
+    }
+
+    async fn save(
+        &self,
+        program_addr: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError> {
+        let err = |e: String| CheckpointError::SaveError(program_addr.to_string(), e);
+        let mut checkpoints = self.checkpoints.lock().await;
+        checkpoints.insert(program_addr.to_string(), checkpoint.clone());
+        let content = serde_json::to_vec_pretty(&*checkpoints).map_err(|e| err(e.to_string()))?;
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .map_err(|e| err(e.to_string()))?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &content)
+            .await
+            .map_err(|e| err(e.to_string()))?;
+        file.sync_all().await.map_err(|e| err(e.to_string()))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|e| err(e.to_string()))
+    }
+}
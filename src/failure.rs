@@ -0,0 +1,140 @@
+use regex::Regex;
+use serde::Serialize;
+use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+use std::sync::LazyLock;
+
+use crate::invocation::Invocation;
+use crate::log_events::SolLog;
+
+// FailureKind is where the error of a failed transaction was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    // Anchor is an `AnchorError` log of the failed program
+    Anchor,
+    // Custom is a custom program error (InstructionError::Custom) without an Anchor log
+    Custom,
+    // Instruction is any other instruction error, e.g. a missing signature
+    Instruction,
+    // Transaction is an error outside of the instructions, e.g. insufficient funds for the fee
+    Transaction,
+}
+
+impl FailureKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Anchor => "anchor",
+            FailureKind::Custom => "custom",
+            FailureKind::Instruction => "instruction",
+            FailureKind::Transaction => "transaction",
+        }
+    }
+}
+
+// TxFailure is the structured error of a failed transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxFailure {
+    pub kind: FailureKind,
+    // instruction_index is the top-level instruction that failed, None for transaction errors
+    pub instruction_index: Option<u8>,
+    // instruction is the name of the failed top-level instruction, if it is known from the IDL or the logs
+    pub instruction: Option<String>,
+    // program_id is the innermost program that failed, e.g. the target of a failed CPI
+    pub program_id: Option<String>,
+    pub error_code: Option<u32>,
+    // error_name is the name of the error code, e.g. ConstraintHasOne or a custom error of the IDL
+    pub error_name: Option<String>,
+    pub error_message: Option<String>,
+    // account is the account that caused an Anchor error, if any
+    pub account: Option<String>,
+}
+
+impl TxFailure {
+    // new classifies the error of a transaction, the error names are filled in from the logs or the IDL later
+    pub fn new(err: &TransactionError) -> Self {
+        let (kind, instruction_index, error_code) = match err {
+            TransactionError::InstructionError(index, InstructionError::Custom(code)) => {
+                (FailureKind::Custom, Some(*index), Some(*code))
+            }
+            TransactionError::InstructionError(index, _) => {
+                (FailureKind::Instruction, Some(*index), None)
+            }
+            _ => (FailureKind::Transaction, None, None),
+        };
+        Self {
+            kind,
+            instruction_index,
+            instruction: None,
+            program_id: None,
+            error_code,
+            error_name: None,
+            // the message of a custom error is only known from the logs or the IDL
+            error_message: (kind != FailureKind::Custom).then(|| err.to_string()),
+            account: None,
+        }
+    }
+
+    // with_anchor_error sets the error reported by an `AnchorError` log
+    pub fn with_anchor_error(mut self, anchor_error: AnchorErrorLog) -> Self {
+        self.kind = FailureKind::Anchor;
+        self.error_code = Some(anchor_error.number);
+        self.error_name = Some(anchor_error.code);
+        self.error_message = Some(anchor_error.message);
+        self.account = anchor_error.account;
+        self
+    }
+}
+
+// AnchorErrorLog is an error logged by an Anchor program, e.g.
+// `AnchorError occurred. Error Code: ConstraintHasOne. Error Number: 2001. Error Message: A has one constraint was violated.`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnchorErrorLog {
+    pub code: String,
+    pub number: u32,
+    pub message: String,
+    // account is set for `AnchorError caused by account: <name>.` logs
+    pub account: Option<String>,
+}
+
+static ANCHOR_ERROR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^AnchorError (?:occurred|thrown in \S+|caused by account: (\w+))\. Error Code: (\w+)\. Error Number: (\d+)\. Error Message: (.*?)\.?$",
+    )
+    .unwrap()
+});
+
+// parse_anchor_error parses the message of a `Program log:` line
+pub fn parse_anchor_error(message: &str) -> Option<AnchorErrorLog> {
+    let caps = ANCHOR_ERROR_RE.captures(message)?;
+    Some(AnchorErrorLog {
+        account: caps.get(1).map(|account| account.as_str().to_string()),
+        code: caps[2].to_string(),
+        number: caps[3].parse().ok()?,
+        message: caps[4].to_string(),
+    })
+}
+
+// failed_frame returns the innermost failed invocation of a top-level frame,
+// the callers of a failed CPI fail as well
+pub fn failed_frame(root: &Invocation) -> Option<&Invocation> {
+    if root.is_success() || root.result.is_none() {
+        return None;
+    }
+    Some(root.children.iter().find_map(failed_frame).unwrap_or(root))
+}
+
+// anchor_error returns the last `AnchorError` logged by a frame
+pub fn anchor_error(frame: &Invocation) -> Option<AnchorErrorLog> {
+    frame.logs.iter().rev().find_map(|entry| match &entry.log {
+        Some(SolLog::Log { message, .. }) => parse_anchor_error(message),
+        _ => None,
+    })
+}
+
+// instruction_name returns the name a frame logged with `Program log: Instruction: <name>`
+pub fn instruction_name(frame: &Invocation) -> Option<String> {
+    frame.logs.iter().find_map(|entry| match &entry.log {
+        Some(SolLog::Instruction { name, .. }) => Some(name.clone()),
+        _ => None,
+    })
+}
@@ -0,0 +1,678 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum IdlError {
+    #[error("failed to read idl {0}: {1}")]
+    ReadError(String, String),
+    #[error("failed to parse idl: {0}")]
+    ParseError(String),
+    #[error("invalid base64 payload: {0}")]
+    Base64Error(String),
+    #[error("payload too short for a discriminator ({0} bytes)")]
+    MissingDiscriminator(usize),
+    #[error("unknown discriminator {0:?}")]
+    UnknownDiscriminator([u8; 8]),
+    #[error("unknown type {0}")]
+    UnknownType(String),
+    #[error("unexpected end of data while decoding {0}")]
+    UnexpectedEof(String),
+    #[error("invalid data for {0}: {1}")]
+    InvalidData(String, String),
+}
+
+// Idl is an Anchor IDL, both the current (>= 0.30) and the legacy format are supported.
+// Only the parts that are needed for decoding are deserialized.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Idl {
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub instructions: Vec<IdlInstruction>,
+    #[serde(default)]
+    pub events: Vec<IdlEvent>,
+    #[serde(default)]
+    pub types: Vec<IdlTypeDef>,
+    #[serde(default)]
+    pub errors: Vec<IdlErrorCode>,
+}
+
+// IdlErrorCode is a custom error of the program, codes start at 6000
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlErrorCode {
+    pub code: u32,
+    pub name: String,
+    #[serde(default)]
+    pub msg: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlInstruction {
+    pub name: String,
+    // discriminator is only present in the current format, it is computed for legacy idls
+    #[serde(default)]
+    pub discriminator: Option<Vec<u8>>,
+    #[serde(default)]
+    pub accounts: Vec<IdlInstructionAccount>,
+    #[serde(default)]
+    pub args: Vec<IdlField>,
+}
+
+// IdlInstructionAccount is an account of an instruction, or a group of accounts (composite accounts)
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlInstructionAccount {
+    pub name: String,
+    #[serde(default)]
+    pub accounts: Option<Vec<IdlInstructionAccount>>,
+}
+
+impl IdlInstructionAccount {
+    // flatten appends the names of the accounts in the order the instruction expects them,
+    // accounts of a group are prefixed with the group name, e.g. "group.counter"
+    fn flatten(&self, prefix: &str, names: &mut Vec<String>) {
+        let name = format!("{}{}", prefix, self.name);
+        match &self.accounts {
+            Some(accounts) => accounts
+                .iter()
+                .for_each(|account| account.flatten(format!("{}.", name).as_str(), names)),
+            None => names.push(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlEvent {
+    pub name: String,
+    // discriminator is only present in the current format, it is computed for legacy idls
+    #[serde(default)]
+    pub discriminator: Option<Vec<u8>>,
+    // fields are only present in the legacy format, the current format defines them in types
+    #[serde(default)]
+    pub fields: Option<Vec<IdlField>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: IdlType,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlTypeDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: IdlTypeDefTy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum IdlTypeDefTy {
+    Struct {
+        #[serde(default)]
+        fields: IdlFields,
+    },
+    Enum {
+        variants: Vec<IdlEnumVariant>,
+    },
+    Type {
+        alias: IdlType,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlEnumVariant {
+    pub name: String,
+    #[serde(default)]
+    pub fields: IdlFields,
+}
+
+// IdlFields are either named (struct like) or tuple fields
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(untagged)]
+pub enum IdlFields {
+    #[default]
+    None,
+    Named(Vec<IdlField>),
+    Tuple(Vec<IdlType>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdlType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+    U128,
+    I128,
+    Bytes,
+    String,
+    Pubkey,
+    Option(Box<IdlType>),
+    Vec(Box<IdlType>),
+    Array(Box<IdlType>, usize),
+    Defined(String),
+}
+
+impl<'de> Deserialize<'de> for IdlType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        IdlType::from_value(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl IdlType {
+    fn from_value(value: &Value) -> Result<Self, IdlError> {
+        let unknown = || IdlError::UnknownType(value.to_string());
+        match value {
+            Value::String(name) => match name.as_str() {
+                "bool" => Ok(IdlType::Bool),
+                "u8" => Ok(IdlType::U8),
+                "i8" => Ok(IdlType::I8),
+                "u16" => Ok(IdlType::U16),
+                "i16" => Ok(IdlType::I16),
+                "u32" => Ok(IdlType::U32),
+                "i32" => Ok(IdlType::I32),
+                "f32" => Ok(IdlType::F32),
+                "u64" => Ok(IdlType::U64),
+                "i64" => Ok(IdlType::I64),
+                "f64" => Ok(IdlType::F64),
+                "u128" => Ok(IdlType::U128),
+                "i128" => Ok(IdlType::I128),
+                "bytes" => Ok(IdlType::Bytes),
+                "string" => Ok(IdlType::String),
+                "pubkey" | "publicKey" => Ok(IdlType::Pubkey),
+                _ => Err(unknown()),
+            },
+            Value::Object(obj) => {
+                if let Some(inner) = obj.get("option").or(obj.get("coption")) {
+                    return Ok(IdlType::Option(Box::new(IdlType::from_value(inner)?)));
+                }
+                if let Some(inner) = obj.get("vec") {
+                    return Ok(IdlType::Vec(Box::new(IdlType::from_value(inner)?)));
+                }
+                if let Some(Value::Array(arr)) = obj.get("array") {
+                    let len = arr.get(1).and_then(Value::as_u64).ok_or_else(unknown)?;
+                    let inner = arr.first().ok_or_else(unknown)?;
+                    return Ok(IdlType::Array(
+                        Box::new(IdlType::from_value(inner)?),
+                        len as usize,
+                    ));
+                }
+                match obj.get("defined") {
+                    // legacy format: {"defined": "Name"}
+                    Some(Value::String(name)) => Ok(IdlType::Defined(name.clone())),
+                    // current format: {"defined": {"name": "Name"}}
+                    Some(Value::Object(def)) => def
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .map(|name| IdlType::Defined(name.to_string()))
+                        .ok_or_else(unknown),
+                    _ => Err(unknown()),
+                }
+            }
+            _ => Err(unknown()),
+        }
+    }
+}
+
+// DecodedEvent is an event that was decoded with the IDL of the emitting program
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub fields: Map<String, Value>,
+}
+
+impl DecodedEvent {
+    // to_json returns the event as a flat json object, e.g. {"name":"CountChangeEvent","data":42,"label":"inc"}
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("name".to_string(), Value::String(self.name.clone()));
+        obj.extend(self.fields.clone());
+        Value::Object(obj)
+    }
+}
+
+// DecodedInstruction is an instruction that was decoded with the IDL of the invoked program
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    pub name: String,
+    pub args: Map<String, Value>,
+    // accounts are the accounts labeled with their IDL names, in the order of the instruction
+    pub accounts: Vec<(String, String)>,
+    // remaining_accounts are the accounts passed after the ones declared in the IDL
+    pub remaining_accounts: Vec<String>,
+}
+
+impl DecodedInstruction {
+    // to_json returns the instruction as a json object,
+    // e.g. {"name":"create","args":{"authority":"..."},"accounts":{"counter":"...","user":"...","system_program":"..."}}
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("name".to_string(), Value::String(self.name.clone()));
+        obj.insert("args".to_string(), Value::Object(self.args.clone()));
+        obj.insert(
+            "accounts".to_string(),
+            Value::Object(
+                self.accounts
+                    .iter()
+                    .map(|(name, key)| (name.clone(), Value::String(key.clone())))
+                    .collect(),
+            ),
+        );
+        if !self.remaining_accounts.is_empty() {
+            obj.insert(
+                "remaining_accounts".to_string(),
+                json!(self.remaining_accounts),
+            );
+        }
+        Value::Object(obj)
+    }
+}
+
+// IdlDecoder decodes borsh encoded payloads of a program based on its IDL
+pub struct IdlDecoder {
+    idl: Idl,
+    // instructions are the discriminators of the instructions, longest first,
+    // with the flattened account names of each instruction
+    instructions: Vec<(Vec<u8>, usize, Vec<String>)>,
+    events: HashMap<[u8; 8], usize>,
+    types: HashMap<String, usize>,
+}
+
+impl IdlDecoder {
+    pub fn new(idl: Idl) -> Self {
+        let mut instructions = idl
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(i, ix)| {
+                let disc = match &ix.discriminator {
+                    Some(disc) if !disc.is_empty() => disc.clone(),
+                    // legacy idls use camelCase names, anchor hashes the snake_case method name
+                    _ => discriminator("global", to_snake_case(ix.name.as_str()).as_str()).to_vec(),
+                };
+                let mut accounts = Vec::new();
+                ix.accounts
+                    .iter()
+                    .for_each(|account| account.flatten("", &mut accounts));
+                (disc, i, accounts)
+            })
+            .collect::<Vec<_>>();
+        instructions.sort_by_key(|(disc, _, _)| std::cmp::Reverse(disc.len()));
+        let events = idl
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| {
+                let disc = match event.discriminator.as_deref() {
+                    Some(disc) if disc.len() == 8 => disc.try_into().unwrap(),
+                    _ => discriminator("event", event.name.as_str()),
+                };
+                (disc, i)
+            })
+            .collect();
+        let types = idl
+            .types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| (ty.name.clone(), i))
+            .collect();
+        Self {
+            idl,
+            instructions,
+            events,
+            types,
+        }
+    }
+
+    // from_file loads an IDL json file
+    pub fn from_file(path: &str) -> Result<Self, IdlError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| IdlError::ReadError(path.to_string(), e.to_string()))?;
+        Self::from_json(content.as_str())
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, IdlError> {
+        let idl: Idl =
+            serde_json::from_str(content).map_err(|e| IdlError::ParseError(e.to_string()))?;
+        Ok(Self::new(idl))
+    }
+
+    pub fn idl(&self) -> &Idl {
+        &self.idl
+    }
+
+    // error returns the custom error with the given code
+    pub fn error(&self, code: u32) -> Option<&IdlErrorCode> {
+        self.idl.errors.iter().find(|error| error.code == code)
+    }
+
+    // decode_event_data decodes the base64 chunks of a `Program data:` log
+    pub fn decode_event_data(&self, chunks: &[String]) -> Result<DecodedEvent, IdlError> {
+        let mut data = Vec::new();
+        for chunk in chunks {
+            data.extend(
+                BASE64
+                    .decode(chunk)
+                    .map_err(|e| IdlError::Base64Error(e.to_string()))?,
+            );
+        }
+        self.decode_event(&data)
+    }
+
+    // decode_event decodes an event payload, prefixed with its 8 bytes discriminator
+    pub fn decode_event(&self, data: &[u8]) -> Result<DecodedEvent, IdlError> {
+        if data.len() < 8 {
+            return Err(IdlError::MissingDiscriminator(data.len()));
+        }
+        let disc: [u8; 8] = data[..8].try_into().unwrap();
+        let event = self
+            .events
+            .get(&disc)
+            .map(|i| &self.idl.events[*i])
+            .ok_or(IdlError::UnknownDiscriminator(disc))?;
+        let mut reader = Reader::new(&data[8..]);
+        let fields = match &event.fields {
+            Some(fields) => self.decode_named_fields(&mut reader, fields, 0)?,
+            None => match self.decode_defined(&mut reader, event.name.as_str(), 0)? {
+                Value::Object(fields) => fields,
+                other => Map::from_iter([("value".to_string(), other)]),
+            },
+        };
+        Ok(DecodedEvent {
+            name: event.name.clone(),
+            fields,
+        })
+    }
+
+    // decode_instruction decodes the data of an instruction, prefixed with its discriminator,
+    // and labels its accounts
+    pub fn decode_instruction(
+        &self,
+        data: &[u8],
+        accounts: &[String],
+    ) -> Result<DecodedInstruction, IdlError> {
+        let (disc, ix, names) = self
+            .instructions
+            .iter()
+            .find(|(disc, _, _)| data.starts_with(disc))
+            .map(|(disc, i, names)| (disc, &self.idl.instructions[*i], names))
+            .ok_or_else(|| match data.get(..8) {
+                Some(disc) => IdlError::UnknownDiscriminator(disc.try_into().unwrap()),
+                None => IdlError::MissingDiscriminator(data.len()),
+            })?;
+        let mut reader = Reader::new(&data[disc.len()..]);
+        let args = self.decode_named_fields(&mut reader, &ix.args, 0)?;
+        Ok(DecodedInstruction {
+            name: ix.name.clone(),
+            args,
+            accounts: names
+                .iter()
+                .cloned()
+                .zip(accounts.iter().cloned())
+                .collect(),
+            remaining_accounts: accounts.iter().skip(names.len()).cloned().collect(),
+        })
+    }
+
+    fn decode_named_fields(
+        &self,
+        reader: &mut Reader,
+        fields: &[IdlField],
+        depth: usize,
+    ) -> Result<Map<String, Value>, IdlError> {
+        let mut obj = Map::new();
+        for field in fields {
+            obj.insert(
+                field.name.clone(),
+                self.decode_type(reader, &field.ty, depth + 1)?,
+            );
+        }
+        Ok(obj)
+    }
+
+    fn decode_fields(
+        &self,
+        reader: &mut Reader,
+        fields: &IdlFields,
+        depth: usize,
+    ) -> Result<Value, IdlError> {
+        match fields {
+            IdlFields::None => Ok(json!({})),
+            IdlFields::Named(fields) => Ok(Value::Object(
+                self.decode_named_fields(reader, fields, depth)?,
+            )),
+            IdlFields::Tuple(types) => Ok(Value::Array(
+                types
+                    .iter()
+                    .map(|ty| self.decode_type(reader, ty, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+
+    fn decode_defined(
+        &self,
+        reader: &mut Reader,
+        name: &str,
+        depth: usize,
+    ) -> Result<Value, IdlError> {
+        // cyclic or deeply nested types would overflow the stack
+        if depth > MAX_TYPE_DEPTH {
+            return Err(IdlError::InvalidData(
+                name.to_string(),
+                format!("nested deeper than {} levels", MAX_TYPE_DEPTH),
+            ));
+        }
+        let def = self
+            .types
+            .get(name)
+            .map(|i| &self.idl.types[*i])
+            .ok_or_else(|| IdlError::UnknownType(name.to_string()))?;
+        match &def.ty {
+            IdlTypeDefTy::Struct { fields } => self.decode_fields(reader, fields, depth),
+            IdlTypeDefTy::Enum { variants } => {
+                let index = reader.read_u8(name)? as usize;
+                let variant = variants.get(index).ok_or_else(|| {
+                    IdlError::InvalidData(name.to_string(), format!("variant {}", index))
+                })?;
+                match variant.fields {
+                    IdlFields::None => Ok(Value::String(variant.name.clone())),
+                    _ => Ok(
+                        json!({ variant.name.clone(): self.decode_fields(reader, &variant.fields, depth)? }),
+                    ),
+                }
+            }
+            IdlTypeDefTy::Type { alias } => self.decode_type(reader, alias, depth + 1),
+        }
+    }
+
+    fn decode_type(
+        &self,
+        reader: &mut Reader,
+        ty: &IdlType,
+        depth: usize,
+    ) -> Result<Value, IdlError> {
+        let value = match ty {
+            IdlType::Bool => json!(reader.read_u8("bool")? != 0),
+            IdlType::U8 => json!(reader.read_u8("u8")?),
+            IdlType::I8 => json!(reader.read_u8("i8")? as i8),
+            IdlType::U16 => json!(u16::from_le_bytes(reader.read_array("u16")?)),
+            IdlType::I16 => json!(i16::from_le_bytes(reader.read_array("i16")?)),
+            IdlType::U32 => json!(u32::from_le_bytes(reader.read_array("u32")?)),
+            IdlType::I32 => json!(i32::from_le_bytes(reader.read_array("i32")?)),
+            IdlType::F32 => json!(f32::from_le_bytes(reader.read_array("f32")?)),
+            IdlType::U64 => json!(u64::from_le_bytes(reader.read_array("u64")?)),
+            IdlType::I64 => json!(i64::from_le_bytes(reader.read_array("i64")?)),
+            IdlType::F64 => json!(f64::from_le_bytes(reader.read_array("f64")?)),
+            // 128 bit integers don't fit into json numbers, so they are encoded as strings
+            IdlType::U128 => json!(u128::from_le_bytes(reader.read_array("u128")?).to_string()),
+            IdlType::I128 => json!(i128::from_le_bytes(reader.read_array("i128")?).to_string()),
+            IdlType::Bytes => {
+                let len = reader.read_len("bytes")?;
+                json!(BASE64.encode(reader.read_bytes(len, "bytes")?))
+            }
+            IdlType::String => {
+                let len = reader.read_len("string")?;
+                let bytes = reader.read_bytes(len, "string")?;
+                json!(String::from_utf8(bytes.to_vec())
+                    .map_err(|e| IdlError::InvalidData("string".to_string(), e.to_string()))?)
+            }
+            IdlType::Pubkey => {
+                json!(Pubkey::new_from_array(reader.read_array("pubkey")?).to_string())
+            }
+            IdlType::Option(inner) => match reader.read_u8("option")? {
+                0 => Value::Null,
+                _ => self.decode_type(reader, inner, depth + 1)?,
+            },
+            IdlType::Vec(inner) => {
+                let len = reader.read_len("vec")?;
+                // the length comes from the payload, it can't exceed the remaining bytes.
+                // Like borsh, vecs of zero-sized elements are rejected, nothing bounds their length.
+                match self.min_size(inner, 0) {
+                    0 if len > 0 => {
+                        return Err(IdlError::InvalidData(
+                            "vec".to_string(),
+                            "vec of zero-sized elements".to_string(),
+                        ))
+                    }
+                    size if len.saturating_mul(size) > reader.remaining() => {
+                        return Err(IdlError::UnexpectedEof("vec".to_string()))
+                    }
+                    _ => {}
+                }
+                Value::Array(
+                    (0..len)
+                        .map(|_| self.decode_type(reader, inner, depth + 1))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            IdlType::Array(inner, len) => Value::Array(
+                (0..*len)
+                    .map(|_| self.decode_type(reader, inner, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            ),
+            IdlType::Defined(name) => self.decode_defined(reader, name.as_str(), depth + 1)?,
+        };
+        Ok(value)
+    }
+
+    // min_size returns the smallest number of bytes a value of the type is encoded with,
+    // unknown and too deeply nested types count as one byte
+    fn min_size(&self, ty: &IdlType, depth: usize) -> usize {
+        if depth > MAX_TYPE_DEPTH {
+            return 1;
+        }
+        let fields_size = |fields: &IdlFields| match fields {
+            IdlFields::None => 0,
+            IdlFields::Named(fields) => fields
+                .iter()
+                .map(|field| self.min_size(&field.ty, depth + 1))
+                .fold(0, usize::saturating_add),
+            IdlFields::Tuple(types) => types
+                .iter()
+                .map(|ty| self.min_size(ty, depth + 1))
+                .fold(0, usize::saturating_add),
+        };
+        match ty {
+            IdlType::Bool | IdlType::U8 | IdlType::I8 | IdlType::Option(_) => 1,
+            IdlType::U16 | IdlType::I16 => 2,
+            IdlType::U32 | IdlType::I32 | IdlType::F32 => 4,
+            IdlType::Bytes | IdlType::String | IdlType::Vec(_) => 4,
+            IdlType::U64 | IdlType::I64 | IdlType::F64 => 8,
+            IdlType::U128 | IdlType::I128 => 16,
+            IdlType::Pubkey => 32,
+            IdlType::Array(inner, len) => self.min_size(inner, depth + 1).saturating_mul(*len),
+            IdlType::Defined(name) => match self.types.get(name).map(|i| &self.idl.types[*i].ty) {
+                Some(IdlTypeDefTy::Struct { fields }) => fields_size(fields),
+                Some(IdlTypeDefTy::Enum { .. }) => 1,
+                Some(IdlTypeDefTy::Type { alias }) => self.min_size(alias, depth + 1),
+                None => 1,
+            },
+        }
+    }
+}
+
+// MAX_TYPE_DEPTH bounds the recursion into the defined types of an idl, which may be cyclic
+const MAX_TYPE_DEPTH: usize = 64;
+
+// EVENT_IX_TAG_LE prefixes the data of the self-CPI instructions emit_cpi! records events with,
+// it is the anchor constant 0x1d9acb512ea545e4 (sha256("anchor:event")[..8]) in little endian
+pub const EVENT_IX_TAG_LE: [u8; 8] = [0xe4, 0x45, 0xa5, 0x2e, 0x51, 0xcb, 0x9a, 0x1d];
+
+// EVENT_AUTHORITY_SEED is the seed of the PDA that signs the self-CPIs of emit_cpi!
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+
+// discriminator computes the anchor discriminator for the given namespace and name, e.g. "event:CountChangeEvent"
+pub fn discriminator(namespace: &str, name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("{}:{}", namespace, name).as_bytes());
+    hash[..8].try_into().unwrap()
+}
+
+// to_snake_case converts the camelCase names of legacy idls, e.g. "initializeCounter" -> "initialize_counter"
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+// Reader is a cursor over borsh encoded data
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read_bytes(&mut self, len: usize, what: &str) -> Result<&'a [u8], IdlError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| IdlError::UnexpectedEof(what.to_string()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], IdlError> {
+        Ok(self.read_bytes(N, what)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self, what: &str) -> Result<u8, IdlError> {
+        Ok(self.read_bytes(1, what)?[0])
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read_len(&mut self, what: &str) -> Result<usize, IdlError> {
+        Ok(u32::from_le_bytes(self.read_array(what)?) as usize)
+    }
+}
@@ -0,0 +1,137 @@
+use crate::log_events::{parse_log, InvokeResult, SolLog};
+
+// LogEntry is a single log line of a transaction, together with its position in the log messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    // index is the position of the line in the log messages of the transaction
+    pub index: usize,
+    pub raw: String,
+    // log is None for lines with unknown structure
+    pub log: Option<SolLog>,
+}
+
+// ComputeUnits is the compute budget usage reported for an invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeUnits {
+    pub consumed: u64,
+    pub limit: u64,
+}
+
+// Invocation is a program invocation frame reconstructed from the logs of a transaction.
+// Top-level instructions have depth 1, CPIs made by a frame are stored in children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program_id: String,
+    pub depth: u32,
+    // logs contains the lines that were emitted while this frame was executing,
+    // including its own invoke, consumed and result lines
+    pub logs: Vec<LogEntry>,
+    pub children: Vec<Invocation>,
+    pub compute_units: Option<ComputeUnits>,
+    // result is None if the frame never returned in the logs (e.g. truncated logs)
+    pub result: Option<InvokeResult>,
+}
+
+impl Invocation {
+    fn new(program_id: String, depth: u32) -> Self {
+        Self {
+            program_id,
+            depth,
+            logs: Vec::new(),
+            children: Vec::new(),
+            compute_units: None,
+            result: None,
+        }
+    }
+
+    // is_success returns true if the invocation returned successfully
+    pub fn is_success(&self) -> bool {
+        matches!(self.result, Some(InvokeResult::Success))
+    }
+
+    // walk returns this frame and all of its descendants in depth-first (execution) order
+    pub fn walk(&self) -> Vec<&Invocation> {
+        let mut frames = vec![self];
+        for child in self.children.iter() {
+            frames.extend(child.walk());
+        }
+        frames
+    }
+}
+
+// build_invocations reconstructs the tree of program invocations from the log messages of a transaction.
+// Every parsed line is attributed to the frame that was executing when it was emitted,
+// so `Program log:` and `Program data:` lines get the program id of the actual emitter.
+// Lines emitted outside of any frame, e.g. a result line before the first invoke, are dropped.
+pub fn build_invocations(logs: &[String]) -> Vec<Invocation> {
+    let mut roots: Vec<Invocation> = Vec::new();
+    let mut stack: Vec<Invocation> = Vec::new();
+    for (index, raw) in logs.iter().enumerate() {
+        let addr = stack
+            .last()
+            .map(|frame| frame.program_id.clone())
+            .unwrap_or_default();
+        let log = parse_log(raw, addr.as_str());
+        let entry = LogEntry {
+            index,
+            raw: raw.clone(),
+            log: log.clone(),
+        };
+        match log {
+            Some(SolLog::Invoke { program_id, depth }) => {
+                let mut frame = Invocation::new(program_id, depth);
+                frame.logs.push(entry);
+                stack.push(frame);
+            }
+            Some(SolLog::Consumed {
+                consumed, limit, ..
+            }) => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.compute_units = Some(ComputeUnits { consumed, limit });
+                    frame.logs.push(entry);
+                }
+            }
+            Some(SolLog::Result { program_id, result }) => {
+                // a result without an open frame of its program (e.g. logs that don't start at
+                // the invoke line) is kept as a line of the current frame, the tree is unchanged
+                if !stack.iter().any(|frame| frame.program_id == program_id) {
+                    if let Some(frame) = stack.last_mut() {
+                        frame.logs.push(entry);
+                    }
+                    continue;
+                }
+                // close frames until the one that returned, frames without a result
+                // are left as is to not lose the logs they contain
+                while let Some(mut frame) = stack.pop() {
+                    let returned = frame.program_id == program_id;
+                    if returned {
+                        frame.result = Some(result.clone());
+                        frame.logs.push(entry.clone());
+                    }
+                    attach(&mut roots, &mut stack, frame);
+                    if returned {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.logs.push(entry);
+                }
+            }
+        }
+    }
+    // frames that are still open didn't return in the logs
+    while let Some(frame) = stack.pop() {
+        attach(&mut roots, &mut stack, frame);
+    }
+    roots
+}
+
+// attach adds a closed frame to its parent, or to the roots if it was a top-level invocation
+fn attach(roots: &mut Vec<Invocation>, stack: &mut [Invocation], frame: Invocation) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(frame),
+        None => roots.push(frame),
+    }
+}
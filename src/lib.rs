@@ -1,3 +1,12 @@
+pub mod checkpoint;
+pub mod failure;
+pub mod idl;
+pub mod invocation;
 pub mod log_events;
 pub mod log_subscriber;
+pub mod rate_limit;
+pub mod retry;
 pub mod rpc;
+pub mod sink;
+pub mod storage;
+pub mod transaction;
@@ -1,12 +1,41 @@
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
-use std::collections::HashMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::{stream, Stream, StreamExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+    transaction::TransactionError,
+};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::collections::{HashMap, VecDeque};
 use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
-    RwLock,
+    Arc, LazyLock, Mutex, RwLock,
+};
+use std::time::Duration;
+
+use crate::checkpoint::{Checkpoint, CheckpointError, CheckpointStore};
+use crate::failure::{anchor_error, failed_frame, instruction_name, FailureKind, TxFailure};
+use crate::idl::{IdlDecoder, EVENT_AUTHORITY_SEED, EVENT_IX_TAG_LE};
+use crate::invocation::{build_invocations, Invocation};
+use crate::log_subscriber::LogNotification;
+use crate::retry::RetryBudget;
+use crate::rpc::{RpcClientWrapper, RpcError};
+use crate::sink::{CommitStatus, EventBatch, EventSink, RetractedTx, Retraction};
+use crate::transaction::{
+    balance_changes, decode_message, instruction_records, BalanceChange, InstructionRecord,
+    TxInstruction, TxMessage,
 };
 
-use crate::rpc::RpcClientWrapper;
+// DEFAULT_RETRACTION_WINDOW is the number of slots a confirmed tx has to finalize before it is retracted
+pub const DEFAULT_RETRACTION_WINDOW: u64 = 150;
+// DEFAULT_FETCH_CONCURRENCY is the default number of batch requests for txs that run at the same time
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 8;
+// DEFAULT_NOTIFICATION_BUDGET is the default retry budget of the txs of websocket notifications,
+// a tx that can't be fetched quickly is left to the poll
+pub const DEFAULT_NOTIFICATION_BUDGET: RetryBudget = RetryBudget::new(3, Duration::from_secs(5));
 
 // Cursor is a helper struct to keep track of the last event that was read for an address.
 struct Cursor {
@@ -27,7 +56,11 @@ impl Cursor {
                 sig: RwLock::new(signature),
             },
             Err(e) => {
-                eprintln!("[cursor/new] could not parse signature {}: {:?}", sig.clone(), e);
+                eprintln!(
+                    "[cursor/new] could not parse signature {}: {:?}",
+                    sig.clone(),
+                    e
+                );
                 Self {
                     slot: AtomicU64::new(slot),
                     sig: RwLock::new(Signature::default()),
@@ -55,41 +88,257 @@ impl Cursor {
     }
 }
 
-// EventLoader loads log events from the Solana blockchain for a given program address.
-pub struct EventLoader {
-    client: RpcClientWrapper,
+// ProgramFilter filters the data that is indexed for a program
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProgramFilter {
+    // log_types limits the indexed logs of the program to the given types, all logs are indexed if None
+    pub log_types: Option<Vec<LogType>>,
+    // events limits the indexed events of the program to the given names, all events are indexed if None
+    pub events: Option<Vec<String>>,
+    // instructions limits the decoded instructions of the program to the given names, all are decoded if None
+    pub instructions: Option<Vec<String>>,
+    // skip_failed skips transactions that failed
+    pub skip_failed: bool,
+    // accounts limits the indexed transactions to the ones that reference one of the given accounts,
+    // including accounts loaded from address lookup tables
+    pub accounts: Option<Vec<String>>,
+}
+
+impl ProgramFilter {
+    fn accepts_log(&self, log_type: Option<LogType>) -> bool {
+        match (&self.log_types, log_type) {
+            (None, _) => true,
+            (Some(types), Some(log_type)) => types.contains(&log_type),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn accepts_tx(&self, record: &TxRecord) -> bool {
+        if record.err.is_some() && self.skip_failed {
+            return false;
+        }
+        match &self.accounts {
+            None => true,
+            Some(accounts) => record.accounts.iter().any(|key| accounts.contains(key)),
+        }
+    }
+
+    fn accepts_instruction(&self, payload: &InstructionPayload) -> bool {
+        match (&self.instructions, payload) {
+            (None, _) => true,
+            (Some(names), InstructionPayload::Decoded(value)) => value
+                .get("name")
+                .and_then(|name| name.as_str())
+                .map(|name| names.iter().any(|n| n == name))
+                .unwrap_or(false),
+            (Some(_), InstructionPayload::Raw { .. }) => false,
+        }
+    }
+
+    fn accepts_event(&self, payload: &EventPayload) -> bool {
+        match (&self.events, payload) {
+            (None, _) => true,
+            (Some(names), EventPayload::Decoded(value)) => value
+                .get("name")
+                .and_then(|name| name.as_str())
+                .map(|name| names.iter().any(|n| n == name))
+                .unwrap_or(false),
+            (Some(_), EventPayload::Raw { .. }) => false,
+        }
+    }
+}
+
+// ProgramConfig is the configuration of a program that is tracked by the EventLoader
+pub struct ProgramConfig {
+    pub addr: String,
+    pub idl: Option<IdlDecoder>,
+    pub filter: ProgramFilter,
+    // initial is the state of the cursors if no checkpoint was saved for the program
+    pub initial: Checkpoint,
+}
+
+impl ProgramConfig {
+    pub fn new(addr: String, initial: Checkpoint) -> Self {
+        Self {
+            addr,
+            idl: None,
+            filter: ProgramFilter::default(),
+            initial,
+        }
+    }
+
+    // with_idl sets the IDL that is used to decode events of the program
+    pub fn with_idl(mut self, idl: IdlDecoder) -> Self {
+        self.idl = Some(idl);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ProgramFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+// Program is a tracked program with its own cursors
+struct Program {
+    addr: String,
+    pk: Pubkey,
+    idl: Option<IdlDecoder>,
+    filter: ProgramFilter,
+    // event_authority is the PDA that signs the self-CPIs of emit_cpi!
+    event_authority: String,
+    // head_cursor tracks confirmed txs, tail_cursor tracks finalized txs
     head_cursor: Cursor,
     tail_cursor: Cursor,
+}
+
+impl Program {
+    fn cursor(&self, commitment: CommitStatus) -> &Cursor {
+        match commitment {
+            CommitStatus::Confirmed => &self.head_cursor,
+            CommitStatus::Finalized => &self.tail_cursor,
+        }
+    }
+
+    // checkpoint returns the current state of the cursors
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint::new(
+            self.head_cursor.get_slot(),
+            self.head_cursor.get_sig().to_string(),
+            self.tail_cursor.get_slot(),
+            self.tail_cursor.get_sig().to_string(),
+        )
+    }
+}
+
+// EventLoader loads log events from the Solana blockchain for a set of program addresses.
+// All programs share the client and the sink, a tx that mentions several tracked programs
+// is only written once per commitment level.
+pub struct EventLoader {
+    client: RpcClientWrapper,
+    sink: Arc<dyn EventSink>,
+    checkpoints: Arc<dyn CheckpointStore>,
+    programs: Vec<Program>,
+    // written keeps the txs that were written to the sink per commitment level (sig -> slot),
+    // entries are dropped once every program cursor moved past them
+    written: Mutex<HashMap<CommitStatus, HashMap<String, u64>>>,
+    // write_lock makes checking written, writing a batch and marking it written atomic,
+    // the poll and process_notifications write concurrently
+    write_lock: tokio::sync::Mutex<()>,
+    // unfinalized keeps the txs that were written as confirmed and not seen finalized yet (sig -> tx).
+    // It is not persisted, txs confirmed before a restart are only promoted, never retracted.
+    unfinalized: Mutex<HashMap<String, Unfinalized>>,
+    // retraction_window is the number of slots the tail cursor has to pass a confirmed tx before it is retracted
+    retraction_window: u64,
 
     batch_size: usize,
+    // fetch_concurrency is the number of batch requests for txs that run at the same time,
+    // and the number of notifications whose tx is fetched at the same time
+    fetch_concurrency: usize,
+    // notification_budget limits the retries of fetching the tx of a notification
+    notification_budget: RetryBudget,
+    // recover_truncated records the balance changes of txs with truncated logs
+    recover_truncated: bool,
+}
+
+// Unfinalized is a tx that was written as confirmed
+struct Unfinalized {
+    slot: u64,
     program_addr: String,
 }
 
 unsafe impl Send for EventLoader {}
 
 impl EventLoader {
-    // new creates a new EventLoader with the given client and cursors
-    pub fn new(
-        program_addr: String,
+    // new creates a new EventLoader with the given client and sink.
+    // The cursors of each program are restored from the checkpoint store,
+    // the initial checkpoint of the program is used if none was saved yet.
+    pub async fn new(
+        programs: Vec<ProgramConfig>,
         batch_size: usize,
         client: RpcClientWrapper,
-        head_slot: u64,
-        head_sig: String,
-        tail_slot: u64,
-        tail_sig: String,
-    ) -> Self {
-        Self {
+        sink: Arc<dyn EventSink>,
+        checkpoints: Arc<dyn CheckpointStore>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut tracked: Vec<Program> = Vec::with_capacity(programs.len());
+        for cfg in programs {
+            if tracked.iter().any(|p| p.addr == cfg.addr) {
+                return Err(format!("program {} is configured more than once", cfg.addr).into());
+            }
+            let checkpoint = match checkpoints.load(cfg.addr.as_str()).await? {
+                Some(checkpoint) => {
+                    println!(
+                        "[event_loader/new] Resuming addr {} from checkpoint {:?}",
+                        cfg.addr, checkpoint
+                    );
+                    checkpoint
+                }
+                None => cfg.initial,
+            };
+            let pk = Pubkey::from_str(cfg.addr.as_str())?;
+            tracked.push(Program {
+                pk,
+                event_authority: Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &pk)
+                    .0
+                    .to_string(),
+                addr: cfg.addr,
+                idl: cfg.idl,
+                filter: cfg.filter,
+                head_cursor: Cursor::new(checkpoint.head_slot, checkpoint.head_sig),
+                tail_cursor: Cursor::new(checkpoint.tail_slot, checkpoint.tail_sig),
+            });
+        }
+        Ok(Self {
             client,
-            head_cursor: Cursor::new(head_slot, head_sig),
-            tail_cursor: Cursor::new(tail_slot, tail_sig),
+            sink,
+            checkpoints,
+            programs: tracked,
+            written: Mutex::new(HashMap::new()),
+            write_lock: tokio::sync::Mutex::new(()),
+            unfinalized: Mutex::new(HashMap::new()),
+            retraction_window: DEFAULT_RETRACTION_WINDOW,
             batch_size,
-            program_addr,
-        }
+            fetch_concurrency: DEFAULT_FETCH_CONCURRENCY,
+            notification_budget: DEFAULT_NOTIFICATION_BUDGET,
+            recover_truncated: false,
+        })
+    }
+
+    // client returns the rpc client, e.g. for its endpoint and rate limit stats
+    pub fn client(&self) -> &RpcClientWrapper {
+        &self.client
+    }
+
+    // with_fetch_concurrency sets the number of batch requests for txs that run at the same time,
+    // the size of the requests is the max_batch_size of the client
+    pub fn with_fetch_concurrency(mut self, fetch_concurrency: usize) -> Self {
+        self.fetch_concurrency = fetch_concurrency.max(1);
+        self
+    }
+
+    // with_notification_budget sets the retry budget of fetching the tx of a websocket notification
+    pub fn with_notification_budget(mut self, budget: RetryBudget) -> Self {
+        self.notification_budget = budget;
+        self
+    }
+
+    // with_retraction_window sets the number of slots after which a confirmed tx that
+    // was not finalized is retracted
+    pub fn with_retraction_window(mut self, slots: u64) -> Self {
+        self.retraction_window = slots;
+        self
+    }
+
+    // with_truncation_recovery records the lamport and token balance changes of txs whose logs
+    // were truncated, so the effects of the lost events can be reconstructed from them.
+    // Events emitted with emit_cpi! are recovered regardless, they are decoded from the instructions.
+    pub fn with_truncation_recovery(mut self, recover: bool) -> Self {
+        self.recover_truncated = recover;
+        self
     }
 
     pub async fn poll(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let tail_slot = self.tail_cursor.get_slot();
-        let head_slot = self.head_cursor.get_slot();
         let last_confirmed_slot = self
             .client
             .get_slot(Some(CommitmentConfig::confirmed()))
@@ -99,278 +348,1077 @@ impl EventLoader {
             .get_slot(Some(CommitmentConfig::finalized()))
             .await?;
         let slot_buffer = 100;
-        println!(
-            "[event_loader/poll] Polling for addr {} with head_slot={}, tail_slot={}, last_confirmed_slot={}, last_finalized_slot={}",
-            self.program_addr, head_slot, tail_slot, last_confirmed_slot, last_finalized_slot
-        );
-        match self.backfill(last_finalized_slot - (slot_buffer*10)).await {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("[event_loader/poll] Error backfilling: {:?}", e);
-                return Err(e);
-            }
-        }
-        match self.load_confirmed_events(last_confirmed_slot - slot_buffer).await {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!(
-                    "[event_loader/poll] Error loading confirmed events: {:?}",
-                    e
-                );
-                return Err(e);
-            }
-        }
-        Ok(())
-    }
-
-    // backfill events from the tail_cursor to the target slot
-    pub async fn backfill(&self, target_slot: u64) -> Result<(), Box<dyn std::error::Error>> {
-        let pk = Pubkey::from_str(self.program_addr.as_str())?;
-        println!(
-            "[event_loader/backfill] Backfilling for addr {} to slot {}",
-            self.program_addr, target_slot
-        );
-        let mut tail_slot = self.tail_cursor.get_slot();
-        let mut tail_sig = self.tail_cursor.get_sig();
-        let mut seen_txs = HashMap::new();
-        while target_slot >= tail_slot {
+        // errors are kept as strings, a boxed error can't be held across awaits of a Send future
+        let mut first_err: Option<String> = None;
+        for program in self.programs.iter() {
+            println!(
+                "[event_loader/poll] Polling for addr {} with head_slot={}, tail_slot={}, last_confirmed_slot={}, last_finalized_slot={}",
+                program.addr,
+                program.head_cursor.get_slot(),
+                program.tail_cursor.get_slot(),
+                last_confirmed_slot,
+                last_finalized_slot
+            );
+            // an error of a program doesn't prevent the other programs from being polled
             match self
-                .client
-                .get_sigs_for_addr(
-                    &pk,
-                    tail_slot + 1,
-                    self.batch_size,
-                    Some(CommitmentConfig::finalized()),
-                    Some(tail_sig),
-                    None,
+                .load_program(
+                    program,
+                    CommitStatus::Finalized,
+                    last_finalized_slot.saturating_sub(slot_buffer * 10),
                 )
                 .await
             {
-                Ok(txs_resp) => {
-                    let mut txs = txs_resp
-                        .iter()
-                        .filter(|tx| !seen_txs.contains_key(&tx.signature))
-                        .collect::<Vec<_>>();
-                    if txs.is_empty() {
-                        self.tail_cursor.update(
-                            target_slot,
-                            self.tail_cursor.get_sig(),
-                        );
-                        return Ok(());
-                    }
-                    println!(
-                        "[event_loader/backfill] Found {} txs for addr {} on tail_slot {} while target_slot is {}",
-                        txs_resp.len(),
-                        self.program_addr,
-                        tail_slot,
-                        target_slot
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!(
+                        "[event_loader/poll] Error backfilling addr {}: {:?}",
+                        program.addr, e
                     );
-                    txs.sort_by(|a, b| a.slot.cmp(&b.slot));
-                    for tx_status in txs.iter() {
-                        let sig = Signature::from_str(tx_status.signature.as_str())?;
-                        println!(
-                            "[event_loader/backfill] Visiting tx (slot={}, sig={}, addr={})",
-                            tx_status.slot,
-                            tx_status.signature.clone(),
-                            self.program_addr.clone()
-                        );
-                        match self
-                            .client
-                            .get_tx(&sig, Some(CommitmentConfig::finalized()))
-                            .await
-                        {
-                            Ok(tx) => {
-                                let logs = tx
-                                    .transaction
-                                    .meta
-                                    .unwrap()
-                                    .log_messages
-                                    .unwrap_or(Vec::new());
-                                self.process_finalized_logs(
-                                    tx.slot,
-                                    tx_status.signature.clone(),
-                                    logs,
-                                );
-                                seen_txs.insert(tx_status.signature.clone(), tx_status.slot);
-                            }
-                            Err(e) => {
-                                eprintln!("[event_loader/backfill] Error fetching tx: {:?}", e);
-                                return Err(e.into());
-                            }
-                        }
-                    }
-                    if let Some(last_tx) = txs.last().or(None) {
-                        if tail_slot < last_tx.slot {
-                            tail_slot = last_tx.slot;
-                            tail_sig = Signature::from_str(last_tx.signature.as_str())?;
-                            println!(
-                                "[event_loader/backfill] Updating tail_cursor to (slot={}, sig={})",
-                                last_tx.slot,
-                                last_tx.signature.as_str()
-                            );
-                            self.tail_cursor.update(
-                                last_tx.slot,
-                                tail_sig.clone(),
-                            );
-                        }
-                    }
+                    first_err.get_or_insert(e.to_string());
+                    continue;
                 }
+            }
+            match self
+                .load_program(
+                    program,
+                    CommitStatus::Confirmed,
+                    last_confirmed_slot.saturating_sub(slot_buffer),
+                )
+                .await
+            {
+                Ok(_) => {}
                 Err(e) => {
-                    eprintln!("[event_loader/backfill] Error fetching txs: {:?}", e);
-                    return Err(e.into());
+                    eprintln!(
+                        "[event_loader/poll] Error loading confirmed events for addr {}: {:?}",
+                        program.addr, e
+                    );
+                    first_err.get_or_insert(e.to_string());
                 }
+            }
+        }
+        match first_err {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    // process_notifications writes the txs of websocket notifications as confirmed until the
+    // stream ends (hybrid mode). The poll keeps running next to it and fills the gaps, e.g. txs
+    // missed while the websocket was down, each tx is written once per commitment level.
+    // The txs of up to fetch_concurrency notifications are fetched at the same time, they are
+    // written in the order of the notifications.
+    pub async fn process_notifications<S>(&self, notifications: S)
+    where
+        S: Stream<Item = LogNotification> + Unpin,
+    {
+        let mut fetched = notifications
+            .map(|notification| async move {
+                let record = self
+                    .fetch_notification(&notification)
+                    .await
+                    .map_err(|e| e.to_string());
+                (notification, record)
+            })
+            .buffered(self.fetch_concurrency);
+        while let Some((notification, record)) = fetched.next().await {
+            let written = match record {
+                Ok(Some(record)) => self
+                    .write_notification(&notification, record)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Ok(None) => Ok(false),
+                Err(e) => Err(e),
             };
+            match written {
+                Ok(true) => println!(
+                    "[event_loader/process_notifications] Wrote tx (slot={}, sig={}, addr={})",
+                    notification.slot, notification.raw.signature, notification.addr
+                ),
+                Ok(false) => {}
+                // the poll writes the tx once its cursor reaches it
+                Err(e) => eprintln!(
+                    "[event_loader/process_notifications] Error processing tx {}: {}",
+                    notification.raw.signature, e
+                ),
+            }
+        }
+    }
+
+    // process_notification fetches and writes the tx of a websocket notification as confirmed,
+    // ahead of the poll. It returns false if the address isn't tracked, the tx is filtered out
+    // or it was already written by the poll or for another program.
+    pub async fn process_notification(
+        &self,
+        notification: &LogNotification,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self.fetch_notification(notification).await? {
+            Some(record) => self.write_notification(notification, record).await,
+            None => Ok(false),
+        }
+    }
+
+    // fetch_notification fetches the tx of a notification within the notification budget,
+    // it returns None if the tx doesn't need to be written
+    async fn fetch_notification(
+        &self,
+        notification: &LogNotification,
+    ) -> Result<Option<TxRecord>, Box<dyn std::error::Error>> {
+        let commitment = CommitStatus::Confirmed;
+        let Some(program) = self.program(notification.addr.as_str()) else {
+            return Ok(None);
+        };
+        let sig = notification.raw.signature.as_str();
+        // every tx of the address before the head cursor was written by the poll
+        if notification.slot < program.head_cursor.get_slot() || self.is_written(commitment, sig) {
+            return Ok(None);
+        }
+        if notification.raw.err.is_some() && program.filter.skip_failed {
+            return Ok(None);
+        }
+        let tx = self
+            .client
+            .get_tx_within(
+                &Signature::from_str(sig)?,
+                Some(commitment.commitment_config()),
+                self.notification_budget,
+            )
+            .await?;
+        let message = decode_message(&tx.transaction)?;
+        let record = self.build_tx_record(sig.to_string(), tx, message);
+        Ok(program.filter.accepts_tx(&record).then_some(record))
+    }
+
+    // write_notification writes the tx of a notification as confirmed unless it was written since it was fetched
+    async fn write_notification(
+        &self,
+        notification: &LogNotification,
+        record: TxRecord,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let commitment = CommitStatus::Confirmed;
+        let Some(program) = self.program(notification.addr.as_str()) else {
+            return Ok(false);
+        };
+        let _write_guard = self.write_lock.lock().await;
+        if self.is_written(commitment, record.signature.as_str()) {
+            return Ok(false);
+        }
+        // the cursors are only moved by the poll, the batch has no checkpoint
+        let batch = EventBatch::new(commitment, program.addr.clone(), vec![record]);
+        self.sink.write_batch(&batch).await?;
+        self.mark_written(commitment, &batch);
+        self.track_finality(commitment, &batch, &[]);
+        Ok(true)
+    }
+
+    // backfill events from the tail_cursor of each program to the target slot
+    pub async fn backfill(&self, target_slot: u64) -> Result<(), Box<dyn std::error::Error>> {
+        for program in self.programs.iter() {
+            self.load_program(program, CommitStatus::Finalized, target_slot)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // retract_dropped retracts the confirmed txs of a program that were passed by its tail cursor
+    // by more than the retraction window without being finalized
+    async fn retract_dropped(&self, program: &Program) -> Result<(), Box<dyn std::error::Error>> {
+        let tail_slot = program.tail_cursor.get_slot();
+        let mut txs = {
+            let unfinalized = self.unfinalized.lock().unwrap();
+            unfinalized
+                .iter()
+                .filter(|(_, tx)| {
+                    tx.program_addr == program.addr
+                        && tx.slot.saturating_add(self.retraction_window) <= tail_slot
+                })
+                .map(|(sig, tx)| RetractedTx {
+                    slot: tx.slot,
+                    signature: sig.clone(),
+                })
+                .collect::<Vec<_>>()
+        };
+        if txs.is_empty() {
+            return Ok(());
+        }
+        txs.sort_by_key(|tx| tx.slot);
+        println!(
+            "[event_loader/retract_dropped] Retracting {} confirmed txs for addr {} not finalized by slot {}",
+            txs.len(),
+            program.addr,
+            tail_slot
+        );
+        let retraction = Retraction {
+            program_addr: program.addr.clone(),
+            txs,
+        };
+        if let Err(e) = self.sink.retract(&retraction).await {
+            eprintln!(
+                "[event_loader/retract_dropped] Error retracting txs: {:?}",
+                e
+            );
+            return Err(e.into());
+        }
+        let mut unfinalized = self.unfinalized.lock().unwrap();
+        for tx in retraction.txs.iter() {
+            unfinalized.remove(&tx.signature);
         }
         Ok(())
     }
 
-    // load_events loads events from the head_cursor to the target slot
+    // load_events loads events from the head_cursor of each program to the target slot
     pub async fn load_confirmed_events(
         &self,
         target_slot: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let pk = Pubkey::from_str(self.program_addr.as_str())?;
-        let mut head_slot = self.head_cursor.get_slot();
-        let mut head_sig = self.head_cursor.get_sig();
-        let mut seen_txs = HashMap::new();
+        for program in self.programs.iter() {
+            self.load_program(program, CommitStatus::Confirmed, target_slot)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // load_program loads the txs of a program from its cursor to the target slot,
+    // finalized txs are loaded from the tail_cursor and confirmed txs from the head_cursor.
+    // The written txs are pruned and a finalized pass retracts the dropped txs however it ends,
+    // the cursor may have moved even if loading failed.
+    async fn load_program(
+        &self,
+        program: &Program,
+        commitment: CommitStatus,
+        target_slot: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // the error is kept as a string, a boxed error can't be held across awaits of a Send future
+        let loaded = self
+            .load_txs(program, commitment, target_slot)
+            .await
+            .map_err(|e| e.to_string());
+        self.prune_written(commitment);
+        if commitment == CommitStatus::Finalized {
+            self.retract_dropped(program).await?;
+            self.save_checkpoint(program).await?;
+        }
+        Ok(loaded?)
+    }
+
+    // load_txs writes the txs of a program from its cursor up to the target slot
+    async fn load_txs(
+        &self,
+        program: &Program,
+        commitment: CommitStatus,
+        target_slot: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let scope = load_scope(commitment);
+        let cursor = program.cursor(commitment);
         println!(
-            "[event_loader/backfill] Loading confirmed events for addr {} to slot {}",
-            self.program_addr, target_slot
+            "[event_loader/{}] Loading {} events for addr {} to slot {}",
+            scope,
+            commitment.as_str(),
+            program.addr,
+            target_slot
         );
-        while target_slot >= head_slot {
-            match self
-                .client
-                .get_sigs_for_addr(
-                    &pk,
-                    head_slot + 1,
-                    self.batch_size,
-                    Some(CommitmentConfig::confirmed()),
-                    Some(head_sig),
-                    None,
-                )
+        let mut cursor_slot = cursor.get_slot();
+        let mut cursor_sig = cursor.get_sig();
+        let mut seen_txs = HashMap::new();
+        // pages keeps the `before` signature of the pages above the one that is processed
+        let mut pages = Vec::new();
+        while target_slot >= cursor_slot {
+            let txs_resp = match self
+                .next_page(program, commitment, cursor_slot, cursor_sig, &mut pages)
                 .await
             {
-                Ok(txs_resp) => {
-                    let mut txs = txs_resp
-                        .iter()
-                        .filter(|tx| !seen_txs.contains_key(&tx.signature))
-                        .collect::<Vec<_>>();
-                    if txs.is_empty() {
-                        self.head_cursor.update(
-                            target_slot,
-                            self.head_cursor.get_sig(),
-                        );
-                        return Ok(());
+                Ok(txs_resp) => txs_resp,
+                Err(e) => {
+                    eprintln!("[event_loader/{}] Error fetching txs: {:?}", scope, e);
+                    return Err(e.into());
+                }
+            };
+            let pending = txs_resp
+                .iter()
+                .filter(|tx| !seen_txs.contains_key(&tx.signature))
+                .collect::<Vec<_>>();
+            if pending.is_empty() && !pages.is_empty() {
+                continue;
+            }
+            if pending.is_empty() {
+                cursor.update(target_slot, cursor.get_sig());
+                self.save_checkpoint(program).await?;
+                return Ok(());
+            }
+            println!(
+                "[event_loader/{}] Found {} txs for addr {} on cursor slot {} while target_slot is {}",
+                scope,
+                txs_resp.len(),
+                program.addr,
+                cursor_slot,
+                target_slot
+            );
+            for chunk in pending.chunks(self.batch_size) {
+                let mut txs = chunk.to_vec();
+                // txs are fetched in batch requests that run concurrently but are buffered in order,
+                // so the records keep the (slot, intra-slot index) order and a failure leaves a contiguous prefix
+                let mut plan = Vec::with_capacity(txs.len());
+                let mut plan_err = None;
+                for tx_status in txs.iter() {
+                    match self.fetch_plan(program, commitment, tx_status) {
+                        Ok(sig) => plan.push(sig),
+                        Err(e) => {
+                            plan_err = Some(e);
+                            break;
+                        }
                     }
-                    txs.sort_by(|a, b| a.slot.cmp(&b.slot));
-                    for tx_status in txs.iter() {
-                        let sig = Signature::from_str(tx_status.signature.as_str())?;
-                        println!(
-                            "[event_loader/load_confirmed_events] Visiting tx (slot={}, sig={}, addr={})",
-                            tx_status.slot,
-                            tx_status.signature.clone(),
-                            self.program_addr.clone()
-                        );
-                        match self
-                            .client
-                            .get_tx(&sig, Some(CommitmentConfig::confirmed()))
-                            .await
-                        {
-                            Ok(tx) => {
-                                let logs = tx
-                                    .transaction
-                                    .meta
-                                    .unwrap()
-                                    .log_messages
-                                    .unwrap_or(Vec::new());
-                                self.process_confirmed_logs(
-                                    tx.slot,
-                                    tx_status.signature.clone(),
-                                    logs,
-                                );
-                                seen_txs.insert(tx_status.signature.clone(), tx_status.slot);
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "[event_loader/load_confirmed_events] Error fetching tx: {:?}",
-                                    e
-                                );
-                                return Err(e.into());
+                }
+                let sigs = plan.iter().flatten().copied().collect::<Vec<_>>();
+                // the futures are collected first, a lazy map held across awaits breaks the Send bound of poll
+                let fetches = sigs
+                    .chunks(self.client.max_batch_size())
+                    .map(|group| {
+                        self.client
+                            .get_txs(group, Some(commitment.commitment_config()))
+                    })
+                    .collect::<Vec<_>>();
+                let mut fetched = stream::iter(fetches).buffered(self.fetch_concurrency);
+                let mut ready = VecDeque::new();
+                let mut records = Vec::with_capacity(txs.len());
+                let mut fetch_err = None;
+                let mut completed = 0;
+                for (tx_status, sig) in txs.iter().zip(plan.iter()) {
+                    if sig.is_none() {
+                        completed += 1;
+                        continue;
+                    }
+                    if ready.is_empty() {
+                        ready.extend(fetched.next().await.unwrap_or_default());
+                    }
+                    match ready.pop_front() {
+                        Some(Ok(tx)) => {
+                            let message = match decode_message(&tx.transaction) {
+                                Ok(message) => message,
+                                Err(e) => {
+                                    let e = RpcError::DecodeError {
+                                        method: "get_tx".to_string(),
+                                        message: format!("tx {}: {}", tx_status.signature, e),
+                                    };
+                                    eprintln!(
+                                        "[event_loader/{}] Error decoding tx: {:?}",
+                                        scope, e
+                                    );
+                                    fetch_err = Some((completed, e));
+                                    break;
+                                }
+                            };
+                            let record =
+                                self.build_tx_record(tx_status.signature.clone(), tx, message);
+                            if program.filter.accepts_tx(&record) {
+                                records.push(record);
                             }
                         }
+                        // the retries of the tx are spent, the txs fetched before it are still written
+                        Some(Err(e)) => {
+                            eprintln!("[event_loader/{}] Error fetching tx: {:?}", scope, e);
+                            fetch_err = Some((completed, e));
+                            break;
+                        }
+                        // get_txs returns a result for every signature
+                        None => {
+                            fetch_err = Some((
+                                completed,
+                                RpcError::DecodeError {
+                                    method: "get_txs".to_string(),
+                                    message: format!("no result for tx {}", tx_status.signature),
+                                },
+                            ));
+                            break;
+                        }
+                    }
+                    completed += 1;
+                }
+                if fetch_err.is_none() {
+                    if let Some(e) = plan_err {
+                        fetch_err = Some((completed, e));
                     }
-                    let last_tx = txs.last().unwrap();
-                    if head_slot < last_tx.slot {
-                        head_slot = last_tx.slot;
-                        head_sig = Signature::from_str(last_tx.signature.as_str())?;
-                        println!(
-                        "[event_loader/load_confirmed_events] Updating head_cursor to (slot={}, sig={})",
+                }
+                // dropping the stream cancels the fetches that are still in flight
+                drop(fetched);
+                if let Some((completed, _)) = &fetch_err {
+                    txs.truncate(*completed);
+                }
+                let Some(last_tx) = txs.last().copied() else {
+                    return Err(fetch_err.unwrap().1.into());
+                };
+                let mut checkpoint = program.checkpoint();
+                if cursor_slot <= last_tx.slot {
+                    match commitment {
+                        CommitStatus::Finalized => {
+                            checkpoint.tail_slot = last_tx.slot;
+                            checkpoint.tail_sig = last_tx.signature.clone();
+                        }
+                        CommitStatus::Confirmed => {
+                            checkpoint.head_slot = last_tx.slot;
+                            checkpoint.head_sig = last_tx.signature.clone();
+                        }
+                    }
+                }
+                // txs written by process_notifications since the fetch plan are dropped
+                let write_guard = self.write_lock.lock().await;
+                records.retain(|record| !self.is_written(commitment, record.signature.as_str()));
+                let batch = EventBatch::new(commitment, program.addr.clone(), records)
+                    .with_checkpoint(checkpoint);
+                if let Err(e) = self.sink.write_batch(&batch).await {
+                    eprintln!("[event_loader/{}] Error writing batch: {:?}", scope, e);
+                    return Err(e.into());
+                }
+                self.mark_written(commitment, &batch);
+                self.track_finality(commitment, &batch, &txs);
+                drop(write_guard);
+                for tx_status in txs.iter() {
+                    seen_txs.insert(tx_status.signature.clone(), tx_status.slot);
+                }
+                if cursor_slot <= last_tx.slot {
+                    cursor_slot = last_tx.slot;
+                    cursor_sig = Signature::from_str(last_tx.signature.as_str())?;
+                    println!(
+                        "[event_loader/{}] Updating cursor of addr {} to (slot={}, sig={})",
+                        scope,
+                        program.addr,
                         last_tx.slot,
                         last_tx.signature.as_str()
                     );
-                        self.head_cursor.update(
-                            last_tx.slot,
-                            head_sig.clone(),
-                        );
-                    }
+                    cursor.update(cursor_slot, cursor_sig);
+                    self.save_checkpoint(program).await?;
+                    // the rpc doesn't return txs older than the cursor again
+                    seen_txs.retain(|_, slot| *slot >= cursor_slot);
                 }
-                Err(e) => {
-                    eprintln!(
-                        "[event_loader/load_confirmed_events] Error fetching txs: {:?}",
-                        e
+                if let Some((_, e)) = fetch_err {
+                    println!(
+                        "[event_loader/{}] Processed {} txs for addr {} before failing",
+                        scope,
+                        txs.len(),
+                        program.addr
                     );
                     return Err(e.into());
                 }
-            };
+            }
         }
         Ok(())
     }
 
-    fn process_finalized_logs(&self, slot: u64, sig: String, logs: Vec<String>) {
-        let addr = self.program_addr.as_str();
-        println!(
-            "[event_loader/process_finalized_logs] Processing {} finalized logs for addr {} on slot {} and sig {}",
-            logs.len(),
-            addr,
-            slot,
-            sig.clone()
-        );
-        logs.iter().for_each(|log| {
-            if let Some(parsed_log) = parse_log(log, addr) {
-                println!(
-                    "[event_loader/process_finalized_logs] Parsed finalized log: {:?}",
-                    parsed_log
-                );
-            } else {
-                println!(
-                    "[event_loader/process_finalized_logs] Log with unknown structure: {}",
-                    log
-                );
+    // next_page returns the oldest page of txs of a program that are newer than the cursor,
+    // oldest first. The rpc pages from the newest tx backwards, so the pages are walked down to
+    // the cursor first, keeping only the `before` signature of each page above, and are read
+    // again upwards as the cursor moves. A pass holds a single page of txs in memory and every
+    // page is written before the next one is read.
+    // Paging also stops at the cursor slot, for cursors that don't point to an existing tx.
+    async fn next_page(
+        &self,
+        program: &Program,
+        commitment: CommitStatus,
+        cursor_slot: u64,
+        cursor_sig: Signature,
+        pages: &mut Vec<Option<Signature>>,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, RpcError> {
+        // the page below was processed, so this page reaches down to the cursor
+        if let Some(before) = pages.pop() {
+            let mut page = self
+                .sigs_page(program, commitment, cursor_slot, cursor_sig, before)
+                .await?;
+            page.reverse();
+            return Ok(page);
+        }
+        let mut before = None;
+        let mut page = self
+            .sigs_page(program, commitment, cursor_slot, cursor_sig, before)
+            .await?;
+        while let Some(oldest) = page.last() {
+            if page.len() < self.batch_size || oldest.slot < cursor_slot {
+                break;
             }
-        });
+            let oldest_slot = oldest.slot;
+            let oldest_sig = Signature::from_str(oldest.signature.as_str()).map_err(|e| {
+                RpcError::DecodeError {
+                    method: "get_sigs_for_addr".to_string(),
+                    message: format!("invalid signature {}: {}", oldest.signature, e),
+                }
+            })?;
+            let older = self
+                .sigs_page(
+                    program,
+                    commitment,
+                    cursor_slot,
+                    cursor_sig,
+                    Some(oldest_sig),
+                )
+                .await?;
+            if older.is_empty() {
+                break;
+            }
+            println!(
+                "[event_loader/{}] Paging txs for addr {} before slot {}",
+                load_scope(commitment),
+                program.addr,
+                oldest_slot
+            );
+            pages.push(before);
+            before = Some(oldest_sig);
+            page = older;
+        }
+        page.reverse();
+        Ok(page)
     }
 
-    fn process_confirmed_logs(&self, slot: u64, sig: String, logs: Vec<String>) {
-        let addr = self.program_addr.as_str();
+    // sigs_page returns a page of the txs of a program between the cursor and `before`, newest first
+    async fn sigs_page(
+        &self,
+        program: &Program,
+        commitment: CommitStatus,
+        cursor_slot: u64,
+        cursor_sig: Signature,
+        before: Option<Signature>,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, RpcError> {
+        self.client
+            .get_sigs_for_addr(
+                &program.pk,
+                cursor_slot + 1,
+                self.batch_size,
+                Some(commitment.commitment_config()),
+                Some(cursor_sig),
+                before,
+            )
+            .await
+    }
+
+    // fetch_plan returns the signature of a tx to fetch, txs that are already written
+    // for another program or from a websocket notification are skipped
+    fn fetch_plan(
+        &self,
+        program: &Program,
+        commitment: CommitStatus,
+        tx_status: &RpcConfirmedTransactionStatusWithSignature,
+    ) -> Result<Option<Signature>, RpcError> {
+        let scope = load_scope(commitment);
+        if self.is_written(commitment, tx_status.signature.as_str()) {
+            println!(
+                "[event_loader/{}] Skipping tx already written (slot={}, sig={}, addr={})",
+                scope, tx_status.slot, tx_status.signature, program.addr
+            );
+            return Ok(None);
+        }
+        let sig = Signature::from_str(tx_status.signature.as_str()).map_err(|e| {
+            RpcError::DecodeError {
+                method: "get_sigs_for_addr".to_string(),
+                message: format!("invalid signature {}: {}", tx_status.signature, e),
+            }
+        })?;
         println!(
-            "[event_loader/process_confirmed_logs] Processing {} confirmed logs for addr {} on slot {} and sig {}",
-            logs.len(),
-            addr,
-            slot,
-            sig.clone()
+            "[event_loader/{}] Visiting tx (slot={}, sig={}, addr={})",
+            scope, tx_status.slot, tx_status.signature, program.addr
         );
-        logs.iter().for_each(|log| {
-            if let Some(parsed_log) = parse_log(log, addr) {
-                println!(
-                    "[event_loader/process_confirmed_logs] Parsed confirmed log: {:?}",
-                    parsed_log
-                );
+        Ok(Some(sig))
+    }
+
+    // save_checkpoint persists the current state of the cursors of a program
+    async fn save_checkpoint(&self, program: &Program) -> Result<(), CheckpointError> {
+        self.checkpoints
+            .save(program.addr.as_str(), &program.checkpoint())
+            .await
+    }
+
+    fn is_written(&self, commitment: CommitStatus, sig: &str) -> bool {
+        let written = self.written.lock().unwrap();
+        written
+            .get(&commitment)
+            .map(|txs| txs.contains_key(sig))
+            .unwrap_or(false)
+    }
+
+    fn mark_written(&self, commitment: CommitStatus, batch: &EventBatch) {
+        let mut written = self.written.lock().unwrap();
+        let txs = written.entry(commitment).or_default();
+        for tx in batch.txs.iter() {
+            txs.insert(tx.signature.clone(), tx.slot);
+        }
+    }
+
+    // track_finality records the txs written as confirmed and promotes the ones seen as finalized
+    fn track_finality(
+        &self,
+        commitment: CommitStatus,
+        batch: &EventBatch,
+        txs: &[&RpcConfirmedTransactionStatusWithSignature],
+    ) {
+        let mut unfinalized = self.unfinalized.lock().unwrap();
+        match commitment {
+            // txs the tail cursor already passed were seen finalized and are never retracted
+            CommitStatus::Confirmed => {
+                let tail_slot = self
+                    .program(batch.program_addr.as_str())
+                    .map(|program| program.tail_cursor.get_slot())
+                    .unwrap_or_default();
+                for tx in batch.txs.iter() {
+                    if tx.slot <= tail_slot
+                        || self.is_written(CommitStatus::Finalized, tx.signature.as_str())
+                    {
+                        continue;
+                    }
+                    unfinalized.insert(
+                        tx.signature.clone(),
+                        Unfinalized {
+                            slot: tx.slot,
+                            program_addr: batch.program_addr.clone(),
+                        },
+                    );
+                }
+            }
+            // txs that were skipped in the batch (filtered, written for another program) are promoted as well
+            CommitStatus::Finalized => {
+                for tx in txs.iter() {
+                    unfinalized.remove(&tx.signature);
+                }
             }
+        }
+    }
+
+    // prune_written drops the txs that all programs have passed with their cursor
+    fn prune_written(&self, commitment: CommitStatus) {
+        let min_slot = self
+            .programs
+            .iter()
+            .map(|program| program.cursor(commitment).get_slot())
+            .min()
+            .unwrap_or_default();
+        let mut written = self.written.lock().unwrap();
+        if let Some(txs) = written.get_mut(&commitment) {
+            txs.retain(|_, slot| *slot >= min_slot);
+        }
+    }
+
+    fn program(&self, addr: &str) -> Option<&Program> {
+        self.programs.iter().find(|program| program.addr == addr)
+    }
+
+    // build_tx_record extracts the logs and events of the tracked programs from a transaction
+    fn build_tx_record(
+        &self,
+        sig: String,
+        tx: EncodedConfirmedTransactionWithStatusMeta,
+        message: TxMessage,
+    ) -> TxRecord {
+        let meta = tx.transaction.meta;
+        let logs: Vec<String> = meta
+            .as_ref()
+            .map(|meta| meta.log_messages.clone().unwrap_or(Vec::new()))
+            .unwrap_or_default();
+        let invocations = build_invocations(&logs);
+        let incomplete = logs.iter().any(|log| log == TRUNCATED_LOG);
+        if incomplete {
+            eprintln!(
+                "[event_loader/build_tx_record] logs of sig {} on slot {} are truncated, events may be missing",
+                sig, tx.slot
+            );
+        }
+        let mut log_records = invocations
+            .iter()
+            .flat_map(|root| root.walk())
+            .flat_map(|frame| {
+                let filter = self.program(frame.program_id.as_str()).map(|p| &p.filter);
+                frame
+                    .logs
+                    .iter()
+                    .filter(move |entry| {
+                        filter
+                            .map(|filter| {
+                                filter.accepts_log(entry.log.as_ref().map(|log| log.log_type()))
+                            })
+                            .unwrap_or(true)
+                    })
+                    .map(|entry| LogRecord {
+                        index: entry.index,
+                        program_id: frame.program_id.clone(),
+                        depth: frame.depth,
+                        log_type: entry.log.as_ref().map(|log| log.log_type()),
+                        raw: entry.raw.clone(),
+                    })
+            })
+            .collect::<Vec<_>>();
+        log_records.sort_by_key(|log| log.index);
+        let mut events = Vec::new();
+        for program in self.programs.iter() {
+            let frames = program_frames(program, &invocations);
+            if frames.is_empty() {
+                continue;
+            }
+            println!(
+                "[event_loader/build_tx_record] Processing {} logs ({} frames) for addr {} on slot {} and sig {}",
+                logs.len(),
+                frames.len(),
+                program.addr,
+                tx.slot,
+                sig
+            );
+            events.extend(
+                decode_events(program, tx.slot, sig.as_str(), &frames)
+                    .into_iter()
+                    .filter(|event| program.filter.accepts_event(&event.payload)),
+            );
+        }
+        // events emitted with emit_cpi! are self-CPIs of the program instead of logs
+        let invoke_indexes = invoke_log_indexes(&message, &invocations);
+        let instructions = instruction_records(&message);
+        let mut program_instructions = Vec::new();
+        for (ix_index, ix) in message.executed().enumerate() {
+            let Some(program) = self.program(ix.program_id.as_str()) else {
+                continue;
+            };
+            let event_data = ix
+                .data
+                .as_deref()
+                .and_then(|data| data.strip_prefix(&EVENT_IX_TAG_LE))
+                .filter(|_| is_self_cpi(program, ix, &instructions[ix_index], &instructions));
+            match event_data {
+                Some(data) => {
+                    // without an invoke line (e.g. no log messages) the event is ordered after all logs
+                    let log_index = invoke_indexes[ix_index].unwrap_or(logs.len() + ix_index);
+                    let event =
+                        decode_cpi_event(program, tx.slot, sig.as_str(), ix_index, log_index, data);
+                    if program.filter.accepts_event(&event.payload) {
+                        events.push(event);
+                    }
+                }
+                None => {
+                    let payload = decode_instruction(program, ix);
+                    if program.filter.accepts_instruction(&payload) {
+                        program_instructions.push(ProgramInstruction {
+                            program_id: program.addr.clone(),
+                            ix_index,
+                            payload,
+                        });
+                    }
+                }
+            }
+        }
+        events.sort_by_key(|event| event.log_index);
+        let failure = meta.as_ref().and_then(|meta| meta.err.as_ref()).map(|err| {
+            self.build_failure(
+                err,
+                &message,
+                &invocations,
+                &invoke_indexes,
+                &program_instructions,
+            )
         });
+        let balance_changes = match meta.as_ref() {
+            Some(meta) if incomplete && self.recover_truncated => {
+                balance_changes(meta, &message.account_keys)
+            }
+            _ => Vec::new(),
+        };
+        TxRecord {
+            slot: tx.slot,
+            signature: sig,
+            block_time: tx.block_time,
+            fee: meta.as_ref().map(|meta| meta.fee).unwrap_or_default(),
+            err: meta
+                .as_ref()
+                .and_then(|meta| meta.err.as_ref())
+                .map(|err| err.to_string()),
+            instructions,
+            accounts: message.account_keys,
+            logs: log_records,
+            events,
+            program_instructions,
+            failure,
+            incomplete,
+            balance_changes,
+        }
+    }
+
+    // build_failure builds the structured error of a failed tx. The error is taken from the
+    // `AnchorError` log of the innermost failed program, or from the error table of its IDL.
+    fn build_failure(
+        &self,
+        err: &TransactionError,
+        message: &TxMessage,
+        invocations: &[Invocation],
+        invoke_indexes: &[Option<usize>],
+        program_instructions: &[ProgramInstruction],
+    ) -> TxFailure {
+        let mut failure = TxFailure::new(err);
+        let Some(top_level) = failure.instruction_index.map(usize::from) else {
+            return failure;
+        };
+        // position of the failed top-level instruction in the executed instructions
+        let ix_index = message
+            .instructions
+            .iter()
+            .enumerate()
+            .take(top_level)
+            .map(|(i, _)| 1 + message.inner_of(i).count())
+            .sum::<usize>();
+        let root = invoke_indexes
+            .get(ix_index)
+            .copied()
+            .flatten()
+            .and_then(|log_index| {
+                invocations
+                    .iter()
+                    .find(|root| root.logs.first().map(|entry| entry.index) == Some(log_index))
+            });
+        let failed = root.and_then(failed_frame);
+        failure.program_id = failed.map(|frame| frame.program_id.clone()).or_else(|| {
+            message
+                .instructions
+                .get(top_level)
+                .map(|ix| ix.program_id.clone())
+        });
+        failure.instruction = program_instructions
+            .iter()
+            .find(|ix| ix.ix_index == ix_index)
+            .and_then(|ix| match &ix.payload {
+                InstructionPayload::Decoded(value) => value
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .map(str::to_string),
+                InstructionPayload::Raw { .. } => None,
+            })
+            .or_else(|| root.and_then(instruction_name));
+        if let Some(anchor_error) = failed.and_then(anchor_error) {
+            return failure.with_anchor_error(anchor_error);
+        }
+        let idl = failure
+            .program_id
+            .as_deref()
+            .and_then(|addr| self.program(addr))
+            .and_then(|program| program.idl.as_ref());
+        if let (FailureKind::Custom, Some(code), Some(idl)) =
+            (failure.kind, failure.error_code, idl)
+        {
+            if let Some(error) = idl.error(code) {
+                failure.error_name = Some(error.name.clone());
+                failure.error_message = error.msg.clone();
+            }
+        }
+        failure
+    }
+}
+
+// load_scope is the log scope of loading txs with the given commitment
+fn load_scope(commitment: CommitStatus) -> &'static str {
+    match commitment {
+        CommitStatus::Finalized => "backfill",
+        CommitStatus::Confirmed => "load_confirmed_events",
+    }
+}
+
+// is_self_cpi checks that a tagged instruction is an event emitted with emit_cpi!: a CPI made by
+// the program itself, signed by its event authority. Anyone can call the program with the tag
+// at the top level or from another program, those instructions are not events.
+fn is_self_cpi(
+    program: &Program,
+    ix: &TxInstruction,
+    record: &InstructionRecord,
+    instructions: &[InstructionRecord],
+) -> bool {
+    let invoked_by_program = record
+        .parent_index
+        .and_then(|parent| instructions.get(parent))
+        .map(|parent| parent.program_id == program.addr)
+        .unwrap_or(false);
+    let signed_by_authority = ix
+        .accounts
+        .first()
+        .map(|account| *account == program.event_authority)
+        .unwrap_or(false);
+    invoked_by_program && signed_by_authority
+}
+
+// program_frames returns the invocations (top-level or CPI) of a tracked program
+fn program_frames<'a>(program: &Program, invocations: &'a [Invocation]) -> Vec<&'a Invocation> {
+    invocations
+        .iter()
+        .flat_map(|root| root.walk())
+        .filter(|frame| frame.program_id == program.addr)
+        .collect()
+}
+
+// decode_events decodes the `Program data:` logs of the given frames with the program IDL,
+// payloads that can't be decoded are kept raw together with the reason
+fn decode_events(program: &Program, slot: u64, sig: &str, frames: &[&Invocation]) -> Vec<Event> {
+    frames
+        .iter()
+        .flat_map(|frame| frame.logs.iter())
+        .filter_map(|entry| match &entry.log {
+            Some(SolLog::Data { addr, data }) => {
+                let payload = match &program.idl {
+                    Some(idl) => match idl.decode_event_data(data) {
+                        Ok(event) => EventPayload::Decoded(event.to_json()),
+                        Err(e) => EventPayload::Raw {
+                            data: data.clone(),
+                            error: e.to_string(),
+                        },
+                    },
+                    None => EventPayload::Raw {
+                        data: data.clone(),
+                        error: format!("no idl for program {}", addr),
+                    },
+                };
+                Some(Event {
+                    slot,
+                    signature: sig.to_string(),
+                    program_id: addr.clone(),
+                    log_index: entry.index,
+                    ix_index: None,
+                    payload,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// invoke_log_indexes returns the index of the invoke log line of each executed instruction.
+// Instructions and invocation frames are both in execution order, instructions without a frame
+// (e.g. precompiles or truncated logs) are skipped by matching the program ids.
+fn invoke_log_indexes(message: &TxMessage, invocations: &[Invocation]) -> Vec<Option<usize>> {
+    let mut frames = invocations.iter().flat_map(|root| root.walk()).peekable();
+    message
+        .executed()
+        .map(|ix| {
+            let frame = frames.next_if(|frame| frame.program_id == ix.program_id)?;
+            frame.logs.first().map(|entry| entry.index)
+        })
+        .collect()
+}
+
+// decode_cpi_event decodes the data of an event emitted with emit_cpi! (without the event tag)
+fn decode_cpi_event(
+    program: &Program,
+    slot: u64,
+    sig: &str,
+    ix_index: usize,
+    log_index: usize,
+    data: &[u8],
+) -> Event {
+    let raw = |error: String| EventPayload::Raw {
+        data: vec![BASE64.encode(data)],
+        error,
+    };
+    let payload = match &program.idl {
+        Some(idl) => match idl.decode_event(data) {
+            Ok(event) => EventPayload::Decoded(event.to_json()),
+            Err(e) => raw(e.to_string()),
+        },
+        None => raw(format!("no idl for program {}", program.addr)),
+    };
+    Event {
+        slot,
+        signature: sig.to_string(),
+        program_id: program.addr.clone(),
+        log_index,
+        ix_index: Some(ix_index),
+        payload,
+    }
+}
+
+// decode_instruction decodes an instruction of a tracked program with its IDL
+fn decode_instruction(program: &Program, ix: &TxInstruction) -> InstructionPayload {
+    let raw = |error: String| InstructionPayload::Raw {
+        data: ix
+            .data
+            .as_ref()
+            .map(|data| bs58::encode(data).into_string()),
+        error,
+    };
+    match (&program.idl, &ix.data) {
+        (Some(idl), Some(data)) => match idl.decode_instruction(data, &ix.accounts) {
+            Ok(decoded) => InstructionPayload::Decoded(decoded.to_json()),
+            Err(e) => raw(e.to_string()),
+        },
+        (None, _) => raw(format!("no idl for program {}", program.addr)),
+        (_, None) => raw("instruction data was parsed by the node".to_string()),
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// InstructionPayload is the content of an instruction of a tracked program
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstructionPayload {
+    // Decoded is the instruction decoded with the program IDL,
+    // e.g. {"name":"create","args":{"authority":"..."},"accounts":{"counter":"...","user":"...","system_program":"..."}}
+    Decoded(serde_json::Value),
+    // Raw keeps the base58 data of instructions that could not be decoded
+    Raw { data: Option<String>, error: String },
+}
+
+// ProgramInstruction is a top-level or inner instruction that invoked a tracked program
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgramInstruction {
+    pub program_id: String,
+    // ix_index is the index of the instruction in the instructions of the transaction
+    pub ix_index: usize,
+    pub payload: InstructionPayload,
+}
+
+// EventPayload is the content of a `Program data:` log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventPayload {
+    // Decoded is the event decoded with the program IDL, e.g. {"name":"CountChangeEvent","data":42,"label":"inc"}
+    Decoded(serde_json::Value),
+    // Raw keeps the base64 data of payloads that could not be decoded
+    Raw { data: Vec<String>, error: String },
+}
+
+// Event is an event emitted by a tracked program in a transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub slot: u64,
+    pub signature: String,
+    pub program_id: String,
+    // log_index is the position of the `Program data:` line in the transaction logs,
+    // for events emitted with emit_cpi! it is the position of the invoke line of the self-CPI
+    pub log_index: usize,
+    // ix_index is the index of the self-CPI instruction of events emitted with emit_cpi!, None for log events
+    pub ix_index: Option<usize>,
+    pub payload: EventPayload,
+}
+
+// LogRecord is a log line of a transaction, attributed to the program that emitted it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    pub index: usize,
+    pub program_id: String,
+    pub depth: u32,
+    // log_type is None for lines with unknown structure
+    pub log_type: Option<LogType>,
+    pub raw: String,
+}
+
+// TxRecord is a transaction of a tracked program with its instructions, logs, events and decoded instructions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TxRecord {
+    pub slot: u64,
+    pub signature: String,
+    pub block_time: Option<i64>,
+    pub fee: u64,
+    pub err: Option<String>,
+    // accounts are the resolved account keys of the tx, the static keys followed by the
+    // writable and readonly keys loaded from address lookup tables (v0 txs)
+    pub accounts: Vec<String>,
+    // instructions are the top-level and inner instructions of the tx, also of untracked programs
+    pub instructions: Vec<InstructionRecord>,
+    pub logs: Vec<LogRecord>,
+    pub events: Vec<Event>,
+    // program_instructions are the instructions of the tracked programs, decoded with their IDL
+    pub program_instructions: Vec<ProgramInstruction>,
+    // failure is the structured error of a failed tx
+    pub failure: Option<TxFailure>,
+    // incomplete is set if the logs of the tx were truncated, events logged after the
+    // truncation are missing (events emitted with emit_cpi! are not affected)
+    pub incomplete: bool,
+    // balance_changes are only recorded for incomplete txs if truncation recovery is enabled
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum LogType {
     ProgramInvoke,
     ProgramLog,
@@ -378,37 +1426,168 @@ pub enum LogType {
     ProgramData,
     ProgramConsumed,
     ProgramResult,
+    LogTruncated,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SolLog {
-    pub addr: String,
-    pub data: String,
-    pub log_type: LogType,
+impl LogType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogType::ProgramInvoke => "ProgramInvoke",
+            LogType::ProgramLog => "ProgramLog",
+            LogType::ProgramLogInstruction => "ProgramLogInstruction",
+            LogType::ProgramData => "ProgramData",
+            LogType::ProgramConsumed => "ProgramConsumed",
+            LogType::ProgramResult => "ProgramResult",
+            LogType::LogTruncated => "LogTruncated",
+        }
+    }
+}
+
+// InvokeResult is the outcome reported by the runtime when a program invocation returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum InvokeResult {
+    Success,
+    Failed(String),
+}
+
+// SolLog is a single parsed log line, the fields depend on the type of the log.
+// Log and data lines don't carry the program id, so addr is the program the line is attributed to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum SolLog {
+    // Program <program_id> invoke [<depth>]
+    Invoke {
+        program_id: String,
+        depth: u32,
+    },
+    // Program log: <message>
+    Log {
+        addr: String,
+        message: String,
+    },
+    // Program log: Instruction: <name>
+    Instruction {
+        addr: String,
+        name: String,
+    },
+    // Program data: <base64> [<base64> ...]
+    Data {
+        addr: String,
+        data: Vec<String>,
+    },
+    // Program <program_id> consumed <consumed> of <limit> compute units
+    Consumed {
+        program_id: String,
+        consumed: u64,
+        limit: u64,
+    },
+    // Program <program_id> success | Program <program_id> failed: <reason>
+    Result {
+        program_id: String,
+        result: InvokeResult,
+    },
+    // Log truncated: the runtime stopped collecting logs after 10KB, addr is the program
+    // that was executing. The lines of the tx after it are lost.
+    Truncated {
+        addr: String,
+    },
 }
 
+impl SolLog {
+    // log_type returns the LogType of the log
+    pub fn log_type(&self) -> LogType {
+        match self {
+            SolLog::Invoke { .. } => LogType::ProgramInvoke,
+            SolLog::Log { .. } => LogType::ProgramLog,
+            SolLog::Instruction { .. } => LogType::ProgramLogInstruction,
+            SolLog::Data { .. } => LogType::ProgramData,
+            SolLog::Consumed { .. } => LogType::ProgramConsumed,
+            SolLog::Result { .. } => LogType::ProgramResult,
+            SolLog::Truncated { .. } => LogType::LogTruncated,
+        }
+    }
+
+    // addr returns the address of the program the log belongs to
+    pub fn addr(&self) -> &str {
+        match self {
+            SolLog::Invoke { program_id, .. }
+            | SolLog::Consumed { program_id, .. }
+            | SolLog::Result { program_id, .. } => program_id,
+            SolLog::Log { addr, .. }
+            | SolLog::Instruction { addr, .. }
+            | SolLog::Data { addr, .. }
+            | SolLog::Truncated { addr } => addr,
+        }
+    }
+}
+
+static INVOKE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Program (\w+) invoke \[(\d+)\]$").unwrap());
+static LOG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Program log: (?:Instruction: (.*)|(.*))$").unwrap());
+static DATA_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^Program data: (.*)$").unwrap());
+static CONSUMED_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Program (\w+) consumed (\d+) of (\d+) compute units$").unwrap());
+static RESULT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Program (\w+) (?:(success)|failed: (.*))$").unwrap());
+
+// TRUNCATED_LOG is the line the runtime emits instead of the logs that exceed its limit
+pub const TRUNCATED_LOG: &str = "Log truncated";
+
 // parse logs from a transaction, supports:
 // - Program (\w*) invoke \[(\d)\]: Program J1zQwrBNBngz26jRPNWsUSZMHJwBwpkoDitXRV95LdK4 invoke [1]
 // - Program log: (Instruction: (.*)|.*): Program log: Instruction: CreateLog
 // - Program data: (.*): Program data: HDQnaQjSWwkNAAAASGVsbG8sIFdvcmxkISoAAAAAAAAA // base64 encoded; borsh encoded with identifier
 // - Program \w* consumed (\d*) (.*): Program J1zQwrBNBngz26jRPNWsUSZMHJwBwpkoDitXRV95LdK4 consumed 1477 of 200000 compute units
 // - Program \w* (success|failed): Program J1zQwrBNBngz26jRPNWsUSZMHJwBwpkoDitXRV95LdK4 success
+// - Log truncated
+// addr is used for log, data and truncation lines, which don't include the program id.
 pub fn parse_log(log: &str, addr: &str) -> Option<SolLog> {
-    let re = regex::Regex::new(r"Program log: (Instruction: (.*)|.*)").unwrap();
-    if let Some(caps) = re.captures(log) {
-        if let Some(instruction) = caps.get(2) {
-            return Some(SolLog {
+    if log == TRUNCATED_LOG {
+        return Some(SolLog::Truncated {
+            addr: addr.to_string(),
+        });
+    }
+    if let Some(caps) = LOG_RE.captures(log) {
+        if let Some(instruction) = caps.get(1) {
+            return Some(SolLog::Instruction {
                 addr: addr.to_string(),
-                data: instruction.as_str().to_string(),
-                log_type: LogType::ProgramLogInstruction,
+                name: instruction.as_str().to_string(),
             });
-        } else if let Some(data) = caps.get(1) {
-            return Some(SolLog {
+        } else if let Some(message) = caps.get(2) {
+            return Some(SolLog::Log {
                 addr: addr.to_string(),
-                data: data.as_str().to_string(),
-                log_type: LogType::ProgramLog,
+                message: message.as_str().to_string(),
             });
         }
     }
+    if let Some(caps) = DATA_RE.captures(log) {
+        return Some(SolLog::Data {
+            addr: addr.to_string(),
+            data: caps[1].split_whitespace().map(str::to_string).collect(),
+        });
+    }
+    if let Some(caps) = INVOKE_RE.captures(log) {
+        return Some(SolLog::Invoke {
+            program_id: caps[1].to_string(),
+            depth: caps[2].parse().ok()?,
+        });
+    }
+    if let Some(caps) = CONSUMED_RE.captures(log) {
+        return Some(SolLog::Consumed {
+            program_id: caps[1].to_string(),
+            consumed: caps[2].parse().ok()?,
+            limit: caps[3].parse().ok()?,
+        });
+    }
+    if let Some(caps) = RESULT_RE.captures(log) {
+        let result = match caps.get(2) {
+            Some(_) => InvokeResult::Success,
+            None => InvokeResult::Failed(caps[3].to_string()),
+        };
+        return Some(SolLog::Result {
+            program_id: caps[1].to_string(),
+            result,
+        });
+    }
     None
 }
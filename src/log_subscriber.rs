@@ -1,15 +1,58 @@
-use crossbeam_channel::Receiver;
-use futures_util::StreamExt;
+use futures_util::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
 use solana_client::{
-    nonblocking::pubsub_client::PubsubClient,
+    nonblocking::pubsub_client::{PubsubClient, PubsubClientError},
     rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
     rpc_response::RpcLogsResponse,
 };
 use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
-use std::sync::{
-    atomic::{self, AtomicBool},
-    Arc,
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
 };
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+
+use crate::retry::{RetryBudget, RetryPolicy};
+
+// DEFAULT_HEARTBEAT_TIMEOUT is the time without any message after which a socket is considered stale.
+// A slot subscription is kept open next to the logs subscription, so a healthy socket receives
+// a message every few hundred milliseconds even if the address is quiet.
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+// CLOSE_TIMEOUT bounds the time spent unsubscribing from a socket that may be dead
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// STATUS_CAPACITY is the number of status updates that are kept for a slow status reader
+const STATUS_CAPACITY: usize = 256;
+// DEFAULT_CAPACITY is the number of notifications that are buffered for the consumer
+pub const DEFAULT_CAPACITY: usize = 32;
+
+#[derive(Error, Debug)]
+enum SubscriberError {
+    #[error("pubsub error: {0}")]
+    Pubsub(#[from] PubsubClientError),
+    #[error("subscription closed by the node")]
+    Closed,
+    #[error("no message received for {0:?}")]
+    Stale(Duration),
+}
+
+// OverflowPolicy is what happens to a notification when the buffer of the consumer is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Block waits until the consumer makes room, the subscriptions stop reading meanwhile
+    #[default]
+    Block,
+    // DropOldest drops the oldest buffered notifications to make room, the consumer skips them
+    DropOldest,
+    // Error stops the subscriber, the stream ends after the buffered notifications
+    Error,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct LogNotification {
@@ -24,73 +67,593 @@ impl LogNotification {
     }
 }
 
+// SubscriberStatus is a change of the state of the shared connection or of the subscription of an address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubscriberStatus {
+    // Connecting is reported before each connection attempt, attempt is the number of failures
+    // since the last successful connection
+    Connecting {
+        attempt: u32,
+    },
+    // Connected is reported once the connection is up, the addresses are subscribed next
+    Connected,
+    // Subscribed is reported once the logs of the address are streamed
+    Subscribed {
+        addr: String,
+    },
+    // SubscriptionFailed is reported when the node rejected the subscription of an address,
+    // it is retried on the same connection after retry_in
+    SubscriptionFailed {
+        addr: String,
+        reason: String,
+        retry_in: Duration,
+    },
+    // Unsubscribed is reported when an address was removed from a running subscriber
+    Unsubscribed {
+        addr: String,
+    },
+    // Disconnected is reported when the connection failed, dropped or went stale,
+    // it is reconnected and all addresses are resubscribed after retry_in
+    Disconnected {
+        reason: String,
+        retry_in: Duration,
+    },
+    // Lagged is reported when the consumer skipped notifications dropped by OverflowPolicy::DropOldest
+    Lagged {
+        dropped: u64,
+    },
+    // Overflowed is reported when the subscriber stops because the buffer was full (OverflowPolicy::Error)
+    Overflowed {
+        capacity: usize,
+    },
+    // Stopped is reported when the subscriber was closed or the notifications are no longer received
+    Stopped,
+}
+
+// LogStream is the stream of the notifications of a running LogSubscriber,
+// it ends when the subscriber is closed or stops on overflow
+pub struct LogStream {
+    inner: BoxStream<'static, LogNotification>,
+    capacity: usize,
+}
+
+impl Stream for LogStream {
+    type Item = LogNotification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl LogStream {
+    // into_crossbeam forwards the notifications to a crossbeam channel of the same capacity,
+    // for blocking consumers. It must be called within a tokio runtime.
+    pub fn into_crossbeam(mut self) -> crossbeam_channel::Receiver<LogNotification> {
+        let (sender, receiver) = crossbeam_channel::bounded(self.capacity);
+        let handle = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            while let Some(notification) = handle.block_on(self.inner.next()) {
+                if sender.send(notification).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+}
+
+// NotificationSender is the sending half of the channel of a LogStream
+#[derive(Clone)]
+enum NotificationSender {
+    // Queue is used by OverflowPolicy::Block and OverflowPolicy::Error
+    Queue(mpsc::Sender<LogNotification>, OverflowPolicy),
+    // Ring is used by OverflowPolicy::DropOldest, a lagging receiver skips the oldest notifications
+    Ring(broadcast::Sender<LogNotification>),
+}
+
+// SendError is why a notification could not be passed on to the consumer
+enum SendError {
+    // Closed is returned once the LogStream was dropped
+    Closed,
+    // Full is returned with OverflowPolicy::Error
+    Full,
+}
+
+impl NotificationSender {
+    // channel creates the channel of a LogStream, statuses is used to report dropped notifications
+    fn channel(
+        capacity: usize,
+        overflow: OverflowPolicy,
+        statuses: broadcast::Sender<SubscriberStatus>,
+    ) -> (Self, LogStream) {
+        let capacity = capacity.max(1);
+        match overflow {
+            OverflowPolicy::Block | OverflowPolicy::Error => {
+                let (sender, receiver) = mpsc::channel(capacity);
+                let inner = stream::unfold(receiver, |mut receiver| async move {
+                    receiver
+                        .recv()
+                        .await
+                        .map(|notification| (notification, receiver))
+                });
+                (
+                    Self::Queue(sender, overflow),
+                    LogStream {
+                        inner: inner.boxed(),
+                        capacity,
+                    },
+                )
+            }
+            OverflowPolicy::DropOldest => {
+                let (sender, receiver) = broadcast::channel(capacity);
+                let inner = stream::unfold(receiver, move |mut receiver| {
+                    let statuses = statuses.clone();
+                    async move {
+                        loop {
+                            match receiver.recv().await {
+                                Ok(notification) => return Some((notification, receiver)),
+                                Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                                    eprintln!(
+                                        "[log_subscriber] Consumer lagged, dropped {} notifications",
+                                        dropped
+                                    );
+                                    report(&statuses, SubscriberStatus::Lagged { dropped });
+                                }
+                                Err(broadcast::error::RecvError::Closed) => return None,
+                            }
+                        }
+                    }
+                });
+                (
+                    Self::Ring(sender),
+                    LogStream {
+                        inner: inner.boxed(),
+                        capacity,
+                    },
+                )
+            }
+        }
+    }
+
+    async fn send(&self, notification: LogNotification) -> Result<(), SendError> {
+        match self {
+            Self::Queue(sender, OverflowPolicy::Error) => {
+                sender.try_send(notification).map_err(|err| match err {
+                    mpsc::error::TrySendError::Full(_) => SendError::Full,
+                    mpsc::error::TrySendError::Closed(_) => SendError::Closed,
+                })
+            }
+            Self::Queue(sender, _) => sender
+                .send(notification)
+                .await
+                .map_err(|_| SendError::Closed),
+            // sending only fails without receivers
+            Self::Ring(sender) => sender
+                .send(notification)
+                .map(|_| ())
+                .map_err(|_| SendError::Closed),
+        }
+    }
+}
+
+// LogSubscriber streams the logs of a set of addresses over websocket subscriptions.
+// All addresses are subscribed on a single connection, which is reconnected with a jittered
+// exponential backoff whenever it fails, is closed by the node or goes stale.
+// Addresses can be added and removed while the subscriber is running.
 pub struct LogSubscriber {
     ws_url: String,
-    is_running: Arc<AtomicBool>,
+    // shutdown stops the tasks of the last run
+    shutdown: Mutex<CancellationToken>,
+    // reconnect is the backoff between connection attempts, its budget is not used
+    reconnect: RetryPolicy,
+    heartbeat_timeout: Duration,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    commitment: CommitmentConfig,
+    statuses: broadcast::Sender<SubscriberStatus>,
 
-    addrs: Vec<String>,
+    addrs: Arc<Mutex<Vec<String>>>,
+    // addrs_changed wakes up the connection task to subscribe added and unsubscribe removed addresses
+    addrs_changed: Arc<Notify>,
 }
 
 impl LogSubscriber {
     pub fn new(ws_url: &str, addrs: Vec<String>) -> Self {
+        let (statuses, _) = broadcast::channel(STATUS_CAPACITY);
+        let mut unique: Vec<String> = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            if !unique.contains(&addr) {
+                unique.push(addr);
+            }
+        }
         Self {
             ws_url: ws_url.to_string(),
-            is_running: Arc::new(AtomicBool::new(false)),
-            addrs,
+            shutdown: Mutex::new(CancellationToken::new()),
+            reconnect: reconnect_policy(Duration::from_millis(500), Duration::from_secs(30)),
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            capacity: DEFAULT_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            commitment: CommitmentConfig {
+                commitment: CommitmentLevel::Processed,
+            },
+            statuses,
+            addrs: Arc::new(Mutex::new(unique)),
+            addrs_changed: Arc::new(Notify::new()),
         }
     }
 
+    // with_reconnect_backoff sets the delays between connection attempts,
+    // they grow exponentially from base_delay up to max_delay
+    pub fn with_reconnect_backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.reconnect = reconnect_policy(base_delay, max_delay);
+        self
+    }
+
+    // with_heartbeat_timeout sets the time without any message after which the socket is reconnected
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    // with_capacity sets the number of notifications that are buffered for the consumer,
+    // it is rounded up to a power of two with OverflowPolicy::DropOldest
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    // with_overflow sets what happens to notifications when the buffer of the consumer is full
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    // with_commitment sets the commitment level of the notifications, the default is processed
+    pub fn with_commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    // status returns a receiver of the connection state changes that happen from now on.
+    // A status reader that falls behind skips the oldest updates, the subscriber never waits for it.
+    pub fn status(&self) -> broadcast::Receiver<SubscriberStatus> {
+        self.statuses.subscribe()
+    }
+
+    // addrs returns the addresses that are subscribed
+    pub fn addrs(&self) -> Vec<String> {
+        self.addrs.lock().unwrap().clone()
+    }
+
+    // add_addr subscribes to the logs of an address, on the running connection if any.
+    // It returns false if the address was already subscribed.
+    pub fn add_addr(&self, addr: &str) -> bool {
+        let mut addrs = self.addrs.lock().unwrap();
+        if addrs.iter().any(|a| a == addr) {
+            return false;
+        }
+        addrs.push(addr.to_string());
+        self.addrs_changed.notify_one();
+        true
+    }
+
+    // remove_addr unsubscribes from the logs of an address, the other subscriptions are kept.
+    // It returns false if the address was not subscribed.
+    pub fn remove_addr(&self, addr: &str) -> bool {
+        let mut addrs = self.addrs.lock().unwrap();
+        let len = addrs.len();
+        addrs.retain(|a| a != addr);
+        if addrs.len() == len {
+            return false;
+        }
+        self.addrs_changed.notify_one();
+        true
+    }
+
     pub async fn close(&self) {
-        self.is_running.store(false, atomic::Ordering::Relaxed);
+        self.shutdown.lock().unwrap().cancel();
     }
 
-    pub async fn run(&self) -> Result<Receiver<LogNotification>, Box<dyn std::error::Error>> {
-        let (sender, receiver) = crossbeam_channel::bounded(32);
-        self.is_running.store(true, atomic::Ordering::Relaxed);
-        let addrs = self.addrs.clone();
-        let ws_url = self.ws_url.clone();
-        for addr in addrs {
-            let ws_url = ws_url.clone();
-            let sender = sender.clone();
-            let is_running = self.is_running.clone();
-            let addr_cp = addr.clone();
-            tokio::spawn(async move {
-                if let Err(e) = async {
-                    println!("[log_subscriber] Subscribing to logs for address: {}", addr);
-                    let ps_client = PubsubClient::new(ws_url.as_str()).await?; // TODO: use a single client
-                    let filter = RpcTransactionLogsFilter::Mentions(vec![addr.to_string()]);
-                    let cfg = RpcTransactionLogsConfig {
-                        commitment: Some(CommitmentConfig {
-                            commitment: CommitmentLevel::Processed,
-                        }),
+    // run starts the subscriptions and returns the stream of their notifications,
+    // use LogStream::into_crossbeam for a blocking receiver
+    pub async fn run(&self) -> Result<LogStream, Box<dyn std::error::Error>> {
+        let (sender, stream) =
+            NotificationSender::channel(self.capacity, self.overflow, self.statuses.clone());
+        let shutdown = CancellationToken::new();
+        {
+            // a subscriber runs at most one connection
+            let mut current = self.shutdown.lock().unwrap();
+            current.cancel();
+            *current = shutdown.clone();
+        }
+        let task = ConnectionTask {
+            ws_url: self.ws_url.clone(),
+            addrs: self.addrs.clone(),
+            addrs_changed: self.addrs_changed.clone(),
+            sender,
+            capacity: self.capacity,
+            commitment: self.commitment,
+            statuses: self.statuses.clone(),
+            reconnect: self.reconnect.clone(),
+            heartbeat_timeout: self.heartbeat_timeout,
+            shutdown,
+        };
+        tokio::spawn(async move { task.run().await });
+
+        Ok(stream)
+    }
+}
+
+fn reconnect_policy(base_delay: Duration, max_delay: Duration) -> RetryPolicy {
+    RetryPolicy::new(
+        base_delay,
+        max_delay,
+        RetryBudget::new(u32::MAX, Duration::MAX),
+    )
+}
+
+// report publishes a status update, it is dropped if nobody reads the statuses
+fn report(statuses: &broadcast::Sender<SubscriberStatus>, status: SubscriberStatus) {
+    let _ = statuses.send(status);
+}
+
+// ConnectionTask keeps the shared connection alive until it is shut down
+// and runs a subscription task per address on it
+struct ConnectionTask {
+    ws_url: String,
+    addrs: Arc<Mutex<Vec<String>>>,
+    addrs_changed: Arc<Notify>,
+    sender: NotificationSender,
+    capacity: usize,
+    commitment: CommitmentConfig,
+    statuses: broadcast::Sender<SubscriberStatus>,
+    reconnect: RetryPolicy,
+    heartbeat_timeout: Duration,
+    shutdown: CancellationToken,
+}
+
+impl ConnectionTask {
+    async fn run(self) {
+        let mut attempt = 0u32;
+        loop {
+            report(&self.statuses, SubscriberStatus::Connecting { attempt });
+            let err = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                result = self.connect(&mut attempt) => match result {
+                    Ok(()) => break,
+                    Err(err) => err,
+                },
+            };
+            let retry_in = self.reconnect.backoff(attempt, None);
+            eprintln!(
+                "[log_subscriber] Connection to {} failed, retrying in {:?}: {}",
+                self.ws_url, retry_in, err
+            );
+            report(
+                &self.statuses,
+                SubscriberStatus::Disconnected {
+                    reason: err.to_string(),
+                    retry_in,
+                },
+            );
+            attempt = attempt.saturating_add(1);
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = tokio::time::sleep(retry_in) => {}
+            }
+        }
+        // stop the subscription tasks of a connection that is still up
+        self.shutdown.cancel();
+        println!("[log_subscriber] Stopped subscriptions on {}", self.ws_url);
+        report(&self.statuses, SubscriberStatus::Stopped);
+    }
+
+    // connect subscribes all addresses on a new connection until it fails (Err)
+    // or the subscriber is shut down (Ok)
+    async fn connect(&self, attempt: &mut u32) -> Result<(), SubscriberError> {
+        println!("[log_subscriber] Connecting to {}", self.ws_url);
+        let ps_client = Arc::new(PubsubClient::new(self.ws_url.as_str()).await?);
+        let (mut slot_stream, slot_unsubscriber) = ps_client.slot_subscribe().await?;
+        *attempt = 0;
+        report(&self.statuses, SubscriberStatus::Connected);
+        // connection is cancelled when the connection is lost, it stops the subscription tasks
+        let connection = self.shutdown.child_token();
+        let mut subscriptions: HashMap<String, CancellationToken> = HashMap::new();
+        let result = loop {
+            self.reconcile(&ps_client, &connection, &mut subscriptions);
+            tokio::select! {
+                _ = connection.cancelled() => {
+                    break match self.shutdown.is_cancelled() {
+                        true => Ok(()),
+                        false => Err(SubscriberError::Closed),
                     };
-                    let (mut slot_stream, unsubscriber) =
-                        ps_client.logs_subscribe(filter, cfg).await?;
-                    while let Some(logs_info) = slot_stream.next().await {
-                        sender
-                            .send(LogNotification::new(
-                                logs_info.value,
-                                addr_cp.clone(),
-                                logs_info.context.slot,
-                            ))
-                            .unwrap();
-                        if !is_running.load(atomic::Ordering::Relaxed) {
-                            break;
-                        }
+                },
+                _ = self.addrs_changed.notified() => {},
+                slot_info = slot_stream.next() => {
+                    if slot_info.is_none() {
+                        break Err(SubscriberError::Closed);
                     }
-                    unsubscriber().await;
-                    Ok::<(), Box<dyn std::error::Error>>(())
+                },
+                _ = tokio::time::sleep(self.heartbeat_timeout) => {
+                    break Err(SubscriberError::Stale(self.heartbeat_timeout));
+                },
+            }
+        };
+        connection.cancel();
+        drop(slot_stream);
+        // unsubscribing waits for the node to answer, which a stale socket never does
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, slot_unsubscriber()).await;
+        result
+    }
+
+    // reconcile starts a subscription task for each added address and stops the ones of removed addresses
+    fn reconcile(
+        &self,
+        ps_client: &Arc<PubsubClient>,
+        connection: &CancellationToken,
+        subscriptions: &mut HashMap<String, CancellationToken>,
+    ) {
+        let addrs = self.addrs.lock().unwrap().clone();
+        subscriptions.retain(|addr, token| {
+            let keep = addrs.contains(addr);
+            if !keep {
+                token.cancel();
+            }
+            keep
+        });
+        for addr in addrs {
+            if subscriptions.contains_key(&addr) {
+                continue;
+            }
+            let token = connection.child_token();
+            let task = SubscriptionTask {
+                addr: addr.clone(),
+                sender: self.sender.clone(),
+                capacity: self.capacity,
+                commitment: self.commitment,
+                statuses: self.statuses.clone(),
+                reconnect: self.reconnect.clone(),
+            };
+            let ps_client = ps_client.clone();
+            let (token_cp, connection, shutdown) =
+                (token.clone(), connection.clone(), self.shutdown.clone());
+            tokio::spawn(async move { task.run(ps_client, token_cp, connection, shutdown).await });
+            subscriptions.insert(addr, token);
+        }
+    }
+}
+
+// SubscriptionTask streams the logs of an address on the shared connection
+struct SubscriptionTask {
+    addr: String,
+    sender: NotificationSender,
+    capacity: usize,
+    commitment: CommitmentConfig,
+    statuses: broadcast::Sender<SubscriberStatus>,
+    reconnect: RetryPolicy,
+}
+
+impl SubscriptionTask {
+    // run subscribes until the address is removed (token), the connection is lost (connection)
+    // or the receiver of the notifications is dropped, which stops the subscriber (shutdown)
+    async fn run(
+        self,
+        ps_client: Arc<PubsubClient>,
+        token: CancellationToken,
+        connection: CancellationToken,
+        shutdown: CancellationToken,
+    ) {
+        let mut attempt = 0u32;
+        loop {
+            let err = match self.stream(&ps_client, &token, &mut attempt).await {
+                Ok(true) => break,
+                Ok(false) => {
+                    shutdown.cancel();
+                    break;
                 }
-                .await
-                {
-                    eprintln!(
-                        "[log_subscriber] Error subscribing to logs for address {}: {:?}",
-                        addr, e
-                    );
+                Err(SubscriberError::Closed) => {
+                    connection.cancel();
+                    break;
                 }
-            });
+                Err(err) => err,
+            };
+            let retry_in = self.reconnect.backoff(attempt, None);
+            eprintln!(
+                "[log_subscriber] Subscription for address {} failed, retrying in {:?}: {}",
+                self.addr, retry_in, err
+            );
+            report(
+                &self.statuses,
+                SubscriberStatus::SubscriptionFailed {
+                    addr: self.addr.clone(),
+                    reason: err.to_string(),
+                    retry_in,
+                },
+            );
+            attempt = attempt.saturating_add(1);
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(retry_in) => {}
+            }
+        }
+        if token.is_cancelled() && !connection.is_cancelled() {
+            println!(
+                "[log_subscriber] Unsubscribed from logs for address: {}",
+                self.addr
+            );
+            report(
+                &self.statuses,
+                SubscriberStatus::Unsubscribed {
+                    addr: self.addr.clone(),
+                },
+            );
         }
+    }
 
-        Ok(receiver)
+    // stream forwards the logs of the address until the token is cancelled (Ok(true)), the consumer
+    // dropped the stream or its buffer overflowed (Ok(false)) or the subscription fails (Err)
+    async fn stream(
+        &self,
+        ps_client: &PubsubClient,
+        token: &CancellationToken,
+        attempt: &mut u32,
+    ) -> Result<bool, SubscriberError> {
+        println!(
+            "[log_subscriber] Subscribing to logs for address: {}",
+            self.addr
+        );
+        let filter = RpcTransactionLogsFilter::Mentions(vec![self.addr.clone()]);
+        let cfg = RpcTransactionLogsConfig {
+            commitment: Some(self.commitment),
+        };
+        let (mut logs_stream, logs_unsubscriber) = tokio::select! {
+            _ = token.cancelled() => return Ok(true),
+            subscription = ps_client.logs_subscribe(filter, cfg) => subscription?,
+        };
+        *attempt = 0;
+        report(
+            &self.statuses,
+            SubscriberStatus::Subscribed {
+                addr: self.addr.clone(),
+            },
+        );
+        let result = loop {
+            tokio::select! {
+                _ = token.cancelled() => break Ok(true),
+                logs_info = logs_stream.next() => match logs_info {
+                    Some(logs_info) => {
+                        let notification = LogNotification::new(
+                            logs_info.value,
+                            self.addr.clone(),
+                            logs_info.context.slot,
+                        );
+                        tokio::select! {
+                            _ = token.cancelled() => break Ok(true),
+                            sent = self.sender.send(notification) => match sent {
+                                Ok(()) => {}
+                                Err(SendError::Closed) => break Ok(false),
+                                Err(SendError::Full) => {
+                                    eprintln!(
+                                        "[log_subscriber] Buffer of {} notifications is full, stopping",
+                                        self.capacity
+                                    );
+                                    report(
+                                        &self.statuses,
+                                        SubscriberStatus::Overflowed {
+                                            capacity: self.capacity,
+                                        },
+                                    );
+                                    break Ok(false);
+                                }
+                            },
+                        }
+                    }
+                    None => break Err(SubscriberError::Closed),
+                },
+            }
+        };
+        drop(logs_stream);
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, logs_unsubscriber()).await;
+        result
     }
 }
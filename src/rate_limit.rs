@@ -0,0 +1,145 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// RateLimit caps the request rate (token bucket) and the number of requests in flight (semaphore).
+// Both are optional, the default is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RateLimit {
+    pub requests_per_sec: Option<f64>,
+    // burst is the capacity of the bucket, defaults to one second of requests
+    pub burst: Option<u32>,
+    pub max_concurrent: Option<usize>,
+}
+
+impl RateLimit {
+    pub fn per_second(requests_per_sec: f64) -> Self {
+        Self {
+            requests_per_sec: Some(requests_per_sec),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_sec.is_none() && self.max_concurrent.is_none()
+    }
+}
+
+// RateLimitStats is the time requests spent waiting for a limiter
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct RateLimitStats {
+    pub scope: String,
+    pub acquired: u64,
+    // throttled is the number of requests that had to wait
+    pub throttled: u64,
+    pub waited: Duration,
+    pub max_wait: Duration,
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    // tokens goes negative when requests reserved tokens that are not refilled yet
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    // reserve takes cost tokens and returns how long the caller has to wait until they are available.
+    // Reservations are served in order, a waiting request doesn't hold the lock.
+    fn reserve(&mut self, cost: f64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+        self.tokens -= cost;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+// Limiter enforces a RateLimit for a scope (global, method or endpoint)
+pub(crate) struct Limiter {
+    bucket: Option<Mutex<TokenBucket>>,
+    semaphore: Option<Arc<Semaphore>>,
+    stats: Mutex<RateLimitStats>,
+}
+
+// LimiterPermit holds the concurrency slot of a request until it is dropped
+pub(crate) struct LimiterPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Limiter {
+    pub(crate) fn new(scope: String, limit: RateLimit) -> Self {
+        Self {
+            bucket: limit
+                .requests_per_sec
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| {
+                    let capacity = limit.burst.map(f64::from).unwrap_or(rate.ceil()).max(1.0);
+                    Mutex::new(TokenBucket::new(rate, capacity))
+                }),
+            semaphore: limit
+                .max_concurrent
+                .map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            stats: Mutex::new(RateLimitStats {
+                scope,
+                ..Default::default()
+            }),
+        }
+    }
+
+    // acquire waits for a concurrency slot and cost tokens, e.g. one per call of a batch request
+    pub(crate) async fn acquire(&self, cost: u32) -> LimiterPermit {
+        let start = Instant::now();
+        let permit = match &self.semaphore {
+            // the semaphore is never closed
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        if let Some(bucket) = &self.bucket {
+            let delay = bucket.lock().unwrap().reserve(f64::from(cost));
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        let waited = start.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        stats.acquired += 1;
+        if waited >= Duration::from_millis(1) {
+            stats.throttled += 1;
+        }
+        stats.waited += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+        LimiterPermit { _permit: permit }
+    }
+
+    pub(crate) fn stats(&self) -> RateLimitStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,156 @@
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+// RetryBudget limits how often and how long a request is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBudget {
+    // max_attempts includes the first attempt, 1 disables retries
+    pub max_attempts: u32,
+    // max_elapsed is the time after which no new attempt is started
+    pub max_elapsed: Duration,
+}
+
+impl RetryBudget {
+    pub const fn new(max_attempts: u32, max_elapsed: Duration) -> Self {
+        Self {
+            max_attempts,
+            max_elapsed,
+        }
+    }
+}
+
+// Retries tracks the retries of an operation against its budget. It is shared by all the
+// requests of the operation, e.g. the halves a batch request is split into, so the operation
+// as a whole is retried at most max_attempts - 1 times and no retry starts after max_elapsed.
+#[derive(Debug)]
+pub(crate) struct Retries {
+    budget: RetryBudget,
+    start: Instant,
+    retries: AtomicU32,
+}
+
+impl Retries {
+    pub(crate) fn new(budget: RetryBudget) -> Self {
+        Self {
+            budget,
+            start: Instant::now(),
+            retries: AtomicU32::new(0),
+        }
+    }
+
+    pub(crate) fn budget(&self) -> RetryBudget {
+        self.budget
+    }
+
+    // expired returns true once max_elapsed has passed
+    pub(crate) fn expired(&self) -> bool {
+        self.start.elapsed() >= self.budget.max_elapsed
+    }
+
+    // take returns the (0-based) number of the retry, or None if the budget is spent
+    pub(crate) fn take(&self) -> Option<u32> {
+        if self.expired() {
+            return None;
+        }
+        let max_retries = self.budget.max_attempts.saturating_sub(1);
+        self.retries
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |retries| {
+                (retries < max_retries).then_some(retries + 1)
+            })
+            .ok()
+    }
+}
+
+// RetryPolicy is the backoff between attempts and the retry budget of each rpc method.
+// Delays grow exponentially from base_delay up to max_delay and are fully jittered
+// so that concurrent requests don't retry in lockstep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    default_budget: RetryBudget,
+    budgets: HashMap<String, RetryBudget>,
+    not_found_budget: RetryBudget,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_millis(250),
+            Duration::from_secs(10),
+            RetryBudget::new(5, Duration::from_secs(30)),
+        )
+        // a confirmed tx can be listed by a node before its transaction is queryable
+        .with_not_found_budget(RetryBudget::new(8, Duration::from_secs(60)))
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(base_delay: Duration, max_delay: Duration, default_budget: RetryBudget) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            default_budget,
+            budgets: HashMap::new(),
+            not_found_budget: default_budget,
+        }
+    }
+
+    // none is a policy that never retries
+    pub fn none() -> Self {
+        Self::new(
+            Duration::ZERO,
+            Duration::ZERO,
+            RetryBudget::new(1, Duration::ZERO),
+        )
+    }
+
+    // with_budget sets the budget of a method (get_slot, get_sigs_for_addr, get_tx, send)
+    pub fn with_budget(mut self, method: &str, budget: RetryBudget) -> Self {
+        self.budgets.insert(method.to_string(), budget);
+        self
+    }
+
+    // with_default_budget sets the budget of the methods without a budget of their own
+    pub fn with_default_budget(mut self, budget: RetryBudget) -> Self {
+        self.default_budget = budget;
+        self
+    }
+
+    pub fn budget(&self, method: &str) -> RetryBudget {
+        self.budgets
+            .get(method)
+            .copied()
+            .unwrap_or(self.default_budget)
+    }
+
+    // with_not_found_budget sets the budget of asking an endpoint again for a tx it doesn't know yet,
+    // these retries are not taken from the budget of get_tx
+    pub fn with_not_found_budget(mut self, budget: RetryBudget) -> Self {
+        self.not_found_budget = budget;
+        self
+    }
+
+    pub fn not_found_budget(&self) -> RetryBudget {
+        self.not_found_budget
+    }
+
+    // backoff returns the delay before the retry that follows the given (0-based) attempt,
+    // it is never shorter than the retry-after the server asked for
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let jittered = if ceiling.is_zero() {
+            ceiling
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+        };
+        jittered.max(retry_after.unwrap_or_default())
+    }
+}
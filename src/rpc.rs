@@ -1,67 +1,806 @@
+use futures_util::future::{BoxFuture, FutureExt};
 use serde_json::json;
 use solana_client::{
-    nonblocking::rpc_client::RpcClient,
     rpc_config::{RpcSignaturesForAddressConfig, RpcTransactionConfig},
+    rpc_custom_error::{
+        JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    },
     rpc_request::RpcRequest,
     rpc_response::RpcConfirmedTransactionStatusWithSignature,
 };
 use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
 use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
 
+use crate::rate_limit::{Limiter, LimiterPermit, RateLimit, RateLimitStats};
+use crate::retry::{Retries, RetryBudget, RetryPolicy};
+
 #[derive(Error, Debug, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
 pub enum RpcError {
-    #[error("failed to get slot: {0}")]
-    GetSlotError(String),
-    #[error("failed to get sigs for addr {0}: {1}")]
-    GetSigsForAddrError(String, String),
-    #[error("failed to get tx for sig {0}: {1}")]
-    GetTxError(String, String),
-    #[error("failed to send {0}: {1}")]
-    SendError(String, String),
+    #[error("{method} timed out on {endpoint}")]
+    Timeout { method: String, endpoint: String },
+    #[error("{method} was rate limited by {endpoint} (retry after {retry_after:?})")]
+    RateLimited {
+        method: String,
+        endpoint: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("{method} failed, {endpoint} is behind (min context slot {min_context_slot:?})")]
+    NodeBehind {
+        method: String,
+        endpoint: String,
+        min_context_slot: Option<u64>,
+    },
+    #[error("tx {0} not found")]
+    TxNotFound(String),
+    #[error("failed to decode {method} response: {message}")]
+    DecodeError { method: String, message: String },
+    #[error("{method} failed on {endpoint}: {message}")]
+    TransportError {
+        method: String,
+        endpoint: String,
+        message: String,
+    },
+    #[error("{method} failed on {endpoint} with code {code}: {message}")]
+    NodeError {
+        method: String,
+        endpoint: String,
+        code: i64,
+        message: String,
+    },
+    #[error("invalid rpc config: {0}")]
+    ConfigError(String),
+}
+
+impl RpcError {
+    // from_code classifies the error object of a JSON-RPC response
+    fn from_code(
+        method: &str,
+        endpoint: &str,
+        min_context_slot: Option<u64>,
+        code: i64,
+        message: String,
+    ) -> Self {
+        let method = method.to_string();
+        let endpoint = endpoint.to_string();
+        match code {
+            429 => RpcError::RateLimited {
+                method,
+                endpoint,
+                retry_after: None,
+            },
+            JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+            | JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY => RpcError::NodeBehind {
+                method,
+                endpoint,
+                min_context_slot,
+            },
+            code => RpcError::NodeError {
+                method,
+                endpoint,
+                code,
+                message,
+            },
+        }
+    }
+
+    // from_http classifies the error of an http request
+    fn from_http(method: &str, endpoint: &str, err: reqwest::Error) -> Self {
+        let method = method.to_string();
+        let endpoint = endpoint.to_string();
+        if err.is_timeout() {
+            RpcError::Timeout { method, endpoint }
+        } else if err.is_decode() {
+            RpcError::DecodeError {
+                method,
+                message: err.to_string(),
+            }
+        } else {
+            RpcError::TransportError {
+                method,
+                endpoint,
+                message: err.to_string(),
+            }
+        }
+    }
+
+    // from_object classifies a JSON-RPC error object
+    fn from_object(
+        method: &str,
+        endpoint: &str,
+        min_context_slot: Option<u64>,
+        error: &serde_json::Value,
+    ) -> Self {
+        match error.get("code").and_then(|code| code.as_i64()) {
+            Some(code) => Self::from_code(
+                method,
+                endpoint,
+                min_context_slot,
+                code,
+                error
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            None => RpcError::DecodeError {
+                method: method.to_string(),
+                message: format!("invalid error object {}", error),
+            },
+        }
+    }
+
+    // is_retryable returns true for errors that might not occur on a later attempt
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            RpcError::Timeout { .. }
+                | RpcError::RateLimited { .. }
+                | RpcError::NodeBehind { .. }
+                | RpcError::TxNotFound(_)
+                | RpcError::TransportError { .. }
+        )
+    }
+
+    // retry_after is the delay requested by a rate limited endpoint
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RpcError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    // should_failover returns true for errors that another endpoint might not have, they count
+    // against the health of the endpoint. A missing tx is not the fault of the endpoint, it is
+    // asked for again on the same endpoint.
+    fn should_failover(&self) -> bool {
+        self.is_retryable() && !matches!(self, RpcError::TxNotFound(_))
+    }
+}
+
+// UNHEALTHY_AFTER is the number of consecutive failures after which an endpoint is taken out of rotation
+const UNHEALTHY_AFTER: u32 = 3;
+// PROBE_INTERVAL is the time between two probes of an unhealthy endpoint
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+// REQUEST_TIMEOUT is the timeout of a request, the same as the default of RpcClient
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// DEFAULT_MAX_BATCH_SIZE is the default number of calls packed into a JSON-RPC batch request
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 20;
+// LATENCY_ALPHA is the weight of the latest request in the latency moving average
+const LATENCY_ALPHA: f64 = 0.2;
+
+// EndpointConfig is an RPC server and its share of the requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointConfig {
+    pub url: String,
+    // weight is relative to the weights of the other endpoints, endpoints with weight 0 are only used for failover
+    pub weight: u32,
+    // rate_limit is the limit of the provider behind the endpoint
+    pub rate_limit: RateLimit,
+}
+
+impl EndpointConfig {
+    pub fn new(url: String, weight: u32) -> Self {
+        Self {
+            url,
+            weight,
+            rate_limit: RateLimit::default(),
+        }
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+}
+
+// EndpointStats is a snapshot of the health of an endpoint
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct EndpointStats {
+    pub url: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub requests: u64,
+    pub errors: u64,
+    pub consecutive_errors: u32,
+    // latency_ms is the moving average of the latency of successful requests
+    pub latency_ms: f64,
+}
+
+impl EndpointStats {
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.errors as f64 / self.requests as f64
+    }
+}
+
+// Served is the response of a request together with the endpoint that served it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Served<T> {
+    pub value: T,
+    pub endpoint: String,
+    pub latency: Duration,
+}
+
+impl<T> Served<T> {
+    pub fn into_inner(self) -> T {
+        self.value
+    }
 }
 
 struct EndPoint {
-    client: RpcClient,
+    url: String,
+    weight: u32,
+    http: reqwest::Client,
+    stats: Mutex<EndpointStats>,
+    last_probe: Mutex<Option<Instant>>,
+    probing: AtomicBool,
+    limiter: Option<Limiter>,
 }
 
 unsafe impl Send for EndPoint {}
 
 impl EndPoint {
-    fn new(url: String) -> Self {
+    fn new(config: EndpointConfig) -> Self {
+        let EndpointConfig {
+            url,
+            weight,
+            rate_limit,
+        } = config;
         Self {
-            client: RpcClient::new(url),
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            limiter: (!rate_limit.is_unlimited())
+                .then(|| Limiter::new(format!("endpoint:{}", url), rate_limit)),
+            stats: Mutex::new(EndpointStats {
+                url: url.clone(),
+                weight,
+                healthy: true,
+                requests: 0,
+                errors: 0,
+                consecutive_errors: 0,
+                latency_ms: 0.0,
+            }),
+            last_probe: Mutex::new(None),
+            probing: AtomicBool::new(false),
+            url,
+            weight,
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.stats.lock().unwrap().healthy
+    }
+
+    fn latency_ms(&self) -> f64 {
+        self.stats.lock().unwrap().latency_ms
+    }
+
+    fn record_success(&self, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        stats.latency_ms = if stats.requests == stats.errors {
+            latency_ms
+        } else {
+            LATENCY_ALPHA * latency_ms + (1.0 - LATENCY_ALPHA) * stats.latency_ms
+        };
+        stats.requests += 1;
+        stats.consecutive_errors = 0;
+    }
+
+    fn record_failure(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.errors += 1;
+        stats.consecutive_errors += 1;
+        if stats.healthy && stats.consecutive_errors >= UNHEALTHY_AFTER {
+            eprintln!(
+                "[rpc/endpoint] Endpoint {} is unhealthy after {} consecutive errors",
+                self.url, stats.consecutive_errors
+            );
+            stats.healthy = false;
+        }
+    }
+
+    fn reinstate(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        println!("[rpc/endpoint] Endpoint {} is healthy again", self.url);
+        stats.healthy = true;
+        stats.consecutive_errors = 0;
+    }
+
+    // should_probe returns true if the endpoint is unhealthy and was not probed recently
+    fn should_probe(&self) -> bool {
+        if self.is_healthy() || self.probing.load(Ordering::Acquire) {
+            return false;
+        }
+        let mut last_probe = self.last_probe.lock().unwrap();
+        match *last_probe {
+            Some(at) if at.elapsed() < PROBE_INTERVAL => false,
+            _ => {
+                *last_probe = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    // acquire waits for the rate limit of the endpoint
+    async fn acquire(&self, cost: u32) -> Option<LimiterPermit> {
+        match &self.limiter {
+            Some(limiter) => Some(limiter.acquire(cost).await),
+            None => None,
+        }
+    }
+
+    // post sends a JSON-RPC request, the status of the response is classified the same way for
+    // single and batch requests
+    async fn post(
+        &self,
+        method: &str,
+        body: &serde_json::Value,
+    ) -> Result<reqwest::Response, RpcError> {
+        let response = self
+            .http
+            .post(&self.url)
+            .json(body)
+            .send()
+            .await
+            .map_err(|err| RpcError::from_http(method, &self.url, err))?;
+        let status = response.status();
+        if status.as_u16() == 429 {
+            return Err(RpcError::RateLimited {
+                method: method.to_string(),
+                endpoint: self.url.clone(),
+                retry_after: retry_after(response.headers()),
+            });
+        }
+        if status.is_server_error() {
+            return Err(RpcError::TransportError {
+                method: method.to_string(),
+                endpoint: self.url.clone(),
+                message: status.to_string(),
+            });
+        }
+        // e.g. 413 if a batch is too large for the provider
+        if !status.is_success() {
+            return Err(RpcError::NodeError {
+                method: method.to_string(),
+                endpoint: self.url.clone(),
+                code: status.as_u16() as i64,
+                message: status.to_string(),
+            });
+        }
+        Ok(response)
+    }
+
+    // call sends a single JSON-RPC request and decodes its result
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        min_context_slot: Option<u64>,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> Result<T, RpcError> {
+        let body = request.build_request_json(0, params);
+        let mut response = self
+            .post(method, &body)
+            .await?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|err| RpcError::from_http(method, &self.url, err))?;
+        if let Some(error) = response.get("error") {
+            return Err(RpcError::from_object(
+                method,
+                &self.url,
+                min_context_slot,
+                error,
+            ));
+        }
+        let result = response
+            .get_mut("result")
+            .map(serde_json::Value::take)
+            .unwrap_or_default();
+        serde_json::from_value(result).map_err(|err| RpcError::DecodeError {
+            method: method.to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    // post_batch sends a JSON-RPC batch request and returns the result or error of each call,
+    // in the order of the ids of the calls (0..len)
+    async fn post_batch(
+        &self,
+        method: &str,
+        body: &serde_json::Value,
+        len: usize,
+    ) -> Result<Vec<Result<serde_json::Value, RpcError>>, RpcError> {
+        let response = self.post(method, body).await?;
+        let items = match response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|err| RpcError::from_http(method, &self.url, err))?
+        {
+            serde_json::Value::Array(items) => items,
+            // servers that reject a batch answer with a single error object
+            other => {
+                return Err(match other.get("error") {
+                    Some(error) => RpcError::from_object(method, &self.url, None, error),
+                    None => RpcError::DecodeError {
+                        method: method.to_string(),
+                        message: "batch response is not an array".to_string(),
+                    },
+                })
+            }
+        };
+        let mut results: Vec<Option<Result<serde_json::Value, RpcError>>> = vec![None; len];
+        for mut item in items {
+            let Some(id) = item.get("id").and_then(|id| id.as_u64()) else {
+                continue;
+            };
+            let Some(slot) = results.get_mut(id as usize) else {
+                continue;
+            };
+            *slot = Some(match item.get("error") {
+                Some(error) => Err(RpcError::from_object(method, &self.url, None, error)),
+                None => Ok(item
+                    .get_mut("result")
+                    .map(serde_json::Value::take)
+                    .unwrap_or_default()),
+            });
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|| {
+                    Err(RpcError::TransportError {
+                        method: method.to_string(),
+                        endpoint: self.url.clone(),
+                        message: "no response for the call in the batch".to_string(),
+                    })
+                })
+            })
+            .collect())
+    }
+
+    // probe checks an unhealthy endpoint with get_slot and reinstates it on success
+    async fn probe(self: Arc<Self>) {
+        if self.probing.swap(true, Ordering::AcqRel) {
+            return;
         }
+        let _permit = self.acquire(1).await;
+        match self
+            .call::<u64>("get_slot", None, RpcRequest::GetSlot, json!([]))
+            .await
+        {
+            Ok(slot) => {
+                println!(
+                    "[rpc/probe] Endpoint {} answered get_slot with {}",
+                    self.url, slot
+                );
+                self.reinstate();
+            }
+            Err(e) => {
+                eprintln!(
+                    "[rpc/probe] Endpoint {} is still unhealthy: {}",
+                    self.url, e
+                );
+            }
+        }
+        self.probing.store(false, Ordering::Release);
     }
 }
 
-// RpcClientWrapper is a wrapper around RpcClient that allows for multiple servers
-// and abstracts the api for the user
+// RpcClientWrapper is a JSON-RPC client that allows for multiple servers
+// and abstracts the api for the user.
+// Requests are distributed over the healthy endpoints by weight and fail over to the
+// other endpoints on transport errors, unhealthy endpoints are probed before they are reinstated.
 pub struct RpcClientWrapper {
-    endpoint: EndPoint,
+    endpoints: Vec<Arc<EndPoint>>,
+    next: AtomicUsize,
+    retry: RetryPolicy,
+    // limiter applies to all requests, method_limiters to the requests of a method
+    limiter: Option<Limiter>,
+    method_limiters: HashMap<String, Limiter>,
+    max_batch_size: usize,
+    tx_encoding: UiTransactionEncoding,
 }
 
 impl RpcClientWrapper {
     pub fn new(url: String) -> Self {
         Self {
-            endpoint: EndPoint::new(url),
+            endpoints: vec![Arc::new(EndPoint::new(EndpointConfig::new(url, 1)))],
+            next: AtomicUsize::new(0),
+            retry: RetryPolicy::default(),
+            limiter: None,
+            method_limiters: HashMap::new(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            tx_encoding: UiTransactionEncoding::Json,
+        }
+    }
+
+    // with_endpoints creates a wrapper that balances the requests over the given endpoints
+    pub fn with_endpoints(endpoints: Vec<EndpointConfig>) -> Result<Self, RpcError> {
+        if endpoints.is_empty() {
+            return Err(RpcError::ConfigError("no rpc endpoint".to_string()));
+        }
+        if endpoints.iter().all(|endpoint| endpoint.weight == 0) {
+            return Err(RpcError::ConfigError(
+                "at least one rpc endpoint needs a weight > 0".to_string(),
+            ));
         }
+        Ok(Self {
+            endpoints: endpoints
+                .into_iter()
+                .map(|endpoint| Arc::new(EndPoint::new(endpoint)))
+                .collect(),
+            next: AtomicUsize::new(0),
+            retry: RetryPolicy::default(),
+            limiter: None,
+            method_limiters: HashMap::new(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            tx_encoding: UiTransactionEncoding::Json,
+        })
+    }
+
+    // with_retry_policy sets the backoff and the retry budgets of the requests
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // with_max_batch_size sets the number of calls packed into a batch request, 1 disables batching
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    // with_tx_encoding sets the encoding txs are fetched with, e.g. base64 responses are smaller
+    // than json and are decoded locally. With jsonParsed the node replaces the instructions of the
+    // programs it knows (system, SPL token, ...) by their parsed form, they are indexed without
+    // accounts and data.
+    pub fn with_tx_encoding(mut self, tx_encoding: UiTransactionEncoding) -> Self {
+        self.tx_encoding = tx_encoding;
+        self
+    }
+
+    // with_rate_limit limits all requests, whatever their method and endpoint
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.limiter =
+            (!rate_limit.is_unlimited()).then(|| Limiter::new("global".to_string(), rate_limit));
+        self
+    }
+
+    // with_method_rate_limit limits the requests of a method (get_slot, get_sigs_for_addr, get_tx, send)
+    pub fn with_method_rate_limit(mut self, method: &str, rate_limit: RateLimit) -> Self {
+        if rate_limit.is_unlimited() {
+            self.method_limiters.remove(method);
+        } else {
+            self.method_limiters.insert(
+                method.to_string(),
+                Limiter::new(format!("method:{}", method), rate_limit),
+            );
+        }
+        self
+    }
+
+    // rate_limit_stats returns the time spent waiting for each configured limit
+    pub fn rate_limit_stats(&self) -> Vec<RateLimitStats> {
+        self.limiter
+            .iter()
+            .chain(self.method_limiters.values())
+            .chain(self.endpoints.iter().filter_map(|e| e.limiter.as_ref()))
+            .map(|limiter| limiter.stats())
+            .collect()
+    }
+
+    // endpoint_stats returns the health of all endpoints
+    pub fn endpoint_stats(&self) -> Vec<EndpointStats> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.stats.lock().unwrap().clone())
+            .collect()
+    }
+
+    // route returns the endpoints in the order they should be tried: a healthy endpoint picked
+    // by weight, then the other healthy endpoints by latency, then the unhealthy ones
+    fn route(&self) -> Vec<Arc<EndPoint>> {
+        for endpoint in self.endpoints.iter() {
+            if endpoint.should_probe() {
+                tokio::spawn(endpoint.clone().probe());
+            }
+        }
+        let (mut healthy, mut unhealthy): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter()
+            .cloned()
+            .partition(|endpoint| endpoint.is_healthy());
+        // the weighted pick is done in config order so the share of each endpoint is stable
+        let total_weight: usize = healthy
+            .iter()
+            .map(|endpoint| endpoint.weight as usize)
+            .sum();
+        let first = if total_weight > 0 {
+            let mut pick = self.next.fetch_add(1, Ordering::Relaxed) % total_weight;
+            healthy.iter().position(|endpoint| {
+                let weight = endpoint.weight as usize;
+                if pick < weight {
+                    return true;
+                }
+                pick -= weight;
+                false
+            })
+        } else {
+            None
+        };
+        let first = first.map(|i| healthy.remove(i));
+        healthy.sort_by(|a, b| a.latency_ms().total_cmp(&b.latency_ms()));
+        unhealthy.sort_by(|a, b| a.latency_ms().total_cmp(&b.latency_ms()));
+        if let Some(first) = first {
+            healthy.insert(0, first);
+        }
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    // request runs a call until it succeeds or the retry budget of the method is spent,
+    // every attempt is routed and fails over to the other endpoints
+    async fn request<T, F>(&self, method: &str, cost: u32, call: F) -> Result<Served<T>, RpcError>
+    where
+        F: for<'c> Fn(&'c EndPoint) -> BoxFuture<'c, Result<T, RpcError>>,
+    {
+        let retries = Retries::new(self.retry.budget(method));
+        self.request_with(method, cost, &retries, None, call).await
+    }
+
+    // request_with runs a call like request, the retries are taken from a budget that may be
+    // shared with other requests. A preferred endpoint is tried first while it is healthy.
+    async fn request_with<T, F>(
+        &self,
+        method: &str,
+        cost: u32,
+        retries: &Retries,
+        prefer: Option<&str>,
+        call: F,
+    ) -> Result<Served<T>, RpcError>
+    where
+        F: for<'c> Fn(&'c EndPoint) -> BoxFuture<'c, Result<T, RpcError>>,
+    {
+        loop {
+            match self.attempt(method, cost, prefer, &call).await {
+                Ok(served) => return Ok(served),
+                Err(err) if err.is_retryable() => {
+                    let Some(retry) = retries.take() else {
+                        return Err(err);
+                    };
+                    let delay = self.retry.backoff(retry, err.retry_after());
+                    eprintln!(
+                        "[rpc/{}] Attempt failed, retry {}/{} in {:?}: {}",
+                        method,
+                        retry + 1,
+                        retries.budget().max_attempts.saturating_sub(1),
+                        delay,
+                        err
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // attempt runs a call on the endpoints returned by route until one of them answers
+    async fn attempt<T, F>(
+        &self,
+        method: &str,
+        cost: u32,
+        prefer: Option<&str>,
+        call: &F,
+    ) -> Result<Served<T>, RpcError>
+    where
+        F: for<'c> Fn(&'c EndPoint) -> BoxFuture<'c, Result<T, RpcError>>,
+    {
+        // the permits are held until the request is answered
+        let _global = match &self.limiter {
+            Some(limiter) => Some(limiter.acquire(cost).await),
+            None => None,
+        };
+        let _method = match self.method_limiters.get(method) {
+            Some(limiter) => Some(limiter.acquire(cost).await),
+            None => None,
+        };
+        let mut endpoints = self.route();
+        if let Some(i) = endpoints
+            .iter()
+            .position(|endpoint| Some(endpoint.url.as_str()) == prefer && endpoint.is_healthy())
+        {
+            let preferred = endpoints.remove(i);
+            endpoints.insert(0, preferred);
+        }
+        let mut failed = Vec::new();
+        let mut last_err = None;
+        for endpoint in endpoints {
+            let _endpoint = endpoint.acquire(cost).await;
+            let start = Instant::now();
+            match call(&endpoint).await {
+                Ok(value) => {
+                    let latency = start.elapsed();
+                    endpoint.record_success(latency);
+                    if !failed.is_empty() {
+                        println!(
+                            "[rpc/{}] Served by {} after failing over from {}",
+                            method,
+                            endpoint.url,
+                            failed.join(", ")
+                        );
+                    }
+                    return Ok(Served {
+                        value,
+                        endpoint: endpoint.url.clone(),
+                        latency,
+                    });
+                }
+                Err(err) if err.should_failover() => {
+                    endpoint.record_failure();
+                    eprintln!(
+                        "[rpc/{}] Endpoint {} failed, trying next endpoint: {}",
+                        method, endpoint.url, err
+                    );
+                    failed.push(endpoint.url.clone());
+                    last_err = Some(err);
+                }
+                // the node answered, the error would be the same on the other endpoints
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| RpcError::ConfigError("no rpc endpoint".to_string())))
     }
 
     pub async fn get_slot(
         &self,
         commitment_config: Option<CommitmentConfig>,
     ) -> Result<u64, RpcError> {
-        match self
-            .endpoint
-            .client
-            .get_slot_with_commitment(commitment_config.unwrap_or(CommitmentConfig::finalized()))
+        self.get_slot_served(commitment_config)
             .await
-        {
-            Ok(slot) => Ok(slot),
-            Err(err) => Err(RpcError::GetSlotError(err.to_string())),
-        }
+            .map(Served::into_inner)
+    }
+
+    // get_slot_served is get_slot, together with the endpoint that served it
+    pub async fn get_slot_served(
+        &self,
+        commitment_config: Option<CommitmentConfig>,
+    ) -> Result<Served<u64>, RpcError> {
+        let commitment_cfg = commitment_config.unwrap_or(CommitmentConfig::finalized());
+        self.request("get_slot", 1, |endpoint| {
+            async move {
+                endpoint
+                    .call(
+                        "get_slot",
+                        None,
+                        RpcRequest::GetSlot,
+                        json!([commitment_cfg]),
+                    )
+                    .await
+            }
+            .boxed()
+        })
+        .await
     }
 
     pub async fn get_sigs_for_addr(
@@ -73,6 +812,28 @@ impl RpcClientWrapper {
         until: Option<Signature>,
         before: Option<Signature>,
     ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, RpcError> {
+        self.get_sigs_for_addr_served(
+            pk,
+            min_context_slot,
+            limit,
+            commitment_config,
+            until,
+            before,
+        )
+        .await
+        .map(Served::into_inner)
+    }
+
+    // get_sigs_for_addr_served is get_sigs_for_addr, together with the endpoint that served it
+    pub async fn get_sigs_for_addr_served(
+        &self,
+        pk: &Pubkey,
+        min_context_slot: u64,
+        limit: usize,
+        commitment_config: Option<CommitmentConfig>,
+        until: Option<Signature>,
+        before: Option<Signature>,
+    ) -> Result<Served<Vec<RpcConfirmedTransactionStatusWithSignature>>, RpcError> {
         let commitment_cfg = commitment_config.unwrap_or(CommitmentConfig::finalized());
         let config = RpcSignaturesForAddressConfig {
             before: before.map(|signature| signature.to_string()),
@@ -81,21 +842,22 @@ impl RpcClientWrapper {
             commitment: Some(commitment_cfg),
             min_context_slot: Some(min_context_slot),
         };
-        match self
-            .endpoint
-            .client
-            .send(
-                RpcRequest::GetSignaturesForAddress,
-                json!([pk.to_string(), config]),
-            )
-            .await
-        {
-            Ok(sigs) => Ok(sigs),
-            Err(err) => Err(RpcError::GetSigsForAddrError(
-                pk.to_string(),
-                err.to_string(),
-            )),
-        }
+        let params = json!([pk.to_string(), config]);
+        self.request("get_sigs_for_addr", 1, |endpoint| {
+            let params = params.clone();
+            async move {
+                endpoint
+                    .call(
+                        "get_sigs_for_addr",
+                        Some(min_context_slot),
+                        RpcRequest::GetSignaturesForAddress,
+                        params,
+                    )
+                    .await
+            }
+            .boxed()
+        })
+        .await
     }
 
     pub async fn get_tx(
@@ -103,34 +865,368 @@ impl RpcClientWrapper {
         sig: &Signature,
         commitment_config: Option<CommitmentConfig>,
     ) -> Result<EncodedConfirmedTransactionWithStatusMeta, RpcError> {
+        self.get_tx_served(sig, commitment_config)
+            .await
+            .map(Served::into_inner)
+    }
+
+    // get_tx_served is get_tx, together with the endpoint that served it
+    pub async fn get_tx_served(
+        &self,
+        sig: &Signature,
+        commitment_config: Option<CommitmentConfig>,
+    ) -> Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError> {
         let commitment_cfg = commitment_config.unwrap_or(CommitmentConfig::finalized());
-        let sig_str = sig.to_string();
-        match self
-            .endpoint
-            .client
-            .get_transaction_with_config(
-                sig,
-                RpcTransactionConfig {
-                    encoding: Some(UiTransactionEncoding::Json),
-                    commitment: Some(commitment_cfg),
-                    max_supported_transaction_version: None,
-                },
-            )
+        let retries = Retries::new(self.retry.budget("get_tx"));
+        self.fetch_tx(sig, commitment_cfg, &retries, self.retry.not_found_budget())
             .await
-        {
-            Ok(tx) => Ok(tx),
-            Err(err) => Err(RpcError::GetTxError(sig_str, err.to_string())),
+    }
+
+    // get_tx_within fetches a tx like get_tx, its retries are limited by the given budget instead
+    // of the budget of get_tx, e.g. for callers that can't wait long
+    pub async fn get_tx_within(
+        &self,
+        sig: &Signature,
+        commitment_config: Option<CommitmentConfig>,
+        budget: RetryBudget,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, RpcError> {
+        self.get_tx_within_served(sig, commitment_config, budget)
+            .await
+            .map(Served::into_inner)
+    }
+
+    // get_tx_within_served is get_tx_within, together with the endpoint that served it
+    pub async fn get_tx_within_served(
+        &self,
+        sig: &Signature,
+        commitment_config: Option<CommitmentConfig>,
+        budget: RetryBudget,
+    ) -> Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError> {
+        let commitment_cfg = commitment_config.unwrap_or(CommitmentConfig::finalized());
+        self.fetch_tx(sig, commitment_cfg, &Retries::new(budget), budget)
+            .await
+    }
+
+    // fetch_tx fetches a tx with single requests, a tx that is not found is asked for again within
+    // the not_found budget
+    async fn fetch_tx(
+        &self,
+        sig: &Signature,
+        commitment_cfg: CommitmentConfig,
+        retries: &Retries,
+        not_found: RetryBudget,
+    ) -> Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError> {
+        self.find_tx(sig, commitment_cfg, retries, Retries::new(not_found), None)
+            .await
+    }
+
+    // find_tx fetches a tx until an endpoint returns it. A confirmed tx can be listed by a node
+    // before its transaction is queryable, so a tx that is not found is asked for again on the same
+    // endpoint. These retries are taken from not_found, they don't count against the health of the
+    // endpoint nor the retries shared with other requests. An endpoint given as not_found_on has
+    // already answered that it doesn't know the tx.
+    async fn find_tx(
+        &self,
+        sig: &Signature,
+        commitment_cfg: CommitmentConfig,
+        retries: &Retries,
+        not_found: Retries,
+        mut not_found_on: Option<String>,
+    ) -> Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError> {
+        let config = tx_config(commitment_cfg, self.tx_encoding);
+        let params = json!([sig.to_string(), config]);
+        loop {
+            if let Some(endpoint) = &not_found_on {
+                let Some(retry) = not_found.take() else {
+                    return Err(RpcError::TxNotFound(sig.to_string()));
+                };
+                let delay = self.retry.backoff(retry, None);
+                println!(
+                    "[rpc/get_tx] Tx {} not found on {}, asking again in {:?}",
+                    sig, endpoint, delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            let served = self
+                .request_with("get_tx", 1, retries, not_found_on.as_deref(), |endpoint| {
+                    let params = params.clone();
+                    async move {
+                        endpoint
+                            .call::<Option<EncodedConfirmedTransactionWithStatusMeta>>(
+                                "get_tx",
+                                None,
+                                RpcRequest::GetTransaction,
+                                params,
+                            )
+                            .await
+                    }
+                    .boxed()
+                })
+                .await?;
+            match served.value {
+                Some(tx) => {
+                    return Ok(Served {
+                        value: tx,
+                        endpoint: served.endpoint,
+                        latency: served.latency,
+                    })
+                }
+                None => not_found_on = Some(served.endpoint),
+            }
         }
     }
 
+    // get_txs fetches txs with JSON-RPC batch requests of up to max_batch_size calls,
+    // the results are in the order of the signatures.
+    // Each call of a batch counts against the rate limits of get_tx. A batch that is rejected as a whole
+    // is split in halves that are sent concurrently, calls that failed with a retryable error are sent
+    // again in a smaller batch. All the requests made for a batch share the retry budget of get_tx.
+    // Txs that are not found are asked for again on the endpoint that answered, within the not found budget.
+    pub async fn get_txs(
+        &self,
+        sigs: &[Signature],
+        commitment_config: Option<CommitmentConfig>,
+    ) -> Vec<Result<EncodedConfirmedTransactionWithStatusMeta, RpcError>> {
+        self.get_txs_served(sigs, commitment_config)
+            .await
+            .into_iter()
+            .map(|result| result.map(Served::into_inner))
+            .collect()
+    }
+
+    // get_txs_served is get_txs, together with the endpoint that served each tx
+    pub async fn get_txs_served(
+        &self,
+        sigs: &[Signature],
+        commitment_config: Option<CommitmentConfig>,
+    ) -> Vec<Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError>> {
+        let commitment_cfg = commitment_config.unwrap_or(CommitmentConfig::finalized());
+        let mut results = Vec::with_capacity(sigs.len());
+        for chunk in sigs.chunks(self.max_batch_size) {
+            let retries = Retries::new(self.retry.budget("get_tx"));
+            results.extend(
+                self.get_tx_batch(chunk.to_vec(), commitment_cfg, &retries)
+                    .await,
+            );
+        }
+        results
+    }
+
+    fn get_tx_batch<'a>(
+        &'a self,
+        sigs: Vec<Signature>,
+        commitment_cfg: CommitmentConfig,
+        retries: &'a Retries,
+    ) -> BoxFuture<'a, Vec<Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError>>>
+    {
+        async move {
+            if sigs.len() == 1 {
+                return vec![
+                    self.fetch_tx(
+                        &sigs[0],
+                        commitment_cfg,
+                        retries,
+                        self.retry.not_found_budget(),
+                    )
+                    .await,
+                ];
+            }
+            let config = tx_config(commitment_cfg, self.tx_encoding);
+            let body = Arc::new(serde_json::Value::Array(
+                sigs.iter()
+                    .enumerate()
+                    .map(|(id, sig)| {
+                        json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "method": RpcRequest::GetTransaction.to_string(),
+                            "params": [sig.to_string(), config],
+                        })
+                    })
+                    .collect(),
+            ));
+            let len = sigs.len();
+            let served = match self
+                // batches share the retry budget and rate limits of get_tx
+                .request_with("get_tx", len as u32, retries, None, |endpoint| {
+                    let body = body.clone();
+                    async move { endpoint.post_batch("get_tx", &body, len).await }.boxed()
+                })
+                .await
+            {
+                Ok(served) => served,
+                // transient errors already spent their retries, splitting would only multiply them
+                Err(err) if err.is_retryable() || retries.expired() => {
+                    return sigs.iter().map(|_| Err(err.clone())).collect();
+                }
+                Err(err) => {
+                    eprintln!(
+                        "[rpc/get_txs] Batch of {} txs failed, splitting it: {}",
+                        len, err
+                    );
+                    return self.split_tx_batch(sigs, commitment_cfg, retries).await;
+                }
+            };
+            let mut results = Vec::with_capacity(len);
+            let mut failed = Vec::new();
+            let mut not_found = Vec::new();
+            for (i, result) in served.value.into_iter().enumerate() {
+                let result = result.and_then(|value| {
+                    serde_json::from_value::<Option<EncodedConfirmedTransactionWithStatusMeta>>(
+                        value,
+                    )
+                    .map_err(|err| RpcError::DecodeError {
+                        method: "get_tx".to_string(),
+                        message: err.to_string(),
+                    })
+                });
+                match result {
+                    Ok(Some(tx)) => results.push(Some(Ok(Served {
+                        value: tx,
+                        endpoint: served.endpoint.clone(),
+                        latency: served.latency,
+                    }))),
+                    Ok(None) => {
+                        not_found.push(i);
+                        results.push(None);
+                    }
+                    Err(err) if err.is_retryable() => {
+                        failed.push((i, err));
+                        results.push(None);
+                    }
+                    Err(err) => results.push(Some(Err(err))),
+                }
+            }
+            // the txs the endpoint doesn't know yet are asked for again on the same endpoint
+            let lookups = futures_util::future::join_all(not_found.iter().map(|i| {
+                self.find_tx(
+                    &sigs[*i],
+                    commitment_cfg,
+                    retries,
+                    Retries::new(self.retry.not_found_budget()),
+                    Some(served.endpoint.clone()),
+                )
+            }));
+            let (found, retried) = futures_util::future::join(
+                lookups,
+                self.retry_failed_calls(&sigs, failed, commitment_cfg, retries),
+            )
+            .await;
+            for (i, result) in not_found.into_iter().zip(found) {
+                results[i] = Some(result);
+            }
+            for (i, result) in retried {
+                results[i] = Some(result);
+            }
+            results.into_iter().flatten().collect()
+        }
+        .boxed()
+    }
+
+    // retry_failed_calls sends the calls of a batch that failed with a retryable error again,
+    // it is a retry of the batch. It returns the results with the index of their call.
+    async fn retry_failed_calls(
+        &self,
+        sigs: &[Signature],
+        failed: Vec<(usize, RpcError)>,
+        commitment_cfg: CommitmentConfig,
+        retries: &Retries,
+    ) -> Vec<(
+        usize,
+        Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError>,
+    )> {
+        if failed.is_empty() {
+            return Vec::new();
+        }
+        let Some(retry) = retries.take() else {
+            return failed.into_iter().map(|(i, err)| (i, Err(err))).collect();
+        };
+        let (failed, errs): (Vec<_>, Vec<_>) = failed.into_iter().unzip();
+        let delay = self
+            .retry
+            .backoff(retry, errs.iter().find_map(RpcError::retry_after));
+        let failed_sigs = failed.iter().map(|i| sigs[*i]).collect::<Vec<_>>();
+        println!(
+            "[rpc/get_txs] {} of {} calls of the batch failed, sending them again in {:?}",
+            failed.len(),
+            sigs.len(),
+            delay
+        );
+        tokio::time::sleep(delay).await;
+        // the batch gets smaller on every round, so the retries end with single requests
+        let retried = if failed_sigs.len() < sigs.len() {
+            self.get_tx_batch(failed_sigs, commitment_cfg, retries)
+                .await
+        } else {
+            self.split_tx_batch(failed_sigs, commitment_cfg, retries)
+                .await
+        };
+        failed.into_iter().zip(retried).collect()
+    }
+
+    // split_tx_batch sends the halves of a batch concurrently, they wait for the rate limits
+    // like any other request
+    async fn split_tx_batch(
+        &self,
+        mut sigs: Vec<Signature>,
+        commitment_cfg: CommitmentConfig,
+        retries: &Retries,
+    ) -> Vec<Result<Served<EncodedConfirmedTransactionWithStatusMeta>, RpcError>> {
+        let right = sigs.split_off(sigs.len() / 2);
+        let (mut results, right) = futures_util::future::join(
+            self.get_tx_batch(sigs, commitment_cfg, retries),
+            self.get_tx_batch(right, commitment_cfg, retries),
+        )
+        .await;
+        results.extend(right);
+        results
+    }
+
     pub async fn send(
         &self,
         req: RpcRequest,
         params: serde_json::Value,
     ) -> Result<serde_json::Value, RpcError> {
-        match self.endpoint.client.send(req, params.clone()).await {
-            Ok(res) => Ok(res),
-            Err(err) => Err(RpcError::SendError(req.to_string(), err.to_string())),
-        }
+        self.send_served(req, params).await.map(Served::into_inner)
+    }
+
+    // send_served is send, together with the endpoint that served it
+    pub async fn send_served(
+        &self,
+        req: RpcRequest,
+        params: serde_json::Value,
+    ) -> Result<Served<serde_json::Value>, RpcError> {
+        self.request("send", 1, |endpoint| {
+            let params = params.clone();
+            async move {
+                endpoint
+                    .call(req.to_string().as_str(), None, req, params)
+                    .await
+            }
+            .boxed()
+        })
+        .await
+    }
+}
+
+// retry_after parses the Retry-After header of a rate limited response, in seconds
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+// tx_config is the config of getTransaction requests. Nodes reject versioned txs unless the
+// highest version the client supports is given, 0 also returns legacy txs.
+fn tx_config(
+    commitment_cfg: CommitmentConfig,
+    encoding: UiTransactionEncoding,
+) -> RpcTransactionConfig {
+    RpcTransactionConfig {
+        encoding: Some(encoding),
+        commitment: Some(commitment_cfg),
+        max_supported_transaction_version: Some(0),
     }
 }
@@ -0,0 +1,225 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use solana_sdk::commitment_config::CommitmentConfig;
+use thiserror::Error;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+use crate::checkpoint::Checkpoint;
+use crate::log_events::TxRecord;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum SinkError {
+    #[error("failed to open sink {0}: {1}")]
+    OpenError(String, String),
+    #[error("failed to write batch: {0}")]
+    WriteError(String),
+}
+
+// CommitStatus is the commitment level the transactions of a batch were read with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStatus {
+    Confirmed,
+    Finalized,
+}
+
+impl CommitStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommitStatus::Confirmed => "confirmed",
+            CommitStatus::Finalized => "finalized",
+        }
+    }
+
+    pub fn commitment_config(&self) -> CommitmentConfig {
+        match self {
+            CommitStatus::Confirmed => CommitmentConfig::confirmed(),
+            CommitStatus::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+// EventBatch is a batch of transactions that were loaded for a program, ordered by slot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventBatch {
+    pub commitment: CommitStatus,
+    pub program_addr: String,
+    pub txs: Vec<TxRecord>,
+    // checkpoint is the state of the program cursors once the batch is written
+    pub checkpoint: Option<Checkpoint>,
+}
+
+impl EventBatch {
+    pub fn new(commitment: CommitStatus, program_addr: String, txs: Vec<TxRecord>) -> Self {
+        Self {
+            commitment,
+            program_addr,
+            txs,
+            checkpoint: None,
+        }
+    }
+
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+}
+
+// RETRACTED is the commitment recorded by the sinks for retracted transactions
+pub const RETRACTED: &str = "retracted";
+
+// RetractedTx is a transaction that was written as confirmed but was not finalized
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RetractedTx {
+    pub slot: u64,
+    pub signature: String,
+}
+
+// Retraction is a set of confirmed transactions of a program that were dropped,
+// e.g. because their block was on a fork that was abandoned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Retraction {
+    pub program_addr: String,
+    pub txs: Vec<RetractedTx>,
+}
+
+// EventSink is the storage for the data produced by the EventLoader.
+// The loader only advances its cursors once write_batch returns successfully,
+// which means that a batch might be written again after a failure.
+// A confirmed tx is promoted when it is written again as finalized, and retracted
+// if it doesn't finalize; finalized txs are never retracted or downgraded.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError>;
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError>;
+}
+
+// StdoutSink prints the batches to stdout
+#[derive(Default)]
+pub struct StdoutSink {}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[async_trait]
+impl EventSink for StdoutSink {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError> {
+        let commitment = batch.commitment.as_str();
+        for tx in batch.txs.iter() {
+            println!(
+                "[stdout_sink] {} tx (slot={}, sig={}, instructions={}, logs={}, events={}, incomplete={})",
+                commitment,
+                tx.slot,
+                tx.signature,
+                tx.instructions.len(),
+                tx.logs.len(),
+                tx.events.len(),
+                tx.incomplete
+            );
+            tx.instructions.iter().for_each(|ix| {
+                println!("[stdout_sink] {} instruction: {:?}", commitment, ix);
+            });
+            tx.logs.iter().for_each(|log| {
+                println!("[stdout_sink] {} log: {:?}", commitment, log);
+            });
+            tx.events.iter().for_each(|event| {
+                println!("[stdout_sink] {} event: {:?}", commitment, event);
+            });
+            tx.program_instructions.iter().for_each(|ix| {
+                println!("[stdout_sink] {} program instruction: {:?}", commitment, ix);
+            });
+            if let Some(failure) = &tx.failure {
+                println!("[stdout_sink] {} failure: {:?}", commitment, failure);
+            }
+            tx.balance_changes.iter().for_each(|change| {
+                println!("[stdout_sink] {} balance change: {:?}", commitment, change);
+            });
+        }
+        Ok(())
+    }
+
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError> {
+        for tx in retraction.txs.iter() {
+            println!(
+                "[stdout_sink] {} tx (slot={}, sig={}, addr={})",
+                RETRACTED, tx.slot, tx.signature, retraction.program_addr
+            );
+        }
+        Ok(())
+    }
+}
+
+// FileSink appends the transactions as json lines to a file
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+#[derive(Serialize)]
+struct FileLine<'a> {
+    commitment: CommitStatus,
+    tx: &'a TxRecord,
+}
+
+#[derive(Serialize)]
+struct RetractionLine<'a> {
+    commitment: &'static str,
+    tx: &'a RetractedTx,
+}
+
+impl FileSink {
+    pub async fn open(path: &str) -> Result<Self, SinkError> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| SinkError::OpenError(path.to_string(), e.to_string()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    async fn append(&self, buf: &[u8]) -> Result<(), SinkError> {
+        let mut file = self.file.lock().await;
+        file.write_all(buf)
+            .await
+            .map_err(|e| SinkError::WriteError(e.to_string()))?;
+        file.sync_data()
+            .await
+            .map_err(|e| SinkError::WriteError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError> {
+        let mut buf = Vec::new();
+        for tx in batch.txs.iter() {
+            let line = FileLine {
+                commitment: batch.commitment,
+                tx,
+            };
+            serde_json::to_writer(&mut buf, &line)
+                .map_err(|e| SinkError::WriteError(e.to_string()))?;
+            buf.push(b'\n');
+        }
+        self.append(&buf).await
+    }
+
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError> {
+        let mut buf = Vec::new();
+        for tx in retraction.txs.iter() {
+            let line = RetractionLine {
+                commitment: RETRACTED,
+                tx,
+            };
+            serde_json::to_writer(&mut buf, &line)
+                .map_err(|e| SinkError::WriteError(e.to_string()))?;
+            buf.push(b'\n');
+        }
+        self.append(&buf).await
+    }
+}
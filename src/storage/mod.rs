@@ -0,0 +1,60 @@
+// storage contains the first-party EventSink implementations backed by a database
+pub mod postgres;
+pub mod sqlite;
+
+use crate::log_events::{EventPayload, InstructionPayload};
+
+// DELETED_ON_RETRACT are the tables without a commitment column, the rows of a retracted
+// transaction are deleted from them instead of being marked
+const DELETED_ON_RETRACT: &[&str] = &["instructions", "logs", "tx_failures", "balance_changes"];
+
+// PayloadColumns is the flattened representation of a decoded payload
+// as stored in the events and program_instructions tables
+struct PayloadColumns {
+    name: Option<String>,
+    data: Option<serde_json::Value>,
+    raw: Option<String>,
+    decode_error: Option<String>,
+}
+
+impl PayloadColumns {
+    fn event(payload: &EventPayload) -> Self {
+        match payload {
+            EventPayload::Decoded(value) => Self {
+                name: value
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .map(str::to_string),
+                data: Some(value.clone()),
+                raw: None,
+                decode_error: None,
+            },
+            EventPayload::Raw { data, error } => Self {
+                name: None,
+                data: None,
+                raw: Some(data.join(" ")),
+                decode_error: Some(error.clone()),
+            },
+        }
+    }
+
+    fn instruction(payload: &InstructionPayload) -> Self {
+        match payload {
+            InstructionPayload::Decoded(value) => Self {
+                name: value
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .map(str::to_string),
+                data: Some(value.clone()),
+                raw: None,
+                decode_error: None,
+            },
+            InstructionPayload::Raw { data, error } => Self {
+                name: None,
+                data: None,
+                raw: data.clone(),
+                decode_error: Some(error.clone()),
+            },
+        }
+    }
+}
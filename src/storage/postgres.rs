@@ -0,0 +1,583 @@
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, GenericClient, NoTls};
+
+use super::{PayloadColumns, DELETED_ON_RETRACT};
+use crate::checkpoint::{Checkpoint, CheckpointError, CheckpointStore};
+use crate::sink::{EventBatch, EventSink, Retraction, SinkError, RETRACTED};
+
+// MIGRATIONS are applied in order, applied versions are tracked in the schema_migrations table
+const MIGRATIONS: &[&str] = &[
+    // v1: transactions, logs and events
+    r#"
+    CREATE TABLE transactions (
+        signature TEXT PRIMARY KEY,
+        slot BIGINT NOT NULL,
+        block_time BIGINT,
+        fee BIGINT NOT NULL,
+        err TEXT,
+        commitment TEXT NOT NULL,
+        program_addr TEXT NOT NULL
+    );
+    CREATE INDEX idx_transactions_slot ON transactions (slot);
+    CREATE INDEX idx_transactions_program_addr ON transactions (program_addr);
+
+    CREATE TABLE logs (
+        signature TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        slot BIGINT NOT NULL,
+        program_id TEXT NOT NULL,
+        depth INTEGER NOT NULL,
+        log_type TEXT,
+        message TEXT NOT NULL,
+        PRIMARY KEY (signature, log_index)
+    );
+    CREATE INDEX idx_logs_slot ON logs (slot);
+    CREATE INDEX idx_logs_program_id ON logs (program_id);
+
+    CREATE TABLE events (
+        signature TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        slot BIGINT NOT NULL,
+        program_id TEXT NOT NULL,
+        name TEXT,
+        data JSONB,
+        raw TEXT,
+        decode_error TEXT,
+        PRIMARY KEY (signature, log_index)
+    );
+    CREATE INDEX idx_events_slot ON events (slot);
+    CREATE INDEX idx_events_program_id ON events (program_id);
+    CREATE INDEX idx_events_name ON events (name);
+    "#,
+    // v2: cursor checkpoints
+    r#"
+    CREATE TABLE checkpoints (
+        program_addr TEXT PRIMARY KEY,
+        head_slot BIGINT NOT NULL,
+        head_sig TEXT NOT NULL,
+        tail_slot BIGINT NOT NULL,
+        tail_sig TEXT NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    "#,
+    // v3: commitment of events, to promote and retract them together with their transaction
+    r#"
+    ALTER TABLE events ADD COLUMN commitment TEXT NOT NULL DEFAULT 'confirmed';
+    UPDATE events SET commitment = transactions.commitment
+        FROM transactions WHERE transactions.signature = events.signature;
+    CREATE INDEX idx_events_commitment ON events (commitment);
+    "#,
+    // v4: resolved account keys of transactions, including lookup table addresses
+    r#"
+    ALTER TABLE transactions ADD COLUMN accounts TEXT[] NOT NULL DEFAULT '{}';
+    "#,
+    // v5: top-level and inner instructions, data is base58
+    r#"
+    CREATE TABLE instructions (
+        signature TEXT NOT NULL,
+        ix_index INTEGER NOT NULL,
+        slot BIGINT NOT NULL,
+        program_id TEXT NOT NULL,
+        accounts TEXT[] NOT NULL,
+        data TEXT,
+        stack_height INTEGER NOT NULL,
+        parent_index INTEGER,
+        PRIMARY KEY (signature, ix_index)
+    );
+    CREATE INDEX idx_instructions_slot ON instructions (slot);
+    CREATE INDEX idx_instructions_program_id ON instructions (program_id);
+    "#,
+    // v6: instructions of tracked programs decoded with their IDL
+    r#"
+    CREATE TABLE program_instructions (
+        signature TEXT NOT NULL,
+        ix_index INTEGER NOT NULL,
+        slot BIGINT NOT NULL,
+        program_id TEXT NOT NULL,
+        name TEXT,
+        data JSONB,
+        raw TEXT,
+        decode_error TEXT,
+        commitment TEXT NOT NULL,
+        PRIMARY KEY (signature, ix_index)
+    );
+    CREATE INDEX idx_program_instructions_slot ON program_instructions (slot);
+    CREATE INDEX idx_program_instructions_program_id ON program_instructions (program_id);
+    CREATE INDEX idx_program_instructions_name ON program_instructions (name);
+    CREATE INDEX idx_program_instructions_commitment ON program_instructions (commitment);
+    "#,
+    // v7: instruction index of events emitted with emit_cpi!
+    r#"
+    ALTER TABLE events ADD COLUMN ix_index INTEGER;
+    "#,
+    // v8: structured errors of failed transactions
+    r#"
+    CREATE TABLE tx_failures (
+        signature TEXT PRIMARY KEY,
+        slot BIGINT NOT NULL,
+        kind TEXT NOT NULL,
+        instruction_index INTEGER,
+        instruction TEXT,
+        program_id TEXT,
+        error_code BIGINT,
+        error_name TEXT,
+        error_message TEXT,
+        account TEXT
+    );
+    CREATE INDEX idx_tx_failures_slot ON tx_failures (slot);
+    CREATE INDEX idx_tx_failures_program_id ON tx_failures (program_id);
+    CREATE INDEX idx_tx_failures_error_name ON tx_failures (error_name);
+    "#,
+    // v9: incomplete (truncated logs) flag and balance changes recovered for incomplete txs
+    r#"
+    ALTER TABLE transactions ADD COLUMN incomplete BOOLEAN NOT NULL DEFAULT FALSE;
+    CREATE TABLE balance_changes (
+        signature TEXT NOT NULL,
+        account TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        slot BIGINT NOT NULL,
+        mint TEXT,
+        owner TEXT,
+        pre NUMERIC(20, 0) NOT NULL,
+        post NUMERIC(20, 0) NOT NULL,
+        PRIMARY KEY (signature, account, kind)
+    );
+    CREATE INDEX idx_balance_changes_slot ON balance_changes (slot);
+    CREATE INDEX idx_balance_changes_account ON balance_changes (account);
+    "#,
+];
+
+// MIGRATIONS_LOCK is the advisory lock that serializes migrations of concurrent indexers
+const MIGRATIONS_LOCK: i64 = 0x736f6c5f69647872;
+
+// PostgresSink writes transactions, instructions, logs, events, decoded instructions, failures and balance changes into PostgreSQL.
+// Rows are keyed by signature (+ log index), all writes are upserts so re-writing
+// a batch after an error never duplicates rows.
+// It is also a CheckpointStore, the checkpoint of a batch is written in the same transaction as its events.
+pub struct PostgresSink {
+    client: Mutex<Client>,
+}
+
+impl PostgresSink {
+    // connect connects to the database and applies pending migrations
+    pub async fn connect(url: &str) -> Result<Self, SinkError> {
+        let (mut client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .map_err(|e| SinkError::OpenError(redact(url), e.to_string()))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("[postgres_sink] Connection error: {:?}", e);
+            }
+        });
+        migrate(&mut client)
+            .await
+            .map_err(|e| SinkError::OpenError(redact(url), e.to_string()))?;
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+
+    // client returns the underlying client, e.g. for querying the index
+    pub fn client(&self) -> &Mutex<Client> {
+        &self.client
+    }
+}
+
+// migrate applies the migrations that were not applied yet
+async fn migrate(client: &mut Client) -> Result<(), tokio_postgres::Error> {
+    let tx = client.transaction().await?;
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATIONS_LOCK])
+        .await?;
+    tx.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )
+    .await?;
+    let version: i32 = tx
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            &[],
+        )
+        .await?
+        .get(0);
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let version = (i + 1) as i32;
+        println!("[postgres_sink/migrate] Applying migration v{}", version);
+        tx.batch_execute(migration).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &[&version],
+        )
+        .await?;
+    }
+    tx.commit().await
+}
+
+async fn write_batch(client: &mut Client, batch: &EventBatch) -> Result<(), tokio_postgres::Error> {
+    let tx = client.transaction().await?;
+    let insert_tx = tx
+        .prepare(
+            "INSERT INTO transactions (signature, slot, block_time, fee, err, commitment, program_addr, accounts, incomplete)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (signature) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                block_time = EXCLUDED.block_time,
+                fee = EXCLUDED.fee,
+                err = EXCLUDED.err,
+                accounts = EXCLUDED.accounts,
+                incomplete = EXCLUDED.incomplete,
+                commitment = CASE WHEN transactions.commitment = 'finalized'
+                    THEN transactions.commitment ELSE EXCLUDED.commitment END",
+        )
+        .await?;
+    let insert_log = tx
+        .prepare(
+            "INSERT INTO logs (signature, log_index, slot, program_id, depth, log_type, message)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (signature, log_index) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                program_id = EXCLUDED.program_id,
+                depth = EXCLUDED.depth,
+                log_type = EXCLUDED.log_type,
+                message = EXCLUDED.message",
+        )
+        .await?;
+    let insert_ix = tx
+        .prepare(
+            "INSERT INTO instructions (signature, ix_index, slot, program_id, accounts, data, stack_height, parent_index)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (signature, ix_index) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                program_id = EXCLUDED.program_id,
+                accounts = EXCLUDED.accounts,
+                data = EXCLUDED.data,
+                stack_height = EXCLUDED.stack_height,
+                parent_index = EXCLUDED.parent_index",
+        )
+        .await?;
+    let insert_event = tx
+        .prepare(
+            "INSERT INTO events (signature, log_index, slot, program_id, name, data, raw, decode_error, commitment, ix_index)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (signature, log_index) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                ix_index = EXCLUDED.ix_index,
+                program_id = EXCLUDED.program_id,
+                name = EXCLUDED.name,
+                data = EXCLUDED.data,
+                raw = EXCLUDED.raw,
+                decode_error = EXCLUDED.decode_error,
+                commitment = CASE WHEN events.commitment = 'finalized'
+                    THEN events.commitment ELSE EXCLUDED.commitment END",
+        )
+        .await?;
+    let insert_program_ix = tx
+        .prepare(
+            "INSERT INTO program_instructions (signature, ix_index, slot, program_id, name, data, raw, decode_error, commitment)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (signature, ix_index) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                program_id = EXCLUDED.program_id,
+                name = EXCLUDED.name,
+                data = EXCLUDED.data,
+                raw = EXCLUDED.raw,
+                decode_error = EXCLUDED.decode_error,
+                commitment = CASE WHEN program_instructions.commitment = 'finalized'
+                    THEN program_instructions.commitment ELSE EXCLUDED.commitment END",
+        )
+        .await?;
+    let insert_failure = tx
+        .prepare(
+            "INSERT INTO tx_failures (signature, slot, kind, instruction_index, instruction, program_id, error_code, error_name, error_message, account)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (signature) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                kind = EXCLUDED.kind,
+                instruction_index = EXCLUDED.instruction_index,
+                instruction = EXCLUDED.instruction,
+                program_id = EXCLUDED.program_id,
+                error_code = EXCLUDED.error_code,
+                error_name = EXCLUDED.error_name,
+                error_message = EXCLUDED.error_message,
+                account = EXCLUDED.account",
+        )
+        .await?;
+    let insert_balance_change = tx
+        .prepare(
+            "INSERT INTO balance_changes (signature, account, kind, slot, mint, owner, pre, post)
+             VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC)
+             ON CONFLICT (signature, account, kind) DO UPDATE SET
+                slot = EXCLUDED.slot,
+                mint = EXCLUDED.mint,
+                owner = EXCLUDED.owner,
+                pre = EXCLUDED.pre,
+                post = EXCLUDED.post",
+        )
+        .await?;
+    for record in batch.txs.iter() {
+        let slot = record.slot as i64;
+        tx.execute(
+            &insert_tx,
+            &[
+                &record.signature,
+                &slot,
+                &record.block_time,
+                &(record.fee as i64),
+                &record.err,
+                &batch.commitment.as_str(),
+                &batch.program_addr,
+                &record.accounts,
+                &record.incomplete,
+            ],
+        )
+        .await?;
+        for ix in record.instructions.iter() {
+            tx.execute(
+                &insert_ix,
+                &[
+                    &record.signature,
+                    &(ix.index as i32),
+                    &slot,
+                    &ix.program_id,
+                    &ix.accounts,
+                    &ix.data,
+                    &(ix.stack_height as i32),
+                    &ix.parent_index.map(|index| index as i32),
+                ],
+            )
+            .await?;
+        }
+        for log in record.logs.iter() {
+            tx.execute(
+                &insert_log,
+                &[
+                    &record.signature,
+                    &(log.index as i32),
+                    &slot,
+                    &log.program_id,
+                    &(log.depth as i32),
+                    &log.log_type.map(|log_type| log_type.as_str()),
+                    &log.raw,
+                ],
+            )
+            .await?;
+        }
+        for event in record.events.iter() {
+            let columns = PayloadColumns::event(&event.payload);
+            tx.execute(
+                &insert_event,
+                &[
+                    &record.signature,
+                    &(event.log_index as i32),
+                    &slot,
+                    &event.program_id,
+                    &columns.name,
+                    &columns.data,
+                    &columns.raw,
+                    &columns.decode_error,
+                    &batch.commitment.as_str(),
+                    &event.ix_index.map(|index| index as i32),
+                ],
+            )
+            .await?;
+        }
+        for ix in record.program_instructions.iter() {
+            let columns = PayloadColumns::instruction(&ix.payload);
+            tx.execute(
+                &insert_program_ix,
+                &[
+                    &record.signature,
+                    &(ix.ix_index as i32),
+                    &slot,
+                    &ix.program_id,
+                    &columns.name,
+                    &columns.data,
+                    &columns.raw,
+                    &columns.decode_error,
+                    &batch.commitment.as_str(),
+                ],
+            )
+            .await?;
+        }
+        if let Some(failure) = &record.failure {
+            tx.execute(
+                &insert_failure,
+                &[
+                    &record.signature,
+                    &slot,
+                    &failure.kind.as_str(),
+                    &failure.instruction_index.map(i32::from),
+                    &failure.instruction,
+                    &failure.program_id,
+                    &failure.error_code.map(i64::from),
+                    &failure.error_name,
+                    &failure.error_message,
+                    &failure.account,
+                ],
+            )
+            .await?;
+        }
+        for change in record.balance_changes.iter() {
+            tx.execute(
+                &insert_balance_change,
+                &[
+                    &record.signature,
+                    &change.account,
+                    &change.kind(),
+                    &slot,
+                    &change.mint,
+                    &change.owner,
+                    &change.pre.to_string(),
+                    &change.post.to_string(),
+                ],
+            )
+            .await?;
+        }
+    }
+    if let Some(checkpoint) = &batch.checkpoint {
+        save_checkpoint(&tx, batch.program_addr.as_str(), checkpoint).await?;
+    }
+    tx.commit().await
+}
+
+// retract marks the confirmed transactions, events and decoded instructions as retracted and
+// deletes their instructions, logs, failures and balance changes, finalized ones are kept
+async fn retract(
+    client: &mut Client,
+    retraction: &Retraction,
+) -> Result<(), tokio_postgres::Error> {
+    let tx = client.transaction().await?;
+    // the rows are deleted first, while the transaction is still marked confirmed
+    let mut deletes = Vec::with_capacity(DELETED_ON_RETRACT.len());
+    for table in DELETED_ON_RETRACT {
+        let delete = tx
+            .prepare(&format!(
+                "DELETE FROM {table} WHERE signature = $1 AND EXISTS (
+                    SELECT 1 FROM transactions WHERE signature = $1 AND commitment = 'confirmed'
+                )"
+            ))
+            .await?;
+        deletes.push(delete);
+    }
+    let retract_tx = tx
+        .prepare(
+            "UPDATE transactions SET commitment = $2 WHERE signature = $1 AND commitment = 'confirmed'",
+        )
+        .await?;
+    let retract_events = tx
+        .prepare(
+            "UPDATE events SET commitment = $2 WHERE signature = $1 AND commitment = 'confirmed'",
+        )
+        .await?;
+    let retract_program_ixs = tx
+        .prepare(
+            "UPDATE program_instructions SET commitment = $2 WHERE signature = $1 AND commitment = 'confirmed'",
+        )
+        .await?;
+    for retracted in retraction.txs.iter() {
+        for delete in deletes.iter() {
+            tx.execute(delete, &[&retracted.signature]).await?;
+        }
+        tx.execute(&retract_tx, &[&retracted.signature, &RETRACTED])
+            .await?;
+        tx.execute(&retract_events, &[&retracted.signature, &RETRACTED])
+            .await?;
+        tx.execute(&retract_program_ixs, &[&retracted.signature, &RETRACTED])
+            .await?;
+    }
+    tx.commit().await
+}
+
+async fn save_checkpoint<C: GenericClient>(
+    client: &C,
+    program_addr: &str,
+    checkpoint: &Checkpoint,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO checkpoints (program_addr, head_slot, head_sig, tail_slot, tail_sig)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (program_addr) DO UPDATE SET
+                head_slot = EXCLUDED.head_slot,
+                head_sig = EXCLUDED.head_sig,
+                tail_slot = EXCLUDED.tail_slot,
+                tail_sig = EXCLUDED.tail_sig,
+                updated_at = now()",
+            &[
+                &program_addr,
+                &(checkpoint.head_slot as i64),
+                &checkpoint.head_sig,
+                &(checkpoint.tail_slot as i64),
+                &checkpoint.tail_sig,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+// redact removes the password from a connection url before it is logged
+fn redact(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            let creds = &url[scheme + 3..at];
+            match creds.find(':') {
+                Some(colon) => format!("{}:***{}", &url[..scheme + 3 + colon], &url[at..]),
+                None => url.to_string(),
+            }
+        }
+        _ => url.to_string(),
+    }
+}
+
+#[async_trait]
+impl EventSink for PostgresSink {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError> {
+        let mut client = self.client.lock().await;
+        write_batch(&mut client, batch)
+            .await
+            .map_err(|e| SinkError::WriteError(e.to_string()))
+    }
+
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError> {
+        let mut client = self.client.lock().await;
+        retract(&mut client, retraction)
+            .await
+            .map_err(|e| SinkError::WriteError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for PostgresSink {
+    async fn load(&self, program_addr: &str) -> Result<Option<Checkpoint>, CheckpointError> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt(
+                "SELECT head_slot, head_sig, tail_slot, tail_sig FROM checkpoints WHERE program_addr = $1",
+                &[&program_addr],
+            )
+            .await
+            .map_err(|e| CheckpointError::LoadError(program_addr.to_string(), e.to_string()))?;
+        Ok(row.map(|row| {
+            Checkpoint::new(
+                row.get::<_, i64>(0) as u64,
+                row.get(1),
+                row.get::<_, i64>(2) as u64,
+                row.get(3),
+            )
+        }))
+    }
+
+    async fn save(
+        &self,
+        program_addr: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError> {
+        let client = self.client.lock().await;
+        save_checkpoint(&*client, program_addr, checkpoint)
+            .await
+            .map_err(|e| CheckpointError::SaveError(program_addr.to_string(), e.to_string()))
+    }
+}
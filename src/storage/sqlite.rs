@@ -0,0 +1,488 @@
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::{Arc, Mutex};
+
+use super::{PayloadColumns, DELETED_ON_RETRACT};
+use crate::checkpoint::{Checkpoint, CheckpointError, CheckpointStore};
+use crate::sink::{EventBatch, EventSink, Retraction, SinkError, RETRACTED};
+
+// MIGRATIONS are applied in order, the schema version is tracked with `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &[
+    // v1: transactions, logs and events
+    r#"
+    CREATE TABLE transactions (
+        signature TEXT PRIMARY KEY,
+        slot INTEGER NOT NULL,
+        block_time INTEGER,
+        fee INTEGER NOT NULL,
+        err TEXT,
+        commitment TEXT NOT NULL,
+        program_addr TEXT NOT NULL
+    );
+    CREATE INDEX idx_transactions_slot ON transactions (slot);
+    CREATE INDEX idx_transactions_program_addr ON transactions (program_addr);
+
+    CREATE TABLE logs (
+        signature TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        program_id TEXT NOT NULL,
+        depth INTEGER NOT NULL,
+        log_type TEXT,
+        message TEXT NOT NULL,
+        PRIMARY KEY (signature, log_index)
+    );
+    CREATE INDEX idx_logs_slot ON logs (slot);
+    CREATE INDEX idx_logs_program_id ON logs (program_id);
+
+    CREATE TABLE events (
+        signature TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        program_id TEXT NOT NULL,
+        name TEXT,
+        data TEXT,
+        raw TEXT,
+        decode_error TEXT,
+        PRIMARY KEY (signature, log_index)
+    );
+    CREATE INDEX idx_events_slot ON events (slot);
+    CREATE INDEX idx_events_program_id ON events (program_id);
+    CREATE INDEX idx_events_name ON events (name);
+    "#,
+    // v2: cursor checkpoints
+    r#"
+    CREATE TABLE checkpoints (
+        program_addr TEXT PRIMARY KEY,
+        head_slot INTEGER NOT NULL,
+        head_sig TEXT NOT NULL,
+        tail_slot INTEGER NOT NULL,
+        tail_sig TEXT NOT NULL
+    );
+    "#,
+    // v3: commitment of events, to promote and retract them together with their transaction
+    r#"
+    ALTER TABLE events ADD COLUMN commitment TEXT NOT NULL DEFAULT 'confirmed';
+    UPDATE events SET commitment = COALESCE(
+        (SELECT commitment FROM transactions WHERE transactions.signature = events.signature),
+        commitment
+    );
+    CREATE INDEX idx_events_commitment ON events (commitment);
+    "#,
+    // v4: resolved account keys of transactions (JSON array), including lookup table addresses
+    r#"
+    ALTER TABLE transactions ADD COLUMN accounts TEXT NOT NULL DEFAULT '[]';
+    "#,
+    // v5: top-level and inner instructions, accounts are a JSON array and data is base58
+    r#"
+    CREATE TABLE instructions (
+        signature TEXT NOT NULL,
+        ix_index INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        program_id TEXT NOT NULL,
+        accounts TEXT NOT NULL,
+        data TEXT,
+        stack_height INTEGER NOT NULL,
+        parent_index INTEGER,
+        PRIMARY KEY (signature, ix_index)
+    );
+    CREATE INDEX idx_instructions_slot ON instructions (slot);
+    CREATE INDEX idx_instructions_program_id ON instructions (program_id);
+    "#,
+    // v6: instructions of tracked programs decoded with their IDL
+    r#"
+    CREATE TABLE program_instructions (
+        signature TEXT NOT NULL,
+        ix_index INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        program_id TEXT NOT NULL,
+        name TEXT,
+        data TEXT,
+        raw TEXT,
+        decode_error TEXT,
+        commitment TEXT NOT NULL,
+        PRIMARY KEY (signature, ix_index)
+    );
+    CREATE INDEX idx_program_instructions_slot ON program_instructions (slot);
+    CREATE INDEX idx_program_instructions_program_id ON program_instructions (program_id);
+    CREATE INDEX idx_program_instructions_name ON program_instructions (name);
+    CREATE INDEX idx_program_instructions_commitment ON program_instructions (commitment);
+    "#,
+    // v7: instruction index of events emitted with emit_cpi!
+    r#"
+    ALTER TABLE events ADD COLUMN ix_index INTEGER;
+    "#,
+    // v8: structured errors of failed transactions
+    r#"
+    CREATE TABLE tx_failures (
+        signature TEXT PRIMARY KEY,
+        slot INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        instruction_index INTEGER,
+        instruction TEXT,
+        program_id TEXT,
+        error_code INTEGER,
+        error_name TEXT,
+        error_message TEXT,
+        account TEXT
+    );
+    CREATE INDEX idx_tx_failures_slot ON tx_failures (slot);
+    CREATE INDEX idx_tx_failures_program_id ON tx_failures (program_id);
+    CREATE INDEX idx_tx_failures_error_name ON tx_failures (error_name);
+    "#,
+    // v9: incomplete (truncated logs) flag and balance changes recovered for incomplete txs,
+    // amounts are stored as text since token amounts may not fit an INTEGER
+    r#"
+    ALTER TABLE transactions ADD COLUMN incomplete INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE balance_changes (
+        signature TEXT NOT NULL,
+        account TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        slot INTEGER NOT NULL,
+        mint TEXT,
+        owner TEXT,
+        pre TEXT NOT NULL,
+        post TEXT NOT NULL,
+        PRIMARY KEY (signature, account, kind)
+    );
+    CREATE INDEX idx_balance_changes_slot ON balance_changes (slot);
+    CREATE INDEX idx_balance_changes_account ON balance_changes (account);
+    "#,
+];
+
+// SqliteSink writes transactions, instructions, logs, events, decoded instructions, failures and balance changes into a local SQLite database.
+// It is also a CheckpointStore, the checkpoint of a batch is written in the same transaction as its events.
+pub struct SqliteSink {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteSink {
+    // open opens (or creates) the database at the given path and applies pending migrations
+    pub fn open(path: &str) -> Result<Self, SinkError> {
+        let conn = Connection::open(path)
+            .map_err(|e| SinkError::OpenError(path.to_string(), e.to_string()))?;
+        Self::with_connection(conn)
+            .map_err(|e| SinkError::OpenError(path.to_string(), e.to_string()))
+    }
+
+    // in_memory creates a sink backed by an in-memory database
+    pub fn in_memory() -> Result<Self, SinkError> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| SinkError::OpenError(":memory:".to_string(), e.to_string()))?;
+        Self::with_connection(conn)
+            .map_err(|e| SinkError::OpenError(":memory:".to_string(), e.to_string()))
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, rusqlite::Error> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // connection returns the underlying connection, e.g. for querying the index
+    pub fn connection(&self) -> Arc<Mutex<Connection>> {
+        self.conn.clone()
+    }
+}
+
+// migrate applies the migrations that are newer than the current schema version
+fn migrate(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        println!("[sqlite_sink/migrate] Applying migration v{}", i + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn write_batch(conn: &mut Connection, batch: &EventBatch) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert_tx = tx.prepare_cached(
+            "INSERT INTO transactions (signature, slot, block_time, fee, err, commitment, program_addr, accounts, incomplete)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (signature) DO UPDATE SET
+                slot = excluded.slot,
+                block_time = excluded.block_time,
+                fee = excluded.fee,
+                err = excluded.err,
+                accounts = excluded.accounts,
+                incomplete = excluded.incomplete,
+                commitment = CASE WHEN transactions.commitment = 'finalized'
+                    THEN transactions.commitment ELSE excluded.commitment END",
+        )?;
+        let mut insert_log = tx.prepare_cached(
+            "INSERT OR REPLACE INTO logs (signature, log_index, slot, program_id, depth, log_type, message)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let mut insert_ix = tx.prepare_cached(
+            "INSERT OR REPLACE INTO instructions (signature, ix_index, slot, program_id, accounts, data, stack_height, parent_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut insert_event = tx.prepare_cached(
+            "INSERT INTO events (signature, log_index, slot, program_id, name, data, raw, decode_error, commitment, ix_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (signature, log_index) DO UPDATE SET
+                slot = excluded.slot,
+                ix_index = excluded.ix_index,
+                program_id = excluded.program_id,
+                name = excluded.name,
+                data = excluded.data,
+                raw = excluded.raw,
+                decode_error = excluded.decode_error,
+                commitment = CASE WHEN events.commitment = 'finalized'
+                    THEN events.commitment ELSE excluded.commitment END",
+        )?;
+        let mut insert_program_ix = tx.prepare_cached(
+            "INSERT INTO program_instructions (signature, ix_index, slot, program_id, name, data, raw, decode_error, commitment)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (signature, ix_index) DO UPDATE SET
+                slot = excluded.slot,
+                program_id = excluded.program_id,
+                name = excluded.name,
+                data = excluded.data,
+                raw = excluded.raw,
+                decode_error = excluded.decode_error,
+                commitment = CASE WHEN program_instructions.commitment = 'finalized'
+                    THEN program_instructions.commitment ELSE excluded.commitment END",
+        )?;
+        let mut insert_failure = tx.prepare_cached(
+            "INSERT OR REPLACE INTO tx_failures (signature, slot, kind, instruction_index, instruction, program_id, error_code, error_name, error_message, account)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        let mut insert_balance_change = tx.prepare_cached(
+            "INSERT OR REPLACE INTO balance_changes (signature, account, kind, slot, mint, owner, pre, post)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for record in batch.txs.iter() {
+            insert_tx.execute(params![
+                record.signature,
+                record.slot,
+                record.block_time,
+                record.fee,
+                record.err,
+                batch.commitment.as_str(),
+                batch.program_addr,
+                serde_json::Value::from(record.accounts.clone()).to_string(),
+                record.incomplete,
+            ])?;
+            for ix in record.instructions.iter() {
+                insert_ix.execute(params![
+                    record.signature,
+                    ix.index,
+                    record.slot,
+                    ix.program_id,
+                    serde_json::Value::from(ix.accounts.clone()).to_string(),
+                    ix.data,
+                    ix.stack_height,
+                    ix.parent_index,
+                ])?;
+            }
+            for log in record.logs.iter() {
+                insert_log.execute(params![
+                    record.signature,
+                    log.index,
+                    record.slot,
+                    log.program_id,
+                    log.depth,
+                    log.log_type.map(|log_type| log_type.as_str()),
+                    log.raw,
+                ])?;
+            }
+            for event in record.events.iter() {
+                let columns = PayloadColumns::event(&event.payload);
+                insert_event.execute(params![
+                    record.signature,
+                    event.log_index,
+                    record.slot,
+                    event.program_id,
+                    columns.name,
+                    columns.data.map(|data| data.to_string()),
+                    columns.raw,
+                    columns.decode_error,
+                    batch.commitment.as_str(),
+                    event.ix_index,
+                ])?;
+            }
+            for ix in record.program_instructions.iter() {
+                let columns = PayloadColumns::instruction(&ix.payload);
+                insert_program_ix.execute(params![
+                    record.signature,
+                    ix.ix_index,
+                    record.slot,
+                    ix.program_id,
+                    columns.name,
+                    columns.data.map(|data| data.to_string()),
+                    columns.raw,
+                    columns.decode_error,
+                    batch.commitment.as_str(),
+                ])?;
+            }
+            if let Some(failure) = &record.failure {
+                insert_failure.execute(params![
+                    record.signature,
+                    record.slot,
+                    failure.kind.as_str(),
+                    failure.instruction_index,
+                    failure.instruction,
+                    failure.program_id,
+                    failure.error_code,
+                    failure.error_name,
+                    failure.error_message,
+                    failure.account,
+                ])?;
+            }
+            for change in record.balance_changes.iter() {
+                insert_balance_change.execute(params![
+                    record.signature,
+                    change.account,
+                    change.kind(),
+                    record.slot,
+                    change.mint,
+                    change.owner,
+                    change.pre.to_string(),
+                    change.post.to_string(),
+                ])?;
+            }
+        }
+    }
+    if let Some(checkpoint) = &batch.checkpoint {
+        save_checkpoint(&tx, batch.program_addr.as_str(), checkpoint)?;
+    }
+    tx.commit()
+}
+
+// retract marks the confirmed transactions, events and decoded instructions as retracted and
+// deletes their instructions, logs, failures and balance changes, finalized ones are kept
+fn retract(conn: &mut Connection, retraction: &Retraction) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    {
+        // the rows are deleted first, while the transaction is still marked confirmed
+        let mut deletes = DELETED_ON_RETRACT
+            .iter()
+            .map(|table| {
+                tx.prepare_cached(&format!(
+                    "DELETE FROM {table} WHERE signature = ?1 AND EXISTS (
+                        SELECT 1 FROM transactions WHERE signature = ?1 AND commitment = 'confirmed'
+                    )"
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut retract_tx = tx.prepare_cached(
+            "UPDATE transactions SET commitment = ?2 WHERE signature = ?1 AND commitment = 'confirmed'",
+        )?;
+        let mut retract_events = tx.prepare_cached(
+            "UPDATE events SET commitment = ?2 WHERE signature = ?1 AND commitment = 'confirmed'",
+        )?;
+        let mut retract_program_ixs = tx.prepare_cached(
+            "UPDATE program_instructions SET commitment = ?2 WHERE signature = ?1 AND commitment = 'confirmed'",
+        )?;
+        for retracted in retraction.txs.iter() {
+            for delete in deletes.iter_mut() {
+                delete.execute(params![retracted.signature])?;
+            }
+            retract_tx.execute(params![retracted.signature, RETRACTED])?;
+            retract_events.execute(params![retracted.signature, RETRACTED])?;
+            retract_program_ixs.execute(params![retracted.signature, RETRACTED])?;
+        }
+    }
+    tx.commit()
+}
+
+fn save_checkpoint(
+    conn: &Connection,
+    program_addr: &str,
+    checkpoint: &Checkpoint,
+) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO checkpoints (program_addr, head_slot, head_sig, tail_slot, tail_sig)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?
+    .execute(params![
+        program_addr,
+        checkpoint.head_slot,
+        checkpoint.head_sig,
+        checkpoint.tail_slot,
+        checkpoint.tail_sig,
+    ])?;
+    Ok(())
+}
+
+fn load_checkpoint(
+    conn: &Connection,
+    program_addr: &str,
+) -> Result<Option<Checkpoint>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT head_slot, head_sig, tail_slot, tail_sig FROM checkpoints WHERE program_addr = ?1",
+        params![program_addr],
+        |row| {
+            Ok(Checkpoint::new(
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        },
+    )
+    .optional()
+}
+
+#[async_trait]
+impl EventSink for SqliteSink {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError> {
+        let conn = self.conn.clone();
+        let batch = batch.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            write_batch(&mut conn, &batch)
+        })
+        .await
+        .map_err(|e| SinkError::WriteError(e.to_string()))?
+        .map_err(|e| SinkError::WriteError(e.to_string()))
+    }
+
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError> {
+        let conn = self.conn.clone();
+        let retraction = retraction.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            retract(&mut conn, &retraction)
+        })
+        .await
+        .map_err(|e| SinkError::WriteError(e.to_string()))?
+        .map_err(|e| SinkError::WriteError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for SqliteSink {
+    async fn load(&self, program_addr: &str) -> Result<Option<Checkpoint>, CheckpointError> {
+        let conn = self.conn.clone();
+        let addr = program_addr.to_string();
+        tokio::task::spawn_blocking(move || load_checkpoint(&conn.lock().unwrap(), addr.as_str()))
+            .await
+            .map_err(|e| CheckpointError::LoadError(program_addr.to_string(), e.to_string()))?
+            .map_err(|e| CheckpointError::LoadError(program_addr.to_string(), e.to_string()))
+    }
+
+    async fn save(
+        &self,
+        program_addr: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError> {
+        let conn = self.conn.clone();
+        let addr = program_addr.to_string();
+        let checkpoint = checkpoint.clone();
+        tokio::task::spawn_blocking(move || {
+            save_checkpoint(&conn.lock().unwrap(), addr.as_str(), &checkpoint)
+        })
+        .await
+        .map_err(|e| CheckpointError::SaveError(program_addr.to_string(), e.to_string()))?
+        .map_err(|e| CheckpointError::SaveError(program_addr.to_string(), e.to_string()))
+    }
+}
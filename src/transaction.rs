@@ -0,0 +1,401 @@
+use serde::Serialize;
+use solana_sdk::{message::VersionedMessage, transaction::TransactionVersion};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedTransaction, EncodedTransactionWithStatusMeta,
+    UiCompiledInstruction, UiInstruction, UiMessage, UiParsedInstruction, UiTransactionStatusMeta,
+    UiTransactionTokenBalance,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum TxDecodeError {
+    #[error("failed to decode binary transaction")]
+    InvalidTransaction,
+    #[error("invalid base58 instruction data: {0}")]
+    InvalidData(String),
+    #[error("account index {0} is out of range of {1} account keys")]
+    AccountIndex(u8, usize),
+}
+
+// TxMessage is the message of a transaction, independent of the encoding it was fetched with
+// (json, jsonParsed, base58 or base64)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TxMessage {
+    // version is None for legacy transactions
+    pub version: Option<u8>,
+    // account_keys are the resolved account keys, the static keys followed by the
+    // writable and readonly keys loaded from address lookup tables (v0 txs)
+    pub account_keys: Vec<String>,
+    pub instructions: Vec<TxInstruction>,
+    // inner_instructions are the CPIs made while executing the top-level instructions
+    pub inner_instructions: Vec<TxInnerInstructions>,
+}
+
+// TxInstruction is an instruction with its accounts resolved to keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxInstruction {
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    // data is None for instructions the node parsed itself (jsonParsed encoding)
+    pub data: Option<Vec<u8>>,
+    // stack_height is reported for inner instructions by recent nodes only
+    pub stack_height: Option<u32>,
+}
+
+// TxInnerInstructions are the inner instructions of the top-level instruction at index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxInnerInstructions {
+    pub index: u8,
+    pub instructions: Vec<TxInstruction>,
+}
+
+// InstructionRecord is a top-level or inner instruction of a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstructionRecord {
+    // index is the position of the instruction in execution order,
+    // inner instructions follow the instruction that invoked them
+    pub index: usize,
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    // data is the base58 encoded instruction data, None for instructions parsed by the node
+    pub data: Option<String>,
+    // stack_height is 1 for top-level instructions, a CPI is one level deeper than its caller
+    pub stack_height: u32,
+    // parent_index is the index of the instruction that invoked this one, None for top-level instructions
+    pub parent_index: Option<usize>,
+}
+
+// BalanceChange is the change of the lamports or the token amount of an account made by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceChange {
+    pub account: String,
+    // mint is None for lamports, the token mint for token accounts
+    pub mint: Option<String>,
+    // owner is the owner of a token account, if reported by the node
+    pub owner: Option<String>,
+    // pre and post are raw amounts (lamports or token base units), 0 for created and closed token accounts
+    pub pre: u64,
+    pub post: u64,
+}
+
+impl BalanceChange {
+    // kind is "lamports" or "token"
+    pub fn kind(&self) -> &'static str {
+        match self.mint {
+            None => "lamports",
+            Some(_) => "token",
+        }
+    }
+}
+
+// decode_message builds the message of a fetched transaction.
+// Binary transactions are deserialized locally into a VersionedTransaction.
+pub fn decode_message(tx: &EncodedTransactionWithStatusMeta) -> Result<TxMessage, TxDecodeError> {
+    let mut message = decode_top_level(tx)?;
+    if let Some(OptionSerializer::Some(inner)) =
+        tx.meta.as_ref().map(|meta| &meta.inner_instructions)
+    {
+        message.inner_instructions = inner
+            .iter()
+            .map(|inner| {
+                Ok(TxInnerInstructions {
+                    index: inner.index,
+                    instructions: inner
+                        .instructions
+                        .iter()
+                        .map(|ix| ui_instruction(ix, &message.account_keys))
+                        .collect::<Result<Vec<_>, _>>()?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(message)
+}
+
+impl TxMessage {
+    // executed returns the top-level and inner instructions in execution order,
+    // in the same order as the records of instruction_records
+    pub fn executed(&self) -> impl Iterator<Item = &TxInstruction> {
+        self.instructions
+            .iter()
+            .enumerate()
+            .flat_map(|(i, ix)| std::iter::once(ix).chain(self.inner_of(i)))
+    }
+
+    pub(crate) fn inner_of(&self, index: usize) -> impl Iterator<Item = &TxInstruction> {
+        self.inner_instructions
+            .iter()
+            .filter(move |inner| inner.index as usize == index)
+            .flat_map(|inner| inner.instructions.iter())
+    }
+}
+
+// balance_changes returns the accounts whose lamports or token amounts changed in a transaction,
+// the lamports first in account order followed by the token balances
+pub fn balance_changes(
+    meta: &UiTransactionStatusMeta,
+    account_keys: &[String],
+) -> Vec<BalanceChange> {
+    let mut changes = meta
+        .pre_balances
+        .iter()
+        .zip(meta.post_balances.iter())
+        .zip(account_keys.iter())
+        .filter(|((pre, post), _)| pre != post)
+        .map(|((pre, post), account)| BalanceChange {
+            account: account.clone(),
+            mint: None,
+            owner: None,
+            pre: *pre,
+            post: *post,
+        })
+        .collect::<Vec<_>>();
+    let token_balances =
+        |balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>| match balances {
+            OptionSerializer::Some(balances) => balances.clone(),
+            _ => Vec::new(),
+        };
+    let pre_tokens = token_balances(&meta.pre_token_balances);
+    let post_tokens = token_balances(&meta.post_token_balances);
+    let mut indexes = pre_tokens
+        .iter()
+        .chain(post_tokens.iter())
+        .map(|balance| balance.account_index)
+        .collect::<Vec<_>>();
+    indexes.sort_unstable();
+    indexes.dedup();
+    for index in indexes {
+        let find = |balances: &[UiTransactionTokenBalance]| {
+            balances
+                .iter()
+                .find(|balance| balance.account_index == index)
+                .cloned()
+        };
+        let (pre, post) = (find(&pre_tokens), find(&post_tokens));
+        let amount = |balance: &Option<UiTransactionTokenBalance>| {
+            balance
+                .as_ref()
+                .and_then(|balance| balance.ui_token_amount.amount.parse::<u64>().ok())
+                .unwrap_or_default()
+        };
+        let (pre_amount, post_amount) = (amount(&pre), amount(&post));
+        let (Some(account), Some(balance)) = (
+            account_keys.get(index as usize),
+            post.as_ref().or(pre.as_ref()),
+        ) else {
+            continue;
+        };
+        if pre_amount == post_amount {
+            continue;
+        }
+        changes.push(BalanceChange {
+            account: account.clone(),
+            mint: Some(balance.mint.clone()),
+            owner: match &balance.owner {
+                OptionSerializer::Some(owner) => Some(owner.clone()),
+                _ => None,
+            },
+            pre: pre_amount,
+            post: post_amount,
+        });
+    }
+    changes
+}
+
+// instruction_records flattens the top-level and inner instructions of a message in execution order.
+// The parent of an inner instruction is the closest preceding instruction with a lower stack height,
+// without reported stack heights all inner instructions are attributed to the top-level instruction.
+pub fn instruction_records(message: &TxMessage) -> Vec<InstructionRecord> {
+    let mut records = Vec::new();
+    for (i, ix) in message.instructions.iter().enumerate() {
+        let top_level = records.len();
+        records.push(instruction_record(top_level, ix, 1, None));
+        // callers of the current instruction as (stack height, index)
+        let mut callers = vec![(1, top_level)];
+        for ix in message.inner_of(i) {
+            let stack_height = ix.stack_height.unwrap_or(2).max(2);
+            while callers.len() > 1 && callers.last().unwrap().0 >= stack_height {
+                callers.pop();
+            }
+            let index = records.len();
+            records.push(instruction_record(
+                index,
+                ix,
+                stack_height,
+                callers.last().map(|caller| caller.1),
+            ));
+            callers.push((stack_height, index));
+        }
+    }
+    records
+}
+
+fn instruction_record(
+    index: usize,
+    ix: &TxInstruction,
+    stack_height: u32,
+    parent_index: Option<usize>,
+) -> InstructionRecord {
+    InstructionRecord {
+        index,
+        program_id: ix.program_id.clone(),
+        accounts: ix.accounts.clone(),
+        data: ix
+            .data
+            .as_ref()
+            .map(|data| bs58::encode(data).into_string()),
+        stack_height,
+        parent_index,
+    }
+}
+
+// decode_top_level decodes the account keys and the top-level instructions of a transaction
+fn decode_top_level(tx: &EncodedTransactionWithStatusMeta) -> Result<TxMessage, TxDecodeError> {
+    let loaded = match tx.meta.as_ref().map(|meta| &meta.loaded_addresses) {
+        Some(OptionSerializer::Some(loaded)) => loaded
+            .writable
+            .iter()
+            .chain(loaded.readonly.iter())
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    };
+    let version = match &tx.version {
+        Some(TransactionVersion::Number(version)) => Some(*version),
+        _ => None,
+    };
+    match &tx.transaction {
+        EncodedTransaction::Json(ui_tx) => match &ui_tx.message {
+            UiMessage::Raw(message) => {
+                let mut account_keys = message.account_keys.clone();
+                account_keys.extend(loaded);
+                let instructions = message
+                    .instructions
+                    .iter()
+                    .map(|ix| compiled_instruction(ix, &account_keys))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(TxMessage {
+                    version,
+                    account_keys,
+                    instructions,
+                    inner_instructions: Vec::new(),
+                })
+            }
+            // parsed keys already include the loaded addresses
+            UiMessage::Parsed(message) => {
+                let account_keys = message
+                    .account_keys
+                    .iter()
+                    .map(|key| key.pubkey.clone())
+                    .collect::<Vec<_>>();
+                let instructions = message
+                    .instructions
+                    .iter()
+                    .map(|ix| ui_instruction(ix, &account_keys))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(TxMessage {
+                    version,
+                    account_keys,
+                    instructions,
+                    inner_instructions: Vec::new(),
+                })
+            }
+        },
+        EncodedTransaction::Accounts(list) => Ok(TxMessage {
+            version,
+            account_keys: list
+                .account_keys
+                .iter()
+                .map(|key| key.pubkey.clone())
+                .collect(),
+            instructions: Vec::new(),
+            inner_instructions: Vec::new(),
+        }),
+        binary => {
+            let decoded = binary.decode().ok_or(TxDecodeError::InvalidTransaction)?;
+            let mut account_keys = decoded
+                .message
+                .static_account_keys()
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>();
+            account_keys.extend(loaded);
+            let instructions = decoded
+                .message
+                .instructions()
+                .iter()
+                .map(|ix| {
+                    Ok(TxInstruction {
+                        program_id: account_key(&account_keys, ix.program_id_index)?,
+                        accounts: account_keys_of(&account_keys, &ix.accounts)?,
+                        data: Some(ix.data.clone()),
+                        stack_height: None,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(TxMessage {
+                version: match decoded.message {
+                    VersionedMessage::Legacy(_) => None,
+                    VersionedMessage::V0(_) => Some(0),
+                },
+                account_keys,
+                instructions,
+                inner_instructions: Vec::new(),
+            })
+        }
+    }
+}
+
+// ui_instruction resolves an instruction of a json or jsonParsed message
+fn ui_instruction(
+    ix: &UiInstruction,
+    account_keys: &[String],
+) -> Result<TxInstruction, TxDecodeError> {
+    match ix {
+        UiInstruction::Compiled(ix) => compiled_instruction(ix, account_keys),
+        UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(ix)) => Ok(TxInstruction {
+            program_id: ix.program_id.clone(),
+            accounts: ix.accounts.clone(),
+            data: Some(decode_data(&ix.data)?),
+            stack_height: ix.stack_height,
+        }),
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(ix)) => Ok(TxInstruction {
+            program_id: ix.program_id.clone(),
+            accounts: Vec::new(),
+            data: None,
+            stack_height: ix.stack_height,
+        }),
+    }
+}
+
+fn compiled_instruction(
+    ix: &UiCompiledInstruction,
+    account_keys: &[String],
+) -> Result<TxInstruction, TxDecodeError> {
+    Ok(TxInstruction {
+        program_id: account_key(account_keys, ix.program_id_index)?,
+        accounts: account_keys_of(account_keys, &ix.accounts)?,
+        data: Some(decode_data(&ix.data)?),
+        stack_height: ix.stack_height,
+    })
+}
+
+fn decode_data(data: &str) -> Result<Vec<u8>, TxDecodeError> {
+    bs58::decode(data)
+        .into_vec()
+        .map_err(|err| TxDecodeError::InvalidData(err.to_string()))
+}
+
+fn account_key(account_keys: &[String], index: u8) -> Result<String, TxDecodeError> {
+    account_keys
+        .get(index as usize)
+        .cloned()
+        .ok_or(TxDecodeError::AccountIndex(index, account_keys.len()))
+}
+
+fn account_keys_of(account_keys: &[String], indexes: &[u8]) -> Result<Vec<String>, TxDecodeError> {
+    indexes
+        .iter()
+        .map(|index| account_key(account_keys, *index))
+        .collect()
+}
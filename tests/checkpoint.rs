@@ -0,0 +1,47 @@
+// Tests for the FileCheckpointStore
+use solana_indexer::checkpoint::{Checkpoint, CheckpointStore, FileCheckpointStore};
+
+#[tokio::test]
+async fn checkpoints_are_loaded_after_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoints.json");
+    let path = path.to_str().unwrap();
+    let first = Checkpoint::new(40, "head-sig".to_string(), 20, "tail-sig".to_string());
+    let second = Checkpoint::new(50, "head-sig-2".to_string(), 30, "tail-sig-2".to_string());
+    {
+        let store = FileCheckpointStore::open(path).await.unwrap();
+        store.save("prog", &first).await.unwrap();
+        store.save("other-prog", &second).await.unwrap();
+        assert_eq!(store.load("prog").await.unwrap(), Some(first.clone()));
+    }
+
+    let store = FileCheckpointStore::open(path).await.unwrap();
+    assert_eq!(store.load("prog").await.unwrap(), Some(first));
+    assert_eq!(store.load("other-prog").await.unwrap(), Some(second));
+    assert!(!dir.path().join("checkpoints.json.tmp").exists());
+}
+
+#[tokio::test]
+async fn a_missing_file_has_no_checkpoints() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoints.json");
+    let store = FileCheckpointStore::open(path.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(store.load("prog").await.unwrap(), None);
+    // the file is only created by the first save
+    assert!(!path.exists());
+    let checkpoint = Checkpoint::new(40, "head-sig".to_string(), 20, "tail-sig".to_string());
+    store.save("prog", &checkpoint).await.unwrap();
+    assert!(path.exists());
+}
+
+#[tokio::test]
+async fn a_corrupt_file_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoints.json");
+    std::fs::write(&path, "not json").unwrap();
+    assert!(FileCheckpointStore::open(path.to_str().unwrap())
+        .await
+        .is_err());
+}
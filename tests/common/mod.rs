@@ -0,0 +1,529 @@
+// Helpers shared by the integration tests: an in-process JSON-RPC node and in-memory stores.
+#![allow(dead_code)]
+
+pub mod pubsub;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use solana_indexer::{
+    checkpoint::{Checkpoint, CheckpointError, CheckpointStore},
+    failure::{FailureKind, TxFailure},
+    log_events::{
+        Event, EventPayload, InstructionPayload, LogRecord, LogType, ProgramInstruction, TxRecord,
+    },
+    sink::{EventBatch, EventSink, Retraction, SinkError},
+    transaction::{BalanceChange, InstructionRecord},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+// MockTx is a tx known to the mock node, finalized txs are also returned with finalized commitment
+#[derive(Clone)]
+pub struct MockTx {
+    pub signature: String,
+    pub slot: u64,
+    pub finalized: bool,
+    pub tx: Value,
+}
+
+// Ledger is the state of the mock node, the txs are kept oldest first
+#[derive(Default)]
+pub struct Ledger {
+    pub confirmed_slot: u64,
+    pub finalized_slot: u64,
+    pub txs: Vec<MockTx>,
+}
+
+impl Ledger {
+    pub fn push(&mut self, tx: MockTx) {
+        self.txs.push(tx);
+        self.txs.sort_by_key(|tx| tx.slot);
+    }
+
+    fn visible<'a>(&'a self, commitment: &str) -> impl DoubleEndedIterator<Item = &'a MockTx> + 'a {
+        let finalized = commitment == "finalized";
+        self.txs.iter().filter(move |tx| !finalized || tx.finalized)
+    }
+
+    fn signatures(&self, params: &Value) -> Value {
+        let config = &params[1];
+        let limit = config["limit"].as_u64().unwrap_or(1000) as usize;
+        let mut sigs = self.visible(commitment(config)).rev();
+        if let Some(before) = config["before"].as_str() {
+            sigs.by_ref().find(|tx| tx.signature == before);
+        }
+        let until = config["until"].as_str();
+        let page = sigs
+            .take_while(|tx| Some(tx.signature.as_str()) != until)
+            .take(limit)
+            .map(|tx| {
+                json!({
+                    "signature": tx.signature,
+                    "slot": tx.slot,
+                    "err": tx.tx["meta"]["err"],
+                    "memo": null,
+                    "blockTime": null,
+                    "confirmationStatus": if tx.finalized { "finalized" } else { "confirmed" },
+                })
+            })
+            .collect::<Vec<_>>();
+        Value::Array(page)
+    }
+
+    fn transaction(&self, params: &Value) -> Value {
+        let sig = params[0].as_str().unwrap_or_default();
+        self.visible(commitment(&params[1]))
+            .find(|tx| tx.signature == sig)
+            .map(|tx| tx.tx.clone())
+            .unwrap_or(Value::Null)
+    }
+
+    fn slot(&self, params: &Value) -> Value {
+        match commitment(&params[0]) {
+            "finalized" => json!(self.finalized_slot),
+            _ => json!(self.confirmed_slot),
+        }
+    }
+}
+
+fn commitment(config: &Value) -> &str {
+    config["commitment"].as_str().unwrap_or("finalized")
+}
+
+// MockRpc serves getSlot, getSignaturesForAddress and getTransaction (also in batches) from a Ledger
+pub struct MockRpc {
+    pub url: String,
+    pub ledger: Arc<Mutex<Ledger>>,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+    failures: Arc<Mutex<VecDeque<Failure>>>,
+    delay: Arc<Mutex<Duration>>,
+}
+
+// Failure is an http error the mock node answers a request with instead of serving it
+#[derive(Clone)]
+pub struct Failure {
+    pub status: u16,
+    pub retry_after: Option<u64>,
+}
+
+impl MockRpc {
+    pub async fn start(ledger: Ledger) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let ledger = Arc::new(Mutex::new(ledger));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(VecDeque::new()));
+        let delay = Arc::new(Mutex::new(Duration::ZERO));
+        let state = (
+            ledger.clone(),
+            requests.clone(),
+            failures.clone(),
+            delay.clone(),
+        );
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (ledger, requests, failures, delay) = state.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(body) = read_request(&mut reader).await {
+                        let delay = *delay.lock().unwrap();
+                        tokio::time::sleep(delay).await;
+                        let failure = failures.lock().unwrap().pop_front();
+                        let (status, headers, response) = match failure {
+                            Some(Failure {
+                                status,
+                                retry_after,
+                            }) => (
+                                status,
+                                retry_after
+                                    .map(|secs| format!("Retry-After: {}\r\n", secs))
+                                    .unwrap_or_default(),
+                                Vec::new(),
+                            ),
+                            None => (200, String::new(), respond(&ledger, &requests, body)),
+                        };
+                        let head = format!(
+                            "HTTP/1.1 {} Mock\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                            status,
+                            headers,
+                            response.len()
+                        );
+                        // a single write, split responses are delayed by Nagle's algorithm
+                        let response = [head.as_bytes(), &response].concat();
+                        if writer.write_all(&response).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Self {
+            url,
+            ledger,
+            requests,
+            failures,
+            delay,
+        }
+    }
+
+    // delay holds every response back for the given time
+    pub fn delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    // fail answers the next requests with the given failures, in order
+    pub fn fail(&self, failures: impl IntoIterator<Item = Failure>) {
+        self.failures.lock().unwrap().extend(failures);
+    }
+
+    // pending_failures returns the number of failures that were not answered yet
+    pub fn pending_failures(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    // requests returns the params of the calls of a method that were served
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(m, _)| m == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(body)
+}
+
+fn respond(
+    ledger: &Mutex<Ledger>,
+    requests: &Mutex<Vec<(String, Value)>>,
+    body: Vec<u8>,
+) -> Vec<u8> {
+    let request: Value = serde_json::from_slice(&body).unwrap();
+    let call = |call: &Value| {
+        let method = call["method"].as_str().unwrap_or_default().to_string();
+        let params = call["params"].clone();
+        let ledger = ledger.lock().unwrap();
+        let result = match method.as_str() {
+            "getSlot" => ledger.slot(&params),
+            "getSignaturesForAddress" => ledger.signatures(&params),
+            "getTransaction" => ledger.transaction(&params),
+            _ => Value::Null,
+        };
+        requests.lock().unwrap().push((method, params));
+        json!({"jsonrpc": "2.0", "id": call["id"], "result": result})
+    };
+    let response = match &request {
+        Value::Array(calls) => Value::Array(calls.iter().map(call).collect()),
+        call_value => call(call_value),
+    };
+    serde_json::to_vec(&response).unwrap()
+}
+
+// Instruction is an instruction of a mock tx, the accounts are indexes into the account keys
+#[derive(Clone)]
+pub struct Instruction {
+    pub program: usize,
+    pub accounts: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+// program_tx returns the json encoded getTransaction result of a successful tx,
+// inner holds the inner instructions of each top level instruction
+pub fn program_tx(
+    sig: &str,
+    slot: u64,
+    keys: &[String],
+    instructions: Vec<Instruction>,
+    inner: Vec<Vec<Instruction>>,
+    logs: Vec<String>,
+) -> Value {
+    let encode = |ix: &Instruction, stack_height: Option<u32>| {
+        json!({
+            "programIdIndex": ix.program,
+            "accounts": ix.accounts,
+            "data": bs58::encode(&ix.data).into_string(),
+            "stackHeight": stack_height,
+        })
+    };
+    let inner = inner
+        .iter()
+        .enumerate()
+        .filter(|(_, ixs)| !ixs.is_empty())
+        .map(|(index, ixs)| {
+            json!({
+                "index": index,
+                "instructions": ixs.iter().map(|ix| encode(ix, Some(2))).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "slot": slot,
+        "blockTime": null,
+        "transaction": {
+            "signatures": [sig],
+            "message": {
+                "accountKeys": keys,
+                "header": {
+                    "numRequiredSignatures": 1,
+                    "numReadonlySignedAccounts": 0,
+                    "numReadonlyUnsignedAccounts": 1,
+                },
+                "recentBlockhash": keys[0],
+                "instructions": instructions.iter().map(|ix| encode(ix, None)).collect::<Vec<_>>(),
+            },
+        },
+        "meta": {
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "innerInstructions": inner,
+            "logMessages": logs,
+            "preTokenBalances": [],
+            "postTokenBalances": [],
+            "rewards": [],
+        },
+    })
+}
+
+// invoke_tx returns a tx with a single instruction of the program that logs a message
+pub fn invoke_tx(sig: &str, slot: u64, program: &str, finalized: bool) -> MockTx {
+    let keys = vec![
+        solana_sdk::pubkey::Pubkey::new_unique().to_string(),
+        program.to_string(),
+    ];
+    let logs = vec![
+        format!("Program {} invoke [1]", program),
+        "Program log: hello".to_string(),
+        format!("Program {} success", program),
+    ];
+    let ix = Instruction {
+        program: 1,
+        accounts: vec![0],
+        data: vec![],
+    };
+    MockTx {
+        signature: sig.to_string(),
+        slot,
+        finalized,
+        tx: program_tx(sig, slot, &keys, vec![ix], vec![], logs),
+    }
+}
+
+// signature returns a unique valid signature
+pub fn signature() -> String {
+    solana_sdk::signature::Signature::new_unique().to_string()
+}
+
+// MemorySink keeps the batches and retractions in memory
+#[derive(Default)]
+pub struct MemorySink {
+    pub batches: Mutex<Vec<EventBatch>>,
+    pub retractions: Mutex<Vec<Retraction>>,
+}
+
+impl MemorySink {
+    // retracted returns the signatures of all retracted txs
+    pub fn retracted(&self) -> Vec<String> {
+        self.retractions
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|retraction| retraction.txs.iter().map(|tx| tx.signature.clone()))
+            .collect()
+    }
+
+    // written returns the signatures of the txs written with the given commitment, in order
+    pub fn written(&self, commitment: solana_indexer::sink::CommitStatus) -> Vec<String> {
+        self.batches
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|batch| batch.commitment == commitment)
+            .flat_map(|batch| batch.txs.iter().map(|tx| tx.signature.clone()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EventSink for MemorySink {
+    async fn write_batch(&self, batch: &EventBatch) -> Result<(), SinkError> {
+        self.batches.lock().unwrap().push(batch.clone());
+        Ok(())
+    }
+
+    async fn retract(&self, retraction: &Retraction) -> Result<(), SinkError> {
+        self.retractions.lock().unwrap().push(retraction.clone());
+        Ok(())
+    }
+}
+
+// MemoryCheckpoints keeps the checkpoints in memory
+#[derive(Default)]
+pub struct MemoryCheckpoints {
+    pub checkpoints: Mutex<HashMap<String, Checkpoint>>,
+}
+
+#[async_trait]
+impl CheckpointStore for MemoryCheckpoints {
+    async fn load(&self, program_addr: &str) -> Result<Option<Checkpoint>, CheckpointError> {
+        Ok(self.checkpoints.lock().unwrap().get(program_addr).cloned())
+    }
+
+    async fn save(
+        &self,
+        program_addr: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError> {
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .insert(program_addr.to_string(), checkpoint.clone());
+        Ok(())
+    }
+}
+
+// tx_record returns a tx with two instructions, two logs, a decoded and a raw event
+// and a decoded instruction of the program `prog`
+pub fn tx_record(sig: &str, slot: u64) -> TxRecord {
+    TxRecord {
+        slot,
+        signature: sig.to_string(),
+        block_time: Some(1_700_000_000),
+        fee: 5000,
+        err: None,
+        accounts: vec![
+            "payer".to_string(),
+            "prog".to_string(),
+            "lookup".to_string(),
+        ],
+        instructions: vec![
+            InstructionRecord {
+                index: 0,
+                program_id: "caller".to_string(),
+                accounts: vec!["payer".to_string()],
+                data: Some("3Bxs4h24hBtQy9rw".to_string()),
+                stack_height: 1,
+                parent_index: None,
+            },
+            InstructionRecord {
+                index: 1,
+                program_id: "prog".to_string(),
+                accounts: vec!["payer".to_string(), "lookup".to_string()],
+                data: None,
+                stack_height: 2,
+                parent_index: Some(0),
+            },
+        ],
+        logs: vec![
+            LogRecord {
+                index: 0,
+                program_id: "prog".to_string(),
+                depth: 1,
+                log_type: Some(LogType::ProgramInvoke),
+                raw: "Program prog invoke [1]".to_string(),
+            },
+            LogRecord {
+                index: 1,
+                program_id: "prog".to_string(),
+                depth: 1,
+                log_type: Some(LogType::ProgramData),
+                raw: "Program data: AAAA".to_string(),
+            },
+        ],
+        events: vec![
+            Event {
+                slot,
+                signature: sig.to_string(),
+                program_id: "prog".to_string(),
+                log_index: 1,
+                ix_index: None,
+                payload: EventPayload::Decoded(serde_json::json!({
+                    "name": "CountChangeEvent",
+                    "data": 42,
+                    "label": "inc",
+                })),
+            },
+            Event {
+                slot,
+                signature: sig.to_string(),
+                program_id: "prog".to_string(),
+                log_index: 2,
+                ix_index: Some(1),
+                payload: EventPayload::Raw {
+                    data: vec!["AAAA".to_string()],
+                    error: "unknown discriminator".to_string(),
+                },
+            },
+        ],
+        program_instructions: vec![ProgramInstruction {
+            program_id: "prog".to_string(),
+            ix_index: 1,
+            payload: InstructionPayload::Decoded(serde_json::json!({
+                "name": "increment",
+                "args": {},
+                "accounts": {"counter": "payer", "authority": "lookup"},
+            })),
+        }],
+        failure: None,
+        incomplete: false,
+        balance_changes: Vec::new(),
+    }
+}
+
+// failed_tx_record is a tx_record that also has a failure and a balance change,
+// it fills every table of the database sinks
+pub fn failed_tx_record(sig: &str, slot: u64) -> TxRecord {
+    let mut tx = tx_record(sig, slot);
+    tx.err = Some("Error processing Instruction 0: custom program error: 0x1770".to_string());
+    tx.failure = Some(TxFailure {
+        kind: FailureKind::Anchor,
+        instruction_index: Some(0),
+        instruction: Some("increment".to_string()),
+        program_id: Some("prog".to_string()),
+        error_code: Some(6000),
+        error_name: Some("Overflow".to_string()),
+        error_message: Some("Counter overflow".to_string()),
+        account: None,
+    });
+    tx.balance_changes = vec![BalanceChange {
+        account: "payer".to_string(),
+        mint: None,
+        owner: None,
+        pre: 1_000_000,
+        post: 995_000,
+    }];
+    tx
+}
@@ -0,0 +1,233 @@
+// MockPubsub is an in-process websocket node for the LogSubscriber tests. The test drives it through
+// a channel: log notifications and dropped connections are broadcast to the open connections.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::broadcast};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+// HEARTBEAT_INTERVAL is the time between two slot notifications
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug)]
+enum Command {
+    Logs {
+        addr: String,
+        slot: u64,
+        signature: String,
+    },
+    Disconnect,
+}
+
+#[derive(Default)]
+struct PubsubState {
+    connections: usize,
+    // subscribed are the addresses with a logs subscription on an open connection
+    subscribed: Vec<String>,
+    // rejected are the addresses whose logs subscriptions fail
+    rejected: HashSet<String>,
+    // silent connections send no slot notifications, they go stale
+    silent: bool,
+}
+
+enum Subscription {
+    Slot,
+    Logs(String),
+}
+
+pub struct MockPubsub {
+    pub url: String,
+    state: Arc<Mutex<PubsubState>>,
+    commands: broadcast::Sender<Command>,
+}
+
+impl MockPubsub {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(PubsubState::default()));
+        let (commands, _) = broadcast::channel(1024);
+        let (accept_state, accept_commands) = (state.clone(), commands.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (state, commands) = (accept_state.clone(), accept_commands.subscribe());
+                tokio::spawn(async move {
+                    if let Ok(ws) = accept_async(stream).await {
+                        serve(ws, state, commands).await;
+                    }
+                });
+            }
+        });
+        Self {
+            url,
+            state,
+            commands,
+        }
+    }
+
+    // logs notifies the subscriptions of an address of a tx
+    pub fn logs(&self, addr: &str, slot: u64, signature: &str) {
+        let _ = self.commands.send(Command::Logs {
+            addr: addr.to_string(),
+            slot,
+            signature: signature.to_string(),
+        });
+    }
+
+    // disconnect drops the open connections without closing them
+    pub fn disconnect(&self) {
+        let _ = self.commands.send(Command::Disconnect);
+    }
+
+    // reject makes the logs subscriptions of an address fail, or succeed again
+    pub fn reject(&self, addr: &str, rejected: bool) {
+        let mut state = self.state.lock().unwrap();
+        match rejected {
+            true => state.rejected.insert(addr.to_string()),
+            false => state.rejected.remove(addr),
+        };
+    }
+
+    // silence stops the slot notifications of the connections, or starts them again
+    pub fn silence(&self, silent: bool) {
+        self.state.lock().unwrap().silent = silent;
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    // subscribed returns the addresses with a logs subscription, sorted
+    pub fn subscribed(&self) -> Vec<String> {
+        let mut subscribed = self.state.lock().unwrap().subscribed.clone();
+        subscribed.sort();
+        subscribed
+    }
+}
+
+async fn serve<S>(
+    ws: tokio_tungstenite::WebSocketStream<S>,
+    state: Arc<Mutex<PubsubState>>,
+    mut commands: broadcast::Receiver<Command>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    state.lock().unwrap().connections += 1;
+    let (mut sink, mut stream) = ws.split();
+    let mut subscriptions: HashMap<u64, Subscription> = HashMap::new();
+    let mut next_id = 1u64;
+    let mut slot = 0u64;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        let message = tokio::select! {
+            request = stream.next() => {
+                let Some(Ok(Message::Text(text))) = request else {
+                    match request {
+                        Some(Ok(_)) => continue,
+                        _ => break,
+                    }
+                };
+                let request: Value = serde_json::from_str(&text).unwrap();
+                let method = request["method"].as_str().unwrap_or_default();
+                match method {
+                    "slotSubscribe" | "logsSubscribe" => {
+                        let addr = request["params"][0]["mentions"][0]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string();
+                        if method == "logsSubscribe" && state.lock().unwrap().rejected.contains(&addr) {
+                            json!({
+                                "jsonrpc": "2.0",
+                                "error": {"code": -32602, "message": "subscription rejected"},
+                                "id": request["id"],
+                            })
+                        } else {
+                            let id = next_id;
+                            next_id += 1;
+                            let subscription = match method {
+                                "slotSubscribe" => Subscription::Slot,
+                                _ => {
+                                    state.lock().unwrap().subscribed.push(addr.clone());
+                                    Subscription::Logs(addr)
+                                }
+                            };
+                            subscriptions.insert(id, subscription);
+                            json!({"jsonrpc": "2.0", "result": id, "id": request["id"]})
+                        }
+                    }
+                    _ => {
+                        let id = request["params"][0].as_u64().unwrap_or_default();
+                        if let Some(Subscription::Logs(addr)) = subscriptions.remove(&id) {
+                            unsubscribe(&state, &addr);
+                        }
+                        json!({"jsonrpc": "2.0", "result": true, "id": request["id"]})
+                    }
+                }
+            }
+            command = commands.recv() => match command {
+                Ok(Command::Logs { addr, slot, signature }) => {
+                    let Some(id) = subscriptions.iter().find_map(|(id, subscription)| match subscription {
+                        Subscription::Logs(a) if *a == addr => Some(*id),
+                        _ => None,
+                    }) else {
+                        continue;
+                    };
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "logsNotification",
+                        "params": {
+                            "result": {
+                                "context": {"slot": slot},
+                                "value": {"signature": signature, "err": null, "logs": []},
+                            },
+                            "subscription": id,
+                        },
+                    })
+                }
+                Ok(Command::Disconnect) | Err(_) => break,
+            },
+            _ = heartbeat.tick() => {
+                let silent = state.lock().unwrap().silent;
+                let Some(id) = subscriptions.iter().find_map(|(id, subscription)| match subscription {
+                    Subscription::Slot => Some(*id),
+                    _ => None,
+                }) else {
+                    continue;
+                };
+                if silent {
+                    continue;
+                }
+                slot += 1;
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "slotNotification",
+                    "params": {
+                        "result": {"parent": slot - 1, "root": slot.saturating_sub(32), "slot": slot},
+                        "subscription": id,
+                    },
+                })
+            }
+        };
+        if sink.send(Message::Text(message.to_string())).await.is_err() {
+            break;
+        }
+    }
+    state.lock().unwrap().connections -= 1;
+    for subscription in subscriptions.into_values() {
+        if let Subscription::Logs(addr) = subscription {
+            unsubscribe(&state, &addr);
+        }
+    }
+}
+
+fn unsubscribe(state: &Mutex<PubsubState>, addr: &str) {
+    let mut state = state.lock().unwrap();
+    if let Some(i) = state.subscribed.iter().position(|a| a == addr) {
+        state.subscribed.remove(i);
+    }
+}
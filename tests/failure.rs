@@ -0,0 +1,60 @@
+// Tests for the classification of failed transactions and the parsing of AnchorError logs
+use solana_indexer::failure::{parse_anchor_error, AnchorErrorLog, FailureKind, TxFailure};
+use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+
+#[test]
+fn anchor_error_logs_are_parsed() {
+    let cases = [
+        (
+            "AnchorError occurred. Error Code: Overflow. Error Number: 6000. Error Message: Counter overflow.",
+            None,
+        ),
+        (
+            "AnchorError thrown in programs/helloworld/src/lib.rs:21. Error Code: Overflow. Error Number: 6000. Error Message: Counter overflow.",
+            None,
+        ),
+        (
+            "AnchorError caused by account: counter. Error Code: Overflow. Error Number: 6000. Error Message: Counter overflow.",
+            Some("counter".to_string()),
+        ),
+    ];
+    for (message, account) in cases {
+        assert_eq!(
+            parse_anchor_error(message),
+            Some(AnchorErrorLog {
+                code: "Overflow".to_string(),
+                number: 6000,
+                message: "Counter overflow".to_string(),
+                account,
+            }),
+            "{}",
+            message
+        );
+    }
+    assert_eq!(parse_anchor_error("Instruction: Increment"), None);
+}
+
+#[test]
+fn errors_are_classified_by_their_origin() {
+    let custom = TxFailure::new(&TransactionError::InstructionError(
+        1,
+        InstructionError::Custom(6000),
+    ));
+    assert_eq!(custom.kind, FailureKind::Custom);
+    assert_eq!(custom.instruction_index, Some(1));
+    assert_eq!(custom.error_code, Some(6000));
+    // the message of a custom error comes from the logs or the IDL
+    assert_eq!(custom.error_message, None);
+
+    let instruction = TxFailure::new(&TransactionError::InstructionError(
+        0,
+        InstructionError::MissingRequiredSignature,
+    ));
+    assert_eq!(instruction.kind, FailureKind::Instruction);
+    assert_eq!(instruction.error_code, None);
+    assert!(instruction.error_message.is_some());
+
+    let transaction = TxFailure::new(&TransactionError::InsufficientFundsForFee);
+    assert_eq!(transaction.kind, FailureKind::Transaction);
+    assert_eq!(transaction.instruction_index, None);
+}
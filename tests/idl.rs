@@ -0,0 +1,270 @@
+// Tests for decoding events and instructions with current (>= 0.30) and legacy Anchor IDLs
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::json;
+use solana_indexer::idl::{discriminator, IdlDecoder, IdlError};
+
+const IDL: &str = r#"{
+    "address": "8weB5xqS5jbQzxmHEr2e79UUSYur6QpFwkMtdGezgtPy",
+    "metadata": {"name": "helloworld"},
+    "instructions": [
+        {
+            "name": "create",
+            "discriminator": [24, 30, 200, 40, 5, 28, 7, 119],
+            "accounts": [
+                {"name": "counter", "writable": true, "signer": true},
+                {"name": "user", "writable": true, "signer": true},
+                {"name": "system_program", "address": "11111111111111111111111111111111"}
+            ],
+            "args": [{"name": "authority", "type": "pubkey"}]
+        },
+        {
+            "name": "increment",
+            "discriminator": [11, 18, 104, 9, 104, 174, 59, 33],
+            "accounts": [{"name": "counter", "writable": true}, {"name": "authority", "signer": true}],
+            "args": [{"name": "by", "type": {"option": "u64"}}]
+        }
+    ],
+    "events": [
+        {"name": "CountChangeEvent", "discriminator": [201, 182, 99, 71, 80, 187, 120, 4]},
+        {"name": "Snapshot", "discriminator": [1, 2, 3, 4, 5, 6, 7, 8]},
+        {"name": "Empties", "discriminator": [8, 7, 6, 5, 4, 3, 2, 1]}
+    ],
+    "types": [
+        {"name": "CountChangeEvent", "type": {"kind": "struct", "fields": [
+            {"name": "data", "type": "u64"},
+            {"name": "label", "type": "string"}
+        ]}},
+        {"name": "Snapshot", "type": {"kind": "struct", "fields": [
+            {"name": "owner", "type": {"option": "pubkey"}},
+            {"name": "state", "type": {"defined": {"name": "State"}}},
+            {"name": "values", "type": {"vec": "u64"}},
+            {"name": "big", "type": "u128"}
+        ]}},
+        {"name": "State", "type": {"kind": "enum", "variants": [
+            {"name": "Idle"},
+            {"name": "Running", "fields": [{"name": "since", "type": "i64"}]},
+            {"name": "Paused", "fields": ["u8", "bool"]}
+        ]}},
+        {"name": "Empty", "type": {"kind": "struct", "fields": []}},
+        {"name": "Empties", "type": {"kind": "struct", "fields": [
+            {"name": "items", "type": {"vec": {"defined": {"name": "Empty"}}}}
+        ]}}
+    ]
+}"#;
+
+const LEGACY_IDL: &str = r#"{
+    "version": "0.1.0",
+    "name": "helloworld",
+    "instructions": [{
+        "name": "initializeCounter",
+        "accounts": [
+            {"name": "counter", "isMut": true, "isSigner": false},
+            {"name": "group", "accounts": [{"name": "authority", "isMut": false, "isSigner": true}]}
+        ],
+        "args": [{"name": "start", "type": "u64"}, {"name": "tags", "type": {"vec": "string"}}]
+    }],
+    "events": [{"name": "CountChangeEvent", "fields": [
+        {"name": "data", "type": "u64", "index": false},
+        {"name": "label", "type": "string", "index": false}
+    ]}]
+}"#;
+
+fn count_change(data: u64, label: &str) -> Vec<u8> {
+    let mut payload = discriminator("event", "CountChangeEvent").to_vec();
+    payload.extend(data.to_le_bytes());
+    payload.extend((label.len() as u32).to_le_bytes());
+    payload.extend(label.as_bytes());
+    payload
+}
+
+#[test]
+fn events_are_decoded_with_both_idl_formats() {
+    let payload = count_change(42, "inc");
+    for idl in [IDL, LEGACY_IDL] {
+        let decoder = IdlDecoder::from_json(idl).unwrap();
+        let event = decoder
+            .decode_event_data(&[BASE64.encode(&payload)])
+            .unwrap();
+        assert_eq!(
+            event.to_json(),
+            json!({"name": "CountChangeEvent", "data": 42, "label": "inc"})
+        );
+    }
+}
+
+#[test]
+fn instructions_are_decoded_with_both_idl_formats() {
+    let decoder = IdlDecoder::from_json(IDL).unwrap();
+    let mut data = vec![11, 18, 104, 9, 104, 174, 59, 33, 1];
+    data.extend(5u64.to_le_bytes());
+    let accounts = ["c".to_string(), "a".to_string(), "extra".to_string()];
+    let ix = decoder.decode_instruction(&data, &accounts).unwrap();
+    assert_eq!(ix.name, "increment");
+    assert_eq!(ix.args["by"], json!(5));
+    assert_eq!(
+        ix.accounts,
+        vec![
+            ("counter".to_string(), "c".to_string()),
+            ("authority".to_string(), "a".to_string())
+        ]
+    );
+    assert_eq!(ix.remaining_accounts, vec!["extra".to_string()]);
+
+    // legacy idls have no discriminators, anchor hashes the snake_case name
+    let decoder = IdlDecoder::from_json(LEGACY_IDL).unwrap();
+    let mut data = discriminator("global", "initialize_counter").to_vec();
+    data.extend(7u64.to_le_bytes());
+    data.extend(1u32.to_le_bytes());
+    data.extend(2u32.to_le_bytes());
+    data.extend(b"hi");
+    let ix = decoder.decode_instruction(&data, &accounts[..2]).unwrap();
+    assert_eq!(ix.name, "initializeCounter");
+    assert_eq!(ix.args["start"], json!(7));
+    assert_eq!(ix.args["tags"], json!(["hi"]));
+    assert_eq!(
+        ix.accounts[1],
+        ("group.authority".to_string(), "a".to_string())
+    );
+}
+
+#[test]
+fn create_is_decoded_with_its_labeled_accounts() {
+    let decoder = IdlDecoder::from_json(IDL).unwrap();
+    let authority = [9u8; 32];
+    let data = [discriminator("global", "create").as_slice(), &authority].concat();
+    let accounts = [
+        "counter-key",
+        "user-key",
+        "11111111111111111111111111111111",
+    ]
+    .map(str::to_string);
+    let ix = decoder.decode_instruction(&data, &accounts).unwrap();
+    assert_eq!(
+        ix.to_json(),
+        json!({
+            "name": "create",
+            "args": {"authority": bs58::encode(authority).into_string()},
+            "accounts": {
+                "counter": "counter-key",
+                "user": "user-key",
+                "system_program": "11111111111111111111111111111111",
+            },
+        })
+    );
+}
+
+#[test]
+fn options_enums_and_vecs_are_decoded() {
+    let decoder = IdlDecoder::from_json(IDL).unwrap();
+    let owner = [7u8; 32];
+    let variants = [
+        (vec![0], json!("Idle")),
+        (
+            [vec![1], (-5i64).to_le_bytes().to_vec()].concat(),
+            json!({"Running": {"since": -5}}),
+        ),
+        (vec![2, 9, 1], json!({"Paused": [9, true]})),
+    ];
+    for (state, expected) in variants {
+        let mut payload = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        payload.push(1);
+        payload.extend(owner);
+        payload.extend(state);
+        payload.extend(2u32.to_le_bytes());
+        payload.extend(1u64.to_le_bytes());
+        payload.extend(u64::MAX.to_le_bytes());
+        payload.extend(u128::MAX.to_le_bytes());
+        let event = decoder.decode_event(&payload).unwrap();
+        assert_eq!(
+            event.fields["owner"],
+            json!(solana_sdk::pubkey::Pubkey::new_from_array(owner).to_string())
+        );
+        assert_eq!(event.fields["state"], expected);
+        assert_eq!(event.fields["values"], json!([1, u64::MAX]));
+        assert_eq!(event.fields["big"], json!(u128::MAX.to_string()));
+    }
+
+    let mut payload = vec![1, 2, 3, 4, 5, 6, 7, 8, 0, 0];
+    payload.extend(0u32.to_le_bytes());
+    payload.extend(0u128.to_le_bytes());
+    let event = decoder.decode_event(&payload).unwrap();
+    assert_eq!(event.fields["owner"], json!(null));
+    assert_eq!(event.fields["values"], json!([]));
+}
+
+#[test]
+fn invalid_payloads_are_rejected() {
+    let decoder = IdlDecoder::from_json(IDL).unwrap();
+    let payload = count_change(42, "inc");
+    assert_eq!(
+        decoder
+            .decode_event(&payload[..payload.len() - 1])
+            .unwrap_err(),
+        IdlError::UnexpectedEof("string".to_string())
+    );
+    assert_eq!(
+        decoder.decode_event(&payload[..4]).unwrap_err(),
+        IdlError::MissingDiscriminator(4)
+    );
+    assert_eq!(
+        decoder.decode_event(&[0; 12]).unwrap_err(),
+        IdlError::UnknownDiscriminator([0; 8])
+    );
+    let mut payload = vec![1, 2, 3, 4, 5, 6, 7, 8, 0, 3];
+    assert_eq!(
+        decoder.decode_event(&payload).unwrap_err(),
+        IdlError::InvalidData("State".to_string(), "variant 3".to_string())
+    );
+
+    // the length of a vec can't exceed the remaining bytes
+    payload[9] = 0;
+    payload.extend(u32::MAX.to_le_bytes());
+    payload.extend([0; 64]);
+    assert_eq!(
+        decoder.decode_event(&payload).unwrap_err(),
+        IdlError::UnexpectedEof("vec".to_string())
+    );
+    // zero-sized elements don't consume any bytes, their vecs are rejected
+    let mut payload = vec![8, 7, 6, 5, 4, 3, 2, 1];
+    payload.extend(u32::MAX.to_le_bytes());
+    assert!(matches!(
+        decoder.decode_event(&payload).unwrap_err(),
+        IdlError::InvalidData(what, _) if what == "vec"
+    ));
+    let mut payload = vec![8, 7, 6, 5, 4, 3, 2, 1];
+    payload.extend(0u32.to_le_bytes());
+    assert_eq!(
+        decoder.decode_event(&payload).unwrap().fields["items"],
+        json!([])
+    );
+}
+
+#[test]
+fn cyclic_types_are_rejected() {
+    let idl = r#"{
+        "address": "8weB5xqS5jbQzxmHEr2e79UUSYur6QpFwkMtdGezgtPy",
+        "metadata": {"name": "cycles"},
+        "instructions": [],
+        "events": [
+            {"name": "Alias", "discriminator": [1, 1, 1, 1, 1, 1, 1, 1]},
+            {"name": "Ping", "discriminator": [2, 2, 2, 2, 2, 2, 2, 2]}
+        ],
+        "types": [
+            {"name": "Alias", "type": {"kind": "type", "alias": {"defined": {"name": "Alias"}}}},
+            {"name": "Ping", "type": {"kind": "struct", "fields": [
+                {"name": "pong", "type": {"defined": {"name": "Pong"}}}
+            ]}},
+            {"name": "Pong", "type": {"kind": "struct", "fields": [
+                {"name": "ping", "type": {"defined": {"name": "Ping"}}}
+            ]}}
+        ]
+    }"#;
+    let decoder = IdlDecoder::from_json(idl).unwrap();
+    for disc in [1u8, 2] {
+        let payload = [[disc; 8].as_slice(), &[0; 16]].concat();
+        assert!(matches!(
+            decoder.decode_event(&payload).unwrap_err(),
+            IdlError::InvalidData(_, reason) if reason.starts_with("nested deeper than")
+        ));
+    }
+}
@@ -0,0 +1,546 @@
+// Tests for the EventLoader against an in-process rpc node
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{
+    invoke_tx, program_tx, signature, Failure, Instruction, Ledger, MemoryCheckpoints, MemorySink,
+    MockRpc, MockTx,
+};
+use serde_json::json;
+use solana_client::rpc_response::RpcLogsResponse;
+use solana_indexer::{
+    checkpoint::Checkpoint,
+    failure::{FailureKind, TxFailure},
+    idl::{discriminator, IdlDecoder, EVENT_AUTHORITY_SEED, EVENT_IX_TAG_LE},
+    log_events::{
+        EventLoader, InstructionPayload, LogType, ProgramConfig, TxRecord, TRUNCATED_LOG,
+    },
+    log_subscriber::LogNotification,
+    retry::RetryBudget,
+    rpc::RpcClientWrapper,
+    sink::CommitStatus,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+struct Harness {
+    rpc: MockRpc,
+    sink: Arc<MemorySink>,
+    checkpoints: Arc<MemoryCheckpoints>,
+    loader: EventLoader,
+}
+
+// HELLOWORLD_IDL is the IDL of programs/localnet with a custom error
+const HELLOWORLD_IDL: &str = r#"{
+    "address": "8weB5xqS5jbQzxmHEr2e79UUSYur6QpFwkMtdGezgtPy",
+    "metadata": {"name": "helloworld"},
+    "instructions": [
+        {
+            "name": "create",
+            "accounts": [{"name": "counter"}, {"name": "user"}, {"name": "system_program"}],
+            "args": [{"name": "authority", "type": "pubkey"}]
+        },
+        {
+            "name": "increment",
+            "accounts": [{"name": "counter"}, {"name": "authority"}],
+            "args": []
+        }
+    ],
+    "errors": [{"code": 6000, "name": "Overflow", "msg": "Counter overflow"}]
+}"#;
+
+fn program_config(program: &str) -> ProgramConfig {
+    let initial = Checkpoint::new(
+        0,
+        Signature::default().to_string(),
+        0,
+        Signature::default().to_string(),
+    );
+    ProgramConfig::new(program.to_string(), initial)
+}
+
+async fn harness(program: &str, ledger: Ledger, batch_size: usize) -> Harness {
+    harness_with(program_config(program), ledger, batch_size).await
+}
+
+async fn harness_with(config: ProgramConfig, ledger: Ledger, batch_size: usize) -> Harness {
+    let rpc = MockRpc::start(ledger).await;
+    let sink = Arc::new(MemorySink::default());
+    let checkpoints = Arc::new(MemoryCheckpoints::default());
+    let loader = EventLoader::new(
+        vec![config],
+        batch_size,
+        RpcClientWrapper::new(rpc.url.clone()),
+        sink.clone(),
+        checkpoints.clone(),
+    )
+    .await
+    .unwrap();
+    Harness {
+        rpc,
+        sink,
+        checkpoints,
+        loader,
+    }
+}
+
+#[tokio::test]
+async fn dropped_txs_are_retracted_when_the_tail_cursor_passes_the_target() {
+    let program = Pubkey::new_unique().to_string();
+    let dropped = signature();
+    let mut ledger = Ledger {
+        confirmed_slot: 300,
+        finalized_slot: 1000,
+        txs: vec![],
+    };
+    ledger.push(invoke_tx(&dropped, 100, &program, false));
+    let h = harness(&program, ledger, 10).await;
+
+    h.loader.poll().await.unwrap();
+    assert_eq!(
+        h.sink.written(CommitStatus::Confirmed),
+        vec![dropped.clone()]
+    );
+    assert!(h.sink.retracted().is_empty());
+
+    // the finalized tx is newer than the finalized target of the poll (2500 - 1000),
+    // so the finalized pass ends on the loop condition and not on an empty listing
+    let finalized = signature();
+    {
+        let mut ledger = h.rpc.ledger.lock().unwrap();
+        ledger.push(invoke_tx(&finalized, 2000, &program, true));
+        ledger.confirmed_slot = 2600;
+        ledger.finalized_slot = 2500;
+    }
+    h.loader.poll().await.unwrap();
+    assert_eq!(
+        h.sink.written(CommitStatus::Finalized),
+        vec![finalized.clone()]
+    );
+    assert_eq!(h.sink.retracted(), vec![dropped]);
+    let checkpoint = h.checkpoints.checkpoints.lock().unwrap()[&program].clone();
+    assert_eq!(checkpoint.tail_slot, 2000);
+    assert_eq!(checkpoint.tail_sig, finalized);
+}
+
+#[tokio::test]
+async fn txs_finalized_before_they_are_confirmed_are_not_retracted() {
+    let program = Pubkey::new_unique().to_string();
+    let finalized = signature();
+    let mut ledger = Ledger {
+        confirmed_slot: 1300,
+        finalized_slot: 1200,
+        txs: vec![],
+    };
+    ledger.push(invoke_tx(&finalized, 100, &program, true));
+    let h = harness(&program, ledger, 10).await;
+
+    // the finalized pass runs first, the confirmed pass writes the tx again
+    h.loader.poll().await.unwrap();
+    assert_eq!(
+        h.sink.written(CommitStatus::Finalized),
+        vec![finalized.clone()]
+    );
+    assert_eq!(h.sink.written(CommitStatus::Confirmed), vec![finalized]);
+
+    h.rpc.ledger.lock().unwrap().finalized_slot = 2000;
+    h.loader.poll().await.unwrap();
+    assert!(h.sink.retracted().is_empty());
+}
+
+#[tokio::test]
+async fn history_is_written_page_by_page_oldest_first() {
+    let program = Pubkey::new_unique().to_string();
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 5000,
+        txs: vec![],
+    };
+    let sigs = (0..10).map(|_| signature()).collect::<Vec<_>>();
+    for (i, sig) in sigs.iter().enumerate() {
+        ledger.push(invoke_tx(sig, 100 + i as u64 * 10, &program, true));
+    }
+    let h = harness(&program, ledger, 3).await;
+
+    h.loader.backfill(4000).await.unwrap();
+    assert_eq!(h.sink.written(CommitStatus::Finalized), sigs);
+    // the pages are cut from the newest tx: [9, 8, 7], [6, 5, 4], [3, 2, 1], [0]
+    let batches = h.sink.batches.lock().unwrap().clone();
+    let sizes = batches
+        .iter()
+        .map(|batch| batch.txs.len())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![1, 3, 3, 3]);
+    let checkpoint = batches[0].checkpoint.as_ref().unwrap();
+    assert_eq!(checkpoint.tail_sig, sigs[0]);
+    // 4 pages down to the cursor, the 3 pages above read again on the way up
+    // and the empty listing above the last tx
+    assert_eq!(h.rpc.requests("getSignaturesForAddress").len(), 8);
+}
+
+// self_cpi_tx indexes a tx of a program whose event authority is the third key and another
+// program is the fourth. It returns the ix indexes of the events and of the program instructions.
+async fn self_cpi_tx(
+    instructions: Vec<Instruction>,
+    inner: Vec<Vec<Instruction>>,
+) -> (Vec<Option<usize>>, Vec<usize>) {
+    let pk = Pubkey::new_unique();
+    let program = pk.to_string();
+    let authority = Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &pk).0;
+    let keys = vec![
+        Pubkey::new_unique().to_string(),
+        program.clone(),
+        authority.to_string(),
+        Pubkey::new_unique().to_string(),
+    ];
+    let sig = signature();
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 5000,
+        txs: vec![],
+    };
+    ledger.push(MockTx {
+        signature: sig.clone(),
+        slot: 100,
+        finalized: true,
+        tx: program_tx(&sig, 100, &keys, instructions, inner, vec![]),
+    });
+    let h = harness(&program, ledger, 10).await;
+
+    h.loader.backfill(4000).await.unwrap();
+    let batches = h.sink.batches.lock().unwrap().clone();
+    let tx = &batches[0].txs[0];
+    let events = tx.events.iter().map(|event| event.ix_index).collect();
+    let instructions = tx
+        .program_instructions
+        .iter()
+        .map(|ix| ix.ix_index)
+        .collect();
+    (events, instructions)
+}
+
+// ix is an instruction of the program at the key index with a single account
+fn ix(program: usize, account: usize, data: &[u8]) -> Instruction {
+    Instruction {
+        program,
+        accounts: vec![account],
+        data: data.to_vec(),
+    }
+}
+
+fn event_data() -> Vec<u8> {
+    [EVENT_IX_TAG_LE.as_slice(), &[1, 2, 3, 4]].concat()
+}
+
+#[tokio::test]
+async fn self_cpis_of_the_event_authority_are_events() {
+    let (events, instructions) =
+        self_cpi_tx(vec![ix(1, 0, &[9])], vec![vec![ix(1, 2, &event_data())]]).await;
+    assert_eq!(events, vec![Some(1)]);
+    assert_eq!(instructions, vec![0]);
+}
+
+#[tokio::test]
+async fn tagged_top_level_instructions_are_not_events() {
+    // forged with the event authority as first account
+    let (events, instructions) = self_cpi_tx(vec![ix(1, 2, &event_data())], vec![vec![]]).await;
+    assert!(events.is_empty());
+    assert_eq!(instructions, vec![0]);
+}
+
+#[tokio::test]
+async fn tagged_cpis_from_other_programs_are_not_events() {
+    let (events, instructions) =
+        self_cpi_tx(vec![ix(3, 0, &[9])], vec![vec![ix(1, 2, &event_data())]]).await;
+    assert!(events.is_empty());
+    assert_eq!(instructions, vec![1]);
+}
+
+#[tokio::test]
+async fn tagged_self_cpis_without_the_event_authority_are_not_events() {
+    let (events, instructions) =
+        self_cpi_tx(vec![ix(1, 0, &[9])], vec![vec![ix(1, 0, &event_data())]]).await;
+    assert!(events.is_empty());
+    assert_eq!(instructions, vec![0, 1]);
+}
+
+#[tokio::test]
+async fn notifications_are_written_in_order_within_their_retry_budget() {
+    let program = Pubkey::new_unique().to_string();
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 4000,
+        txs: vec![],
+    };
+    let sigs = (0..4).map(|_| signature()).collect::<Vec<_>>();
+    for (i, sig) in sigs.iter().enumerate() {
+        // the third tx is never available
+        if i != 2 {
+            ledger.push(invoke_tx(sig, 4500 + i as u64, &program, false));
+        }
+    }
+    let mut h = harness(&program, ledger, 10).await;
+    h.loader = h
+        .loader
+        .with_notification_budget(RetryBudget::new(2, Duration::from_secs(5)));
+    // one of the requests is held back by the server for a second
+    h.rpc.fail([Failure {
+        status: 429,
+        retry_after: Some(1),
+    }]);
+    let notifications = sigs
+        .iter()
+        .enumerate()
+        .map(|(i, sig)| {
+            let raw = RpcLogsResponse {
+                signature: sig.clone(),
+                err: None,
+                logs: vec![],
+            };
+            LogNotification::new(raw, program.clone(), 4500 + i as u64)
+        })
+        .collect::<Vec<_>>();
+
+    let start = std::time::Instant::now();
+    h.loader
+        .process_notifications(futures_util::stream::iter(notifications))
+        .await;
+    assert!(start.elapsed() < Duration::from_secs(2));
+    let written = h.sink.written(CommitStatus::Confirmed);
+    assert_eq!(
+        written,
+        vec![sigs[0].clone(), sigs[1].clone(), sigs[3].clone()]
+    );
+    // the missing tx is given up after the notification budget, the poll writes it later
+    let missing = h
+        .rpc
+        .requests("getTransaction")
+        .iter()
+        .filter(|params| params[0] == sigs[2].as_str())
+        .count();
+    assert_eq!(missing, 2);
+}
+
+#[tokio::test]
+async fn instructions_are_decoded_with_the_idl_also_when_invoked_through_a_cpi() {
+    let program = Pubkey::new_unique();
+    let system_program = "11111111111111111111111111111111".to_string();
+    let keys = vec![
+        Pubkey::new_unique().to_string(),
+        program.to_string(),
+        Pubkey::new_unique().to_string(),
+        system_program.clone(),
+        Pubkey::new_unique().to_string(),
+    ];
+    let authority = Pubkey::new_unique();
+    let create = Instruction {
+        program: 1,
+        accounts: vec![2, 0, 3],
+        data: [
+            discriminator("global", "create").as_slice(),
+            &authority.to_bytes(),
+        ]
+        .concat(),
+    };
+    // the second top-level instruction is another program that calls create
+    let caller = Instruction {
+        program: 4,
+        accounts: vec![0],
+        data: vec![1],
+    };
+    let sig = signature();
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 5000,
+        txs: vec![],
+    };
+    ledger.push(MockTx {
+        signature: sig.clone(),
+        slot: 100,
+        finalized: true,
+        tx: program_tx(
+            &sig,
+            100,
+            &keys,
+            vec![create.clone(), caller],
+            vec![vec![], vec![create]],
+            vec![],
+        ),
+    });
+    let config = program_config(&program.to_string())
+        .with_idl(IdlDecoder::from_json(HELLOWORLD_IDL).unwrap());
+    let h = harness_with(config, ledger, 10).await;
+
+    h.loader.backfill(4000).await.unwrap();
+    let batches = h.sink.batches.lock().unwrap().clone();
+    let instructions = &batches[0].txs[0].program_instructions;
+    assert_eq!(
+        instructions
+            .iter()
+            .map(|ix| ix.ix_index)
+            .collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    let expected = json!({
+        "name": "create",
+        "args": {"authority": authority.to_string()},
+        "accounts": {"counter": keys[2], "user": keys[0], "system_program": system_program},
+    });
+    for ix in instructions {
+        assert_eq!(ix.program_id, program.to_string());
+        assert_eq!(ix.payload, InstructionPayload::Decoded(expected.clone()));
+    }
+}
+
+#[tokio::test]
+async fn txs_with_truncated_logs_are_flagged_incomplete() {
+    let program = Pubkey::new_unique().to_string();
+    let (truncated, complete) = (signature(), signature());
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 5000,
+        txs: vec![],
+    };
+    let mut tx = invoke_tx(&truncated, 100, &program, true);
+    tx.tx["meta"]["logMessages"] = json!([
+        format!("Program {} invoke [1]", program),
+        "Program log: hello",
+        TRUNCATED_LOG,
+    ]);
+    tx.tx["meta"]["preBalances"] = json!([1_000_000, 1]);
+    tx.tx["meta"]["postBalances"] = json!([995_000, 1]);
+    ledger.push(tx);
+    ledger.push(invoke_tx(&complete, 101, &program, true));
+    let mut h = harness(&program, ledger, 10).await;
+    h.loader = h.loader.with_truncation_recovery(true);
+
+    h.loader.backfill(4000).await.unwrap();
+    let batches = h.sink.batches.lock().unwrap().clone();
+    let txs = &batches[0].txs;
+    let tx = txs.iter().find(|tx| tx.signature == truncated).unwrap();
+    assert!(tx.incomplete);
+    let last = tx.logs.last().unwrap();
+    assert_eq!(last.log_type, Some(LogType::LogTruncated));
+    assert_eq!(last.program_id, program);
+    // the balance changes of incomplete txs are recovered
+    assert_eq!(tx.balance_changes.len(), 1);
+    assert_eq!(tx.balance_changes[0].post, 995_000);
+
+    let tx = txs.iter().find(|tx| tx.signature == complete).unwrap();
+    assert!(!tx.incomplete);
+    assert!(tx.balance_changes.is_empty());
+}
+
+// failed_tx indexes a failed tx with a single top-level instruction of the program,
+// the program has the helloworld IDL and the instruction calls another program
+async fn failed_tx(
+    program: &Pubkey,
+    callee: &Pubkey,
+    data: &[u8],
+    err: serde_json::Value,
+    logs: Vec<String>,
+) -> TxRecord {
+    let keys = vec![
+        Pubkey::new_unique().to_string(),
+        program.to_string(),
+        callee.to_string(),
+    ];
+    let top_level = Instruction {
+        program: 1,
+        accounts: vec![0],
+        data: data.to_vec(),
+    };
+    let cpi = Instruction {
+        program: 2,
+        accounts: vec![0],
+        data: vec![],
+    };
+    let sig = signature();
+    let mut tx = program_tx(&sig, 100, &keys, vec![top_level], vec![vec![cpi]], logs);
+    tx["meta"]["err"] = err.clone();
+    tx["meta"]["status"] = json!({ "Err": err });
+    let mut ledger = Ledger {
+        confirmed_slot: 5000,
+        finalized_slot: 5000,
+        txs: vec![],
+    };
+    ledger.push(MockTx {
+        signature: sig,
+        slot: 100,
+        finalized: true,
+        tx,
+    });
+    let config = program_config(&program.to_string())
+        .with_idl(IdlDecoder::from_json(HELLOWORLD_IDL).unwrap());
+    let h = harness_with(config, ledger, 10).await;
+
+    h.loader.backfill(4000).await.unwrap();
+    let batches = h.sink.batches.lock().unwrap().clone();
+    batches[0].txs[0].clone()
+}
+
+#[tokio::test]
+async fn anchor_errors_are_taken_from_the_log_of_the_failed_cpi() {
+    let (program, callee) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let logs = vec![
+        format!("Program {} invoke [1]", program),
+        "Program log: Instruction: Create".to_string(),
+        format!("Program {} invoke [2]", callee),
+        "Program log: AnchorError caused by account: counter. Error Code: ConstraintHasOne. Error Number: 2001. Error Message: A has one constraint was violated.".to_string(),
+        format!("Program {} consumed 2000 of 190000 compute units", callee),
+        format!("Program {} failed: custom program error: 0x7d1", callee),
+        format!("Program {} consumed 10000 of 200000 compute units", program),
+        format!("Program {} failed: custom program error: 0x7d1", program),
+    ];
+    let data = discriminator("global", "create")
+        .into_iter()
+        .chain(Pubkey::new_unique().to_bytes())
+        .collect::<Vec<_>>();
+    let err = json!({"InstructionError": [0, {"Custom": 2001}]});
+    let tx = failed_tx(&program, &callee, &data, err, logs).await;
+
+    assert_eq!(
+        tx.failure,
+        Some(TxFailure {
+            kind: FailureKind::Anchor,
+            instruction_index: Some(0),
+            instruction: Some("create".to_string()),
+            program_id: Some(callee.to_string()),
+            error_code: Some(2001),
+            error_name: Some("ConstraintHasOne".to_string()),
+            error_message: Some("A has one constraint was violated".to_string()),
+            account: Some("counter".to_string()),
+        })
+    );
+}
+
+#[tokio::test]
+async fn custom_errors_are_resolved_with_the_idl_of_the_failed_program() {
+    let (program, callee) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // the CPI succeeds, the program fails after it without an AnchorError log
+    let logs = vec![
+        format!("Program {} invoke [1]", program),
+        "Program log: Instruction: Increment".to_string(),
+        format!("Program {} invoke [2]", callee),
+        format!("Program {} success", callee),
+        format!("Program {} consumed 10000 of 200000 compute units", program),
+        format!("Program {} failed: custom program error: 0x1770", program),
+    ];
+    let data = discriminator("global", "increment");
+    let err = json!({"InstructionError": [0, {"Custom": 6000}]});
+    let tx = failed_tx(&program, &callee, &data, err, logs).await;
+
+    assert_eq!(
+        tx.failure,
+        Some(TxFailure {
+            kind: FailureKind::Custom,
+            instruction_index: Some(0),
+            instruction: Some("increment".to_string()),
+            program_id: Some(program.to_string()),
+            error_code: Some(6000),
+            error_name: Some("Overflow".to_string()),
+            error_message: Some("Counter overflow".to_string()),
+            account: None,
+        })
+    );
+}
@@ -0,0 +1,243 @@
+// Tests for the LogSubscriber against an in-process websocket node
+mod common;
+
+use std::time::Duration;
+
+use common::{pubsub::MockPubsub, signature};
+use futures_util::StreamExt;
+use solana_indexer::log_subscriber::{
+    LogNotification, LogStream, LogSubscriber, OverflowPolicy, SubscriberStatus,
+};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::broadcast;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// next_status waits for a status that matches, the statuses before it are skipped
+async fn next_status(
+    statuses: &mut broadcast::Receiver<SubscriberStatus>,
+    matches: impl Fn(&SubscriberStatus) -> bool,
+) -> SubscriberStatus {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let status = statuses.recv().await.unwrap();
+            if matches(&status) {
+                return status;
+            }
+        }
+    })
+    .await
+    .expect("status not reported")
+}
+
+async fn subscribed(statuses: &mut broadcast::Receiver<SubscriberStatus>, addr: &str) {
+    next_status(
+        statuses,
+        |status| matches!(status, SubscriberStatus::Subscribed { addr: a } if a == addr),
+    )
+    .await;
+}
+
+async fn next(stream: &mut LogStream) -> Option<LogNotification> {
+    tokio::time::timeout(TIMEOUT, stream.next())
+        .await
+        .expect("no notification")
+}
+
+fn subscriber(pubsub: &MockPubsub, addrs: &[&str]) -> LogSubscriber {
+    LogSubscriber::new(
+        pubsub.url.as_str(),
+        addrs.iter().map(|addr| addr.to_string()).collect(),
+    )
+    .with_reconnect_backoff(Duration::from_millis(10), Duration::from_millis(50))
+}
+
+fn addr() -> String {
+    Pubkey::new_unique().to_string()
+}
+
+#[tokio::test]
+async fn addresses_are_resubscribed_after_a_reconnect() {
+    let pubsub = MockPubsub::start().await;
+    let (a, b) = (addr(), addr());
+    let subscriber = subscriber(&pubsub, &[&a, &b]);
+    let mut statuses = subscriber.status();
+    let mut stream = subscriber.run().await.unwrap();
+
+    assert_eq!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Connecting { attempt: 0 }
+    );
+    assert_eq!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Connected
+    );
+    subscribed(&mut statuses, &a).await;
+    subscribed(&mut statuses, &b).await;
+    let sig = signature();
+    pubsub.logs(&a, 10, &sig);
+    let notification = next(&mut stream).await.unwrap();
+    assert_eq!(
+        (
+            notification.addr,
+            notification.slot,
+            notification.raw.signature
+        ),
+        (a.clone(), 10, sig)
+    );
+
+    // a dropped connection is reported once, then both addresses are subscribed again
+    pubsub.disconnect();
+    assert!(matches!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Disconnected { .. }
+    ));
+    assert_eq!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Connecting { attempt: 1 }
+    );
+    assert_eq!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Connected
+    );
+    subscribed(&mut statuses, &a).await;
+    subscribed(&mut statuses, &b).await;
+    assert_eq!(pubsub.connections(), 1);
+    pubsub.logs(&b, 11, &signature());
+    assert_eq!(next(&mut stream).await.unwrap().addr, b);
+
+    // a removed address is unsubscribed, the other subscription is kept
+    assert!(subscriber.remove_addr(&a));
+    assert_eq!(
+        next_status(&mut statuses, |_| true).await,
+        SubscriberStatus::Unsubscribed { addr: a.clone() }
+    );
+    tokio::time::timeout(TIMEOUT, async {
+        while pubsub.subscribed() != vec![b.clone()] {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // closing the subscriber ends the stream
+    subscriber.close().await;
+    next_status(&mut statuses, |status| *status == SubscriberStatus::Stopped).await;
+    assert!(next(&mut stream).await.is_none());
+}
+
+#[tokio::test]
+async fn stale_connections_are_reconnected() {
+    let pubsub = MockPubsub::start().await;
+    let a = addr();
+    let subscriber = subscriber(&pubsub, &[&a]).with_heartbeat_timeout(Duration::from_millis(300));
+    let mut statuses = subscriber.status();
+    let _stream = subscriber.run().await.unwrap();
+    subscribed(&mut statuses, &a).await;
+
+    // the slot notifications keep a quiet connection alive
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(
+        statuses.try_recv(),
+        Err(broadcast::error::TryRecvError::Empty)
+    );
+
+    pubsub.silence(true);
+    let SubscriberStatus::Disconnected { reason, .. } = next_status(&mut statuses, |status| {
+        matches!(status, SubscriberStatus::Disconnected { .. })
+    })
+    .await
+    else {
+        unreachable!()
+    };
+    assert!(reason.starts_with("no message received"), "{}", reason);
+    pubsub.silence(false);
+    next_status(&mut statuses, |status| {
+        *status == SubscriberStatus::Connected
+    })
+    .await;
+    subscribed(&mut statuses, &a).await;
+    subscriber.close().await;
+}
+
+#[tokio::test]
+async fn rejected_subscriptions_are_retried_on_the_same_connection() {
+    let pubsub = MockPubsub::start().await;
+    let (a, b) = (addr(), addr());
+    pubsub.reject(&a, true);
+    let subscriber = subscriber(&pubsub, &[&a, &b]);
+    let mut statuses = subscriber.status();
+    let _stream = subscriber.run().await.unwrap();
+
+    let failed = next_status(&mut statuses, |status| {
+        matches!(status, SubscriberStatus::SubscriptionFailed { .. })
+    })
+    .await;
+    assert!(matches!(failed, SubscriberStatus::SubscriptionFailed { addr, .. } if addr == a));
+    pubsub.reject(&a, false);
+    subscribed(&mut statuses, &a).await;
+    assert_eq!(pubsub.connections(), 1);
+    let mut expected = vec![a, b];
+    expected.sort();
+    assert_eq!(pubsub.subscribed(), expected);
+    subscriber.close().await;
+}
+
+// overflow sends 5 notifications to a subscriber with a buffer of 2 that isn't read meanwhile
+async fn overflow(policy: OverflowPolicy) -> (Vec<u64>, Vec<SubscriberStatus>) {
+    let pubsub = MockPubsub::start().await;
+    let a = addr();
+    let subscriber = subscriber(&pubsub, &[&a])
+        .with_capacity(2)
+        .with_overflow(policy);
+    let mut statuses = subscriber.status();
+    let mut stream = subscriber.run().await.unwrap();
+    subscribed(&mut statuses, &a).await;
+    for slot in 1..=5 {
+        pubsub.logs(&a, slot, &signature());
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut slots = Vec::new();
+    while slots.last() != Some(&5) {
+        let next = tokio::time::timeout(Duration::from_millis(500), stream.next()).await;
+        match next {
+            Ok(Some(notification)) => slots.push(notification.slot),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    subscriber.close().await;
+    let mut reported = Vec::new();
+    while let Ok(status) = statuses.try_recv() {
+        reported.push(status);
+    }
+    (slots, reported)
+}
+
+#[tokio::test]
+async fn blocked_subscriptions_deliver_every_notification() {
+    let (slots, statuses) = overflow(OverflowPolicy::Block).await;
+    assert_eq!(slots, vec![1, 2, 3, 4, 5]);
+    assert!(!statuses.iter().any(|status| matches!(
+        status,
+        SubscriberStatus::Lagged { .. } | SubscriberStatus::Overflowed { .. }
+    )));
+}
+
+#[tokio::test]
+async fn lagging_consumers_skip_the_oldest_notifications() {
+    let (slots, statuses) = overflow(OverflowPolicy::DropOldest).await;
+    assert_eq!(slots, vec![4, 5]);
+    assert!(statuses.contains(&SubscriberStatus::Lagged { dropped: 3 }));
+}
+
+#[tokio::test]
+async fn overflowing_subscribers_stop_after_the_buffered_notifications() {
+    let (slots, statuses) = overflow(OverflowPolicy::Error).await;
+    assert_eq!(slots, vec![1, 2]);
+    let overflowed = statuses
+        .iter()
+        .position(|status| *status == SubscriberStatus::Overflowed { capacity: 2 })
+        .unwrap();
+    assert_eq!(statuses[overflowed + 1..], [SubscriberStatus::Stopped]);
+}
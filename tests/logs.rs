@@ -0,0 +1,222 @@
+// Tests for parsing log lines and reconstructing the invocation tree of a transaction
+use solana_indexer::{
+    invocation::{build_invocations, ComputeUnits, Invocation},
+    log_events::{parse_log, InvokeResult, SolLog, TRUNCATED_LOG},
+};
+
+const OUTER: &str = "Outer1111111111111111111111111111111111111";
+const INNER: &str = "Inner1111111111111111111111111111111111111";
+
+fn lines(logs: &[&str]) -> Vec<String> {
+    logs.iter().map(|log| log.to_string()).collect()
+}
+
+// raw returns the lines of a frame, without the lines of its children
+fn raw(frame: &Invocation) -> Vec<&str> {
+    frame.logs.iter().map(|entry| entry.raw.as_str()).collect()
+}
+
+#[test]
+fn every_log_variant_is_parsed() {
+    let cases = [
+        (
+            format!("Program {} invoke [2]", INNER),
+            SolLog::Invoke {
+                program_id: INNER.to_string(),
+                depth: 2,
+            },
+        ),
+        (
+            "Program log: hello world".to_string(),
+            SolLog::Log {
+                addr: OUTER.to_string(),
+                message: "hello world".to_string(),
+            },
+        ),
+        (
+            "Program log: Instruction: Increment".to_string(),
+            SolLog::Instruction {
+                addr: OUTER.to_string(),
+                name: "Increment".to_string(),
+            },
+        ),
+        (
+            "Program data: AAAA BBBB".to_string(),
+            SolLog::Data {
+                addr: OUTER.to_string(),
+                data: vec!["AAAA".to_string(), "BBBB".to_string()],
+            },
+        ),
+        (
+            format!("Program {} consumed 1234 of 200000 compute units", INNER),
+            SolLog::Consumed {
+                program_id: INNER.to_string(),
+                consumed: 1234,
+                limit: 200000,
+            },
+        ),
+        (
+            format!("Program {} success", INNER),
+            SolLog::Result {
+                program_id: INNER.to_string(),
+                result: InvokeResult::Success,
+            },
+        ),
+        (
+            format!("Program {} failed: custom program error: 0x1770", INNER),
+            SolLog::Result {
+                program_id: INNER.to_string(),
+                result: InvokeResult::Failed("custom program error: 0x1770".to_string()),
+            },
+        ),
+        (
+            TRUNCATED_LOG.to_string(),
+            SolLog::Truncated {
+                addr: OUTER.to_string(),
+            },
+        ),
+    ];
+    for (log, expected) in cases {
+        assert_eq!(parse_log(log.as_str(), OUTER), Some(expected), "{}", log);
+    }
+    assert_eq!(parse_log("Program return: Outer AAAA", OUTER), None);
+    assert_eq!(parse_log("Program Outer invoke [x]", OUTER), None);
+}
+
+#[test]
+fn nested_cpis_are_attributed_to_their_frame() {
+    let logs = lines(&[
+        &format!("Program {} invoke [1]", OUTER),
+        "Program log: Instruction: Increment",
+        &format!("Program {} invoke [2]", INNER),
+        "Program data: AAAA",
+        &format!("Program {} consumed 100 of 1000 compute units", INNER),
+        &format!("Program {} success", INNER),
+        "Program log: after",
+        &format!("Program {} consumed 500 of 2000 compute units", OUTER),
+        &format!("Program {} success", OUTER),
+        &format!("Program {} invoke [1]", INNER),
+        &format!("Program {} success", INNER),
+    ]);
+    let roots = build_invocations(&logs);
+    assert_eq!(roots.len(), 2);
+    let outer = &roots[0];
+    assert_eq!(outer.program_id, OUTER);
+    assert_eq!(outer.depth, 1);
+    assert!(outer.is_success());
+    assert_eq!(
+        outer.compute_units,
+        Some(ComputeUnits {
+            consumed: 500,
+            limit: 2000
+        })
+    );
+    assert_eq!(
+        raw(outer),
+        vec![
+            logs[0].as_str(),
+            logs[1].as_str(),
+            logs[6].as_str(),
+            logs[7].as_str(),
+            logs[8].as_str()
+        ]
+    );
+    let inner = &outer.children[0];
+    assert_eq!(inner.program_id, INNER);
+    assert_eq!(inner.depth, 2);
+    assert_eq!(
+        inner.logs[1].log,
+        Some(SolLog::Data {
+            addr: INNER.to_string(),
+            data: vec!["AAAA".to_string()],
+        })
+    );
+    assert_eq!(
+        inner
+            .logs
+            .iter()
+            .map(|entry| entry.index)
+            .collect::<Vec<_>>(),
+        vec![2, 3, 4, 5]
+    );
+    let walked = outer
+        .walk()
+        .iter()
+        .map(|frame| frame.program_id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(walked, vec![OUTER, INNER]);
+    assert_eq!(roots[1].program_id, INNER);
+    assert!(roots[1].children.is_empty());
+}
+
+#[test]
+fn failed_cpis_close_their_frame() {
+    let logs = lines(&[
+        &format!("Program {} invoke [1]", OUTER),
+        &format!("Program {} invoke [2]", INNER),
+        "Program log: AnchorError occurred.",
+        &format!("Program {} failed: custom program error: 0x1770", INNER),
+        &format!("Program {} failed: custom program error: 0x1770", OUTER),
+    ]);
+    let roots = build_invocations(&logs);
+    assert_eq!(roots.len(), 1);
+    let outer = &roots[0];
+    let failed = Some(InvokeResult::Failed(
+        "custom program error: 0x1770".to_string(),
+    ));
+    assert_eq!(outer.result, failed);
+    assert_eq!(outer.children[0].result, failed);
+    assert!(!outer.children[0].is_success());
+    assert_eq!(raw(outer), vec![logs[0].as_str(), logs[4].as_str()]);
+}
+
+#[test]
+fn truncated_logs_leave_the_frames_open() {
+    let logs = lines(&[
+        &format!("Program {} invoke [1]", OUTER),
+        &format!("Program {} invoke [2]", INNER),
+        "Program data: AAAA",
+        TRUNCATED_LOG,
+    ]);
+    let roots = build_invocations(&logs);
+    assert_eq!(roots.len(), 1);
+    let outer = &roots[0];
+    assert_eq!(outer.result, None);
+    let inner = &outer.children[0];
+    assert_eq!(inner.result, None);
+    assert_eq!(
+        inner.logs.last().unwrap().log,
+        Some(SolLog::Truncated {
+            addr: INNER.to_string()
+        })
+    );
+}
+
+#[test]
+fn results_without_an_open_frame_are_kept_as_lines() {
+    let logs = lines(&[
+        &format!("Program {} success", INNER),
+        &format!("Program {} invoke [1]", OUTER),
+        &format!("Program {} invoke [2]", INNER),
+        &format!("Program {} success", INNER),
+        // the frame of this program isn't open, the tree stays intact
+        "Program Other111111111111111111111111111111111 success",
+        "Program log: still in outer",
+        &format!("Program {} success", OUTER),
+    ]);
+    let roots = build_invocations(&logs);
+    assert_eq!(roots.len(), 1);
+    let outer = &roots[0];
+    assert!(outer.is_success());
+    assert_eq!(outer.children.len(), 1);
+    assert!(outer.children[0].is_success());
+    assert_eq!(
+        raw(outer),
+        vec![
+            logs[1].as_str(),
+            logs[4].as_str(),
+            logs[5].as_str(),
+            logs[6].as_str()
+        ]
+    );
+}
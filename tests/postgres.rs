@@ -0,0 +1,423 @@
+// Tests for the PostgreSQL sink.
+// They run against SOL_TEST_PG_URL if set, otherwise a throwaway cluster is spawned
+// with initdb/pg_ctl. The tests are skipped if neither is available.
+mod common;
+
+use std::{
+    net::TcpListener,
+    process::{Command, Stdio},
+};
+
+use solana_indexer::{
+    checkpoint::{Checkpoint, CheckpointStore},
+    failure::{FailureKind, TxFailure},
+    sink::{CommitStatus, EventBatch, EventSink, RetractedTx, Retraction},
+    storage::postgres::PostgresSink,
+    transaction::BalanceChange,
+};
+
+use common::{failed_tx_record, tx_record};
+
+// LocalPostgres is a postgres cluster that lives for the duration of a test
+struct LocalPostgres {
+    url: String,
+    data_dir: Option<tempfile::TempDir>,
+}
+
+impl LocalPostgres {
+    fn start() -> Result<Self, String> {
+        if let Ok(url) = std::env::var("SOL_TEST_PG_URL") {
+            return Ok(Self {
+                url,
+                data_dir: None,
+            });
+        }
+        let data_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map_err(|e| e.to_string())?
+            .port();
+        let path = data_dir.path().to_str().ok_or("invalid temp dir")?;
+        let initdb = Command::new("initdb")
+            .args(["-D", path, "-U", "postgres", "--auth=trust"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("initdb: {}", e))?;
+        if !initdb.success() {
+            return Err(format!("initdb: {}", initdb));
+        }
+        let opts = format!("-p {} -k {} -c listen_addresses=127.0.0.1", port, path);
+        let started = Command::new("pg_ctl")
+            .args(["-D", path, "-o", opts.as_str(), "-w", "-l"])
+            .arg(data_dir.path().join("postgres.log"))
+            .arg("start")
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|e| format!("pg_ctl: {}", e))?;
+        if !started.success() {
+            return Err(format!("pg_ctl: {}", started));
+        }
+        Ok(Self {
+            url: format!("postgres://postgres@127.0.0.1:{}/postgres", port),
+            data_dir: Some(data_dir),
+        })
+    }
+}
+
+impl Drop for LocalPostgres {
+    fn drop(&mut self) {
+        if let Some(data_dir) = &self.data_dir {
+            let _ = Command::new("pg_ctl")
+                .args([
+                    "-D",
+                    data_dir.path().to_str().unwrap(),
+                    "-m",
+                    "immediate",
+                    "stop",
+                ])
+                .stdout(Stdio::null())
+                .status();
+        }
+    }
+}
+
+// local_postgres starts a LocalPostgres, or returns from the test if no postgres is available
+macro_rules! local_postgres {
+    () => {
+        match LocalPostgres::start() {
+            Ok(pg) => pg,
+            Err(e) => {
+                eprintln!(
+                    "skipping: no postgres available, set SOL_TEST_PG_URL or install initdb/pg_ctl (non-root): {}",
+                    e
+                );
+                return;
+            }
+        }
+    };
+}
+
+async fn count(sink: &PostgresSink, table: &str, sig: &str) -> i64 {
+    let client = sink.client().lock().await;
+    client
+        .query_one(
+            format!("SELECT COUNT(*) FROM {} WHERE signature = $1", table).as_str(),
+            &[&sig],
+        )
+        .await
+        .unwrap()
+        .get(0)
+}
+
+#[tokio::test]
+async fn migrations_are_applied_once() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    // connecting again must not re-apply migrations
+    PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let client = sink.client().lock().await;
+    let row = client
+        .query_one(
+            "SELECT COUNT(*), COUNT(DISTINCT version) FROM schema_migrations",
+            &[],
+        )
+        .await
+        .unwrap();
+    let (total, distinct): (i64, i64) = (row.get(0), row.get(1));
+    assert!(total > 0);
+    assert_eq!(total, distinct);
+}
+
+#[tokio::test]
+async fn rewriting_a_batch_is_idempotent() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let sig = "idempotent-sig";
+    let batch = EventBatch::new(
+        CommitStatus::Finalized,
+        "prog".to_string(),
+        vec![tx_record(sig, 10)],
+    );
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "transactions", sig).await, 1);
+    assert_eq!(count(&sink, "instructions", sig).await, 2);
+    assert_eq!(count(&sink, "logs", sig).await, 2);
+    assert_eq!(count(&sink, "events", sig).await, 2);
+    assert_eq!(count(&sink, "program_instructions", sig).await, 1);
+
+    let client = sink.client().lock().await;
+    let row = client
+        .query_one(
+            "SELECT program_id, stack_height, parent_index FROM instructions WHERE signature = $1 AND ix_index = 1",
+            &[&sig],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, String>(0), "prog");
+    assert_eq!(row.get::<_, i32>(1), 2);
+    assert_eq!(row.get::<_, Option<i32>>(2), Some(0));
+    let row = client
+        .query_one(
+            "SELECT name, data->>'label', decode_error FROM events WHERE signature = $1 AND log_index = 1",
+            &[&sig],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        row.get::<_, Option<String>>(0).as_deref(),
+        Some("CountChangeEvent")
+    );
+    assert_eq!(row.get::<_, Option<String>>(1).as_deref(), Some("inc"));
+    assert_eq!(row.get::<_, Option<String>>(2), None);
+    let row = client
+        .query_one(
+            "SELECT raw, decode_error, ix_index FROM events WHERE signature = $1 AND log_index = 2",
+            &[&sig],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, Option<String>>(0).as_deref(), Some("AAAA"));
+    assert_eq!(row.get::<_, Option<i32>>(2), Some(1));
+    assert_eq!(
+        row.get::<_, Option<String>>(1).as_deref(),
+        Some("unknown discriminator")
+    );
+}
+
+#[tokio::test]
+async fn finalized_transactions_are_not_downgraded() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let sig = "finalized-sig";
+    let tx = tx_record(sig, 20);
+    for commitment in [
+        CommitStatus::Confirmed,
+        CommitStatus::Finalized,
+        CommitStatus::Confirmed,
+    ] {
+        let batch = EventBatch::new(commitment, "prog".to_string(), vec![tx.clone()]);
+        sink.write_batch(&batch).await.unwrap();
+    }
+
+    let client = sink.client().lock().await;
+    let commitment: String = client
+        .query_one(
+            "SELECT commitment FROM transactions WHERE signature = $1",
+            &[&sig],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(commitment, "finalized");
+}
+
+#[tokio::test]
+async fn lookup_table_accounts_are_stored() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let sig = "v0-sig";
+    let tx = tx_record(sig, 25);
+    let batch = EventBatch::new(
+        CommitStatus::Confirmed,
+        "prog".to_string(),
+        vec![tx.clone()],
+    );
+    sink.write_batch(&batch).await.unwrap();
+
+    let client = sink.client().lock().await;
+    let accounts: Vec<String> = client
+        .query_one(
+            "SELECT accounts FROM transactions WHERE signature = $1",
+            &[&sig],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(accounts, tx.accounts);
+}
+
+#[tokio::test]
+async fn failures_are_stored() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let sig = "failed-sig";
+    let mut tx = tx_record(sig, 26);
+    tx.err = Some("Error processing Instruction 0: custom program error: 0x1770".to_string());
+    tx.failure = Some(TxFailure {
+        kind: FailureKind::Anchor,
+        instruction_index: Some(0),
+        instruction: Some("increment".to_string()),
+        program_id: Some("prog".to_string()),
+        error_code: Some(6000),
+        error_name: Some("Overflow".to_string()),
+        error_message: Some("Counter overflow".to_string()),
+        account: None,
+    });
+    let batch = EventBatch::new(CommitStatus::Confirmed, "prog".to_string(), vec![tx]);
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "tx_failures", sig).await, 1);
+    let client = sink.client().lock().await;
+    let row = client
+        .query_one(
+            "SELECT kind, instruction, error_code, error_name FROM tx_failures WHERE signature = $1",
+            &[&sig],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, String>(0), "anchor");
+    assert_eq!(
+        row.get::<_, Option<String>>(1).as_deref(),
+        Some("increment")
+    );
+    assert_eq!(row.get::<_, Option<i64>>(2), Some(6000));
+    assert_eq!(row.get::<_, Option<String>>(3).as_deref(), Some("Overflow"));
+}
+
+#[tokio::test]
+async fn incomplete_transactions_are_flagged() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let sig = "truncated-sig";
+    let mut tx = tx_record(sig, 27);
+    tx.incomplete = true;
+    tx.balance_changes = vec![
+        BalanceChange {
+            account: "payer".to_string(),
+            mint: None,
+            owner: None,
+            pre: 1_000_000,
+            post: 995_000,
+        },
+        BalanceChange {
+            account: "lookup".to_string(),
+            mint: Some("mint".to_string()),
+            owner: Some("payer".to_string()),
+            pre: 0,
+            post: u64::MAX,
+        },
+    ];
+    let batch = EventBatch::new(CommitStatus::Confirmed, "prog".to_string(), vec![tx]);
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "balance_changes", sig).await, 2);
+    let client = sink.client().lock().await;
+    let incomplete: bool = client
+        .query_one(
+            "SELECT incomplete FROM transactions WHERE signature = $1",
+            &[&sig],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert!(incomplete);
+    let post: String = client
+        .query_one(
+            "SELECT post::TEXT FROM balance_changes WHERE signature = $1 AND kind = 'token'",
+            &[&sig],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(post, u64::MAX.to_string());
+}
+
+#[tokio::test]
+async fn checkpoints_are_written_with_the_batch() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let program = "checkpoint-prog";
+    let checkpoint = Checkpoint::new(30, "head-sig".to_string(), 25, "tail-sig".to_string());
+    let batch = EventBatch::new(
+        CommitStatus::Confirmed,
+        program.to_string(),
+        vec![tx_record("checkpoint-sig", 30)],
+    )
+    .with_checkpoint(checkpoint.clone());
+    sink.write_batch(&batch).await.unwrap();
+    assert_eq!(sink.load(program).await.unwrap(), Some(checkpoint));
+
+    let checkpoint = Checkpoint::new(40, "head-sig-2".to_string(), 25, "tail-sig".to_string());
+    sink.save(program, &checkpoint).await.unwrap();
+    assert_eq!(sink.load(program).await.unwrap(), Some(checkpoint));
+    assert_eq!(sink.load("unknown-prog").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn only_confirmed_transactions_are_retracted() {
+    let pg = local_postgres!();
+    let sink = PostgresSink::connect(pg.url.as_str()).await.unwrap();
+    let (dropped, finalized) = ("retracted-sig", "kept-sig");
+    sink.write_batch(&EventBatch::new(
+        CommitStatus::Confirmed,
+        "prog".to_string(),
+        vec![
+            failed_tx_record(dropped, 50),
+            failed_tx_record(finalized, 51),
+        ],
+    ))
+    .await
+    .unwrap();
+    sink.write_batch(&EventBatch::new(
+        CommitStatus::Finalized,
+        "prog".to_string(),
+        vec![failed_tx_record(finalized, 51)],
+    ))
+    .await
+    .unwrap();
+    let retraction = Retraction {
+        program_addr: "prog".to_string(),
+        txs: [(dropped, 50), (finalized, 51)]
+            .iter()
+            .map(|(sig, slot)| RetractedTx {
+                slot: *slot,
+                signature: sig.to_string(),
+            })
+            .collect(),
+    };
+    sink.retract(&retraction).await.unwrap();
+
+    let client = sink.client().lock().await;
+    for (sig, expected) in [(dropped, "retracted"), (finalized, "finalized")] {
+        let tx_commitment: String = client
+            .query_one(
+                "SELECT commitment FROM transactions WHERE signature = $1",
+                &[&sig],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(tx_commitment, expected);
+        let event_commitments: Vec<String> = client
+            .query(
+                "SELECT DISTINCT commitment FROM events WHERE signature = $1",
+                &[&sig],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(event_commitments, vec![expected.to_string()]);
+        let ix_commitment: String = client
+            .query_one(
+                "SELECT commitment FROM program_instructions WHERE signature = $1",
+                &[&sig],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(ix_commitment, expected);
+    }
+    drop(client);
+    // the tables without a commitment only keep the rows of the finalized tx
+    for table in ["instructions", "logs", "tx_failures", "balance_changes"] {
+        assert_eq!(count(&sink, table, dropped).await, 0, "{}", table);
+        assert!(count(&sink, table, finalized).await > 0, "{}", table);
+    }
+}
@@ -0,0 +1,123 @@
+// Tests for the client-side rate limits of the rpc client, against an in-process rpc node
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{invoke_tx, signature, Ledger, MockRpc};
+use solana_indexer::{
+    rate_limit::{RateLimit, RateLimitStats},
+    rpc::{EndpointConfig, RpcClientWrapper},
+};
+use solana_sdk::signature::Signature;
+
+fn stats(client: &RpcClientWrapper, scope: &str) -> RateLimitStats {
+    client
+        .rate_limit_stats()
+        .into_iter()
+        .find(|stats| stats.scope == scope)
+        .unwrap()
+}
+
+async fn mock_rpc() -> MockRpc {
+    let mut ledger = Ledger {
+        confirmed_slot: 100,
+        finalized_slot: 100,
+        txs: vec![],
+    };
+    for slot in 0..8 {
+        ledger.push(invoke_tx(&signature(), slot, "prog", true));
+    }
+    MockRpc::start(ledger).await
+}
+
+#[tokio::test]
+async fn requests_beyond_the_burst_wait_for_tokens() {
+    let rpc = mock_rpc().await;
+    let client = RpcClientWrapper::new(rpc.url.clone())
+        .with_rate_limit(RateLimit::per_second(20.0).with_burst(2));
+
+    let start = Instant::now();
+    for _ in 0..8 {
+        client.get_slot(None).await.unwrap();
+    }
+    // the burst is served at once, the 6 other requests wait for a token each (50ms)
+    assert!(
+        start.elapsed() >= Duration::from_millis(290),
+        "{:?}",
+        start.elapsed()
+    );
+    let global = stats(&client, "global");
+    assert_eq!(global.acquired, 8);
+    assert!(global.throttled >= 5, "{:?}", global);
+    assert!(global.max_wait >= Duration::from_millis(30), "{:?}", global);
+}
+
+#[tokio::test]
+async fn method_limits_only_apply_to_their_method_and_count_every_call_of_a_batch() {
+    let rpc = mock_rpc().await;
+    let client = RpcClientWrapper::new(rpc.url.clone())
+        .with_method_rate_limit("get_tx", RateLimit::per_second(10.0).with_burst(4));
+
+    let start = Instant::now();
+    for _ in 0..10 {
+        client.get_slot(None).await.unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(300));
+
+    let sigs = rpc
+        .ledger
+        .lock()
+        .unwrap()
+        .txs
+        .iter()
+        .map(|tx| tx.signature.parse::<Signature>().unwrap())
+        .collect::<Vec<_>>();
+    let start = Instant::now();
+    // a batch of 4 calls takes the whole burst, the second one waits until 4 tokens are refilled
+    for chunk in sigs.chunks(4) {
+        let results = client.get_txs(chunk, None).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+    assert!(
+        start.elapsed() >= Duration::from_millis(390),
+        "{:?}",
+        start.elapsed()
+    );
+    let get_tx = stats(&client, "method:get_tx");
+    assert_eq!((get_tx.acquired, get_tx.throttled), (2, 1));
+    assert!(client
+        .rate_limit_stats()
+        .iter()
+        .all(|stats| stats.scope == "method:get_tx"));
+}
+
+#[tokio::test]
+async fn endpoint_limits_cap_the_requests_in_flight() {
+    let rpc = mock_rpc().await;
+    let limit = RateLimit::per_second(1000.0).with_max_concurrent(1);
+    let client = RpcClientWrapper::with_endpoints(vec![
+        EndpointConfig::new(rpc.url.clone(), 1).with_rate_limit(limit)
+    ])
+    .unwrap();
+
+    rpc.delay(Duration::from_millis(50));
+
+    // concurrent requests take turns on the single slot of the endpoint
+    let start = Instant::now();
+    let requests = (0..4).map(|_| client.get_slot(None)).collect::<Vec<_>>();
+    for slot in futures_util::future::join_all(requests).await {
+        assert_eq!(slot.unwrap(), 100);
+    }
+    assert!(
+        start.elapsed() >= Duration::from_millis(200),
+        "{:?}",
+        start.elapsed()
+    );
+    let endpoint = stats(&client, format!("endpoint:{}", rpc.url).as_str());
+    assert_eq!((endpoint.acquired, endpoint.throttled), (4, 3));
+    assert!(
+        endpoint.max_wait >= Duration::from_millis(150),
+        "{:?}",
+        endpoint
+    );
+}
@@ -0,0 +1,70 @@
+// Tests for the retry policy: per-method budgets and jittered exponential backoff
+use std::time::Duration;
+
+use solana_indexer::retry::{RetryBudget, RetryPolicy};
+
+fn policy() -> RetryPolicy {
+    RetryPolicy::new(
+        Duration::from_millis(100),
+        Duration::from_secs(1),
+        RetryBudget::new(5, Duration::from_secs(30)),
+    )
+}
+
+#[test]
+fn backoff_is_jittered_below_an_exponential_ceiling() {
+    let policy = policy();
+    // the ceiling doubles from the base delay and is capped at the max delay
+    let ceilings = [100, 200, 400, 800, 1000, 1000];
+    for (attempt, ceiling) in ceilings.into_iter().enumerate() {
+        let ceiling = Duration::from_millis(ceiling);
+        let delays = (0..200)
+            .map(|_| policy.backoff(attempt as u32, None))
+            .collect::<Vec<_>>();
+        assert!(delays.iter().all(|delay| *delay <= ceiling), "{:?}", delays);
+        // full jitter spreads the delays over the whole range
+        assert!(delays.iter().any(|delay| *delay < ceiling / 2));
+        assert!(delays.iter().any(|delay| *delay > ceiling / 2));
+    }
+    // the ceiling doesn't overflow on large attempts
+    assert!(policy.backoff(u32::MAX, None) <= Duration::from_secs(1));
+    assert_eq!(RetryPolicy::none().backoff(3, None), Duration::ZERO);
+}
+
+#[test]
+fn backoff_waits_at_least_the_retry_after_of_the_server() {
+    let policy = policy();
+    let retry_after = Duration::from_secs(5);
+    for attempt in 0..5 {
+        assert_eq!(policy.backoff(attempt, Some(retry_after)), retry_after);
+    }
+    let short = Duration::from_millis(1);
+    for _ in 0..100 {
+        assert!(policy.backoff(2, Some(short)) >= short);
+    }
+}
+
+#[test]
+fn methods_have_their_own_budget() {
+    let tx_budget = RetryBudget::new(8, Duration::from_secs(60));
+    let policy = policy().with_budget("get_tx", tx_budget);
+    assert_eq!(policy.budget("get_tx"), tx_budget);
+    assert_eq!(
+        policy.budget("get_slot"),
+        RetryBudget::new(5, Duration::from_secs(30))
+    );
+    let policy = policy.with_default_budget(RetryBudget::new(2, Duration::from_secs(1)));
+    assert_eq!(policy.budget("get_slot").max_attempts, 2);
+    assert_eq!(policy.budget("get_tx"), tx_budget);
+    assert_eq!(RetryPolicy::none().budget("get_tx").max_attempts, 1);
+}
+
+#[test]
+fn missing_txs_have_a_budget_of_their_own() {
+    let not_found = RetryBudget::new(8, Duration::from_secs(60));
+    let policy = policy().with_not_found_budget(not_found);
+    assert_eq!(policy.not_found_budget(), not_found);
+    assert_eq!(policy.budget("get_tx").max_attempts, 5);
+    assert_eq!(RetryPolicy::default().not_found_budget(), not_found);
+    assert_eq!(RetryPolicy::none().not_found_budget().max_attempts, 1);
+}
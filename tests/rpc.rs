@@ -0,0 +1,234 @@
+// Tests for the rpc client against in-process rpc nodes
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{invoke_tx, signature, Failure, Ledger, MockRpc};
+use solana_indexer::{
+    retry::{RetryBudget, RetryPolicy},
+    rpc::{EndpointConfig, RpcClientWrapper, RpcError},
+};
+use solana_sdk::signature::Signature;
+
+fn empty_ledger() -> Ledger {
+    Ledger {
+        confirmed_slot: 100,
+        finalized_slot: 100,
+        txs: vec![],
+    }
+}
+
+#[tokio::test]
+async fn a_split_batch_shares_one_retry_budget() {
+    let mut ledger = empty_ledger();
+    let sigs = (0..20).map(|_| signature()).collect::<Vec<_>>();
+    for sig in sigs.iter() {
+        ledger.push(invoke_tx(sig, 50, "prog", true));
+    }
+    let rpc = MockRpc::start(ledger).await;
+    let retry = RetryPolicy::new(
+        Duration::from_millis(1),
+        Duration::from_millis(1),
+        RetryBudget::new(3, Duration::from_secs(10)),
+    );
+    let client = RpcClientWrapper::new(rpc.url.clone())
+        .with_retry_policy(retry)
+        .with_max_batch_size(20);
+    let sigs = sigs
+        .iter()
+        .map(|sig| sig.parse::<Signature>().unwrap())
+        .collect::<Vec<_>>();
+    // the batch is rejected and split, then the node keeps failing
+    let failure = |status| Failure {
+        status,
+        retry_after: None,
+    };
+    rpc.fail(std::iter::once(failure(413)).chain(std::iter::repeat_n(failure(500), 10)));
+
+    let results = client.get_txs(&sigs, None).await;
+    assert_eq!(results.len(), 20);
+    assert!(results
+        .iter()
+        .all(|result| matches!(result, Err(RpcError::TransportError { .. }))));
+    // the rejected batch, the first attempt of each half and the 2 retries they share
+    assert_eq!(rpc.pending_failures(), 10 - 4);
+}
+
+#[tokio::test]
+async fn missing_txs_are_asked_for_again_on_the_same_endpoint() {
+    let rpcs = [
+        MockRpc::start(empty_ledger()).await,
+        MockRpc::start(empty_ledger()).await,
+    ];
+    // no retries for errors, missing txs are asked for 3 times
+    let retry = RetryPolicy::new(
+        Duration::from_millis(1),
+        Duration::from_millis(1),
+        RetryBudget::new(1, Duration::from_secs(10)),
+    )
+    .with_not_found_budget(RetryBudget::new(3, Duration::from_secs(10)));
+    let client = RpcClientWrapper::with_endpoints(vec![
+        EndpointConfig::new(rpcs[0].url.clone(), 1),
+        EndpointConfig::new(rpcs[1].url.clone(), 1),
+    ])
+    .unwrap()
+    .with_retry_policy(retry);
+    let sig = signature();
+
+    let err = client
+        .get_tx(&sig.parse::<Signature>().unwrap(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(err, RpcError::TxNotFound(sig));
+    let requests = rpcs
+        .iter()
+        .map(|rpc| rpc.requests("getTransaction").len())
+        .collect::<Vec<_>>();
+    assert_eq!(requests, vec![3, 0]);
+    // a missing tx is not an error of the endpoint
+    let stats = client.endpoint_stats();
+    assert!(stats.iter().all(|stats| stats.healthy && stats.errors == 0));
+    assert_eq!(stats[0].requests, 3);
+}
+
+#[tokio::test]
+async fn missing_txs_of_a_batch_are_asked_for_again_on_their_own() {
+    let mut ledger = empty_ledger();
+    let (found, missing) = (signature(), signature());
+    ledger.push(invoke_tx(&found, 50, "prog", true));
+    let rpc = MockRpc::start(ledger).await;
+    let retry = RetryPolicy::new(
+        Duration::from_millis(1),
+        Duration::from_millis(1),
+        RetryBudget::new(1, Duration::from_secs(10)),
+    )
+    .with_not_found_budget(RetryBudget::new(3, Duration::from_secs(10)));
+    let client = RpcClientWrapper::new(rpc.url.clone()).with_retry_policy(retry);
+    let sigs = [&found, &missing].map(|sig| sig.parse::<Signature>().unwrap());
+
+    let results = client.get_txs(&sigs, None).await;
+    assert_eq!(results[0].as_ref().unwrap().slot, 50);
+    assert_eq!(results[1], Err(RpcError::TxNotFound(missing.clone())));
+    // the batch, then the missing tx twice on its own
+    let requests = rpc.requests("getTransaction");
+    assert_eq!(requests.len(), 4);
+    assert!(requests[2..].iter().all(|params| params[0] == missing));
+}
+
+#[tokio::test]
+async fn single_requests_wait_for_the_retry_after_of_the_server() {
+    let mut ledger = empty_ledger();
+    let sig = signature();
+    ledger.push(invoke_tx(&sig, 50, "prog", true));
+    let rpc = MockRpc::start(ledger).await;
+    let retry = RetryPolicy::new(
+        Duration::from_millis(1),
+        Duration::from_millis(1),
+        RetryBudget::new(3, Duration::from_secs(10)),
+    );
+    let client = RpcClientWrapper::new(rpc.url.clone()).with_retry_policy(retry);
+    rpc.fail([Failure {
+        status: 429,
+        retry_after: Some(1),
+    }]);
+
+    let start = Instant::now();
+    let tx = client
+        .get_tx(&sig.parse::<Signature>().unwrap(), None)
+        .await
+        .unwrap();
+    assert_eq!(tx.slot, 50);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(rpc.requests("getTransaction").len(), 1);
+}
+
+#[tokio::test]
+async fn requests_are_shared_by_weight() {
+    let rpcs = [
+        MockRpc::start(empty_ledger()).await,
+        MockRpc::start(empty_ledger()).await,
+        MockRpc::start(empty_ledger()).await,
+    ];
+    let client = RpcClientWrapper::with_endpoints(vec![
+        EndpointConfig::new(rpcs[0].url.clone(), 3),
+        EndpointConfig::new(rpcs[1].url.clone(), 1),
+        // weight 0 is only used for failover
+        EndpointConfig::new(rpcs[2].url.clone(), 0),
+    ])
+    .unwrap();
+
+    for _ in 0..40 {
+        client.get_slot(None).await.unwrap();
+    }
+    let served = rpcs
+        .iter()
+        .map(|rpc| rpc.requests("getSlot").len())
+        .collect::<Vec<_>>();
+    assert_eq!(served, vec![30, 10, 0]);
+    let stats = client.endpoint_stats();
+    assert!(stats.iter().all(|stats| stats.healthy && stats.errors == 0));
+    assert_eq!(stats[0].requests, 30);
+}
+
+#[tokio::test]
+async fn failing_endpoints_fail_over_and_are_taken_out_of_rotation() {
+    let failing = MockRpc::start(empty_ledger()).await;
+    let backup = MockRpc::start(empty_ledger()).await;
+    failing.fail(vec![
+        Failure {
+            status: 503,
+            retry_after: None,
+        };
+        20
+    ]);
+    let client = RpcClientWrapper::with_endpoints(vec![
+        EndpointConfig::new(failing.url.clone(), 1),
+        EndpointConfig::new(backup.url.clone(), 0),
+    ])
+    .unwrap()
+    .with_retry_policy(RetryPolicy::none());
+
+    for _ in 0..6 {
+        let served = client.get_slot_served(None).await.unwrap();
+        assert_eq!(served.endpoint, backup.url);
+        assert_eq!(served.value, 100);
+    }
+    // the endpoint is skipped after 3 consecutive errors, it is only tried after the healthy ones
+    let stats = client.endpoint_stats();
+    assert!(!stats[0].healthy);
+    assert_eq!((stats[0].requests, stats[0].errors), (3, 3));
+    assert_eq!(stats[0].error_rate(), 1.0);
+    assert!(stats[1].healthy);
+    assert_eq!(backup.requests("getSlot").len(), 6);
+}
+
+#[tokio::test]
+async fn errors_of_the_request_are_not_failed_over() {
+    let rejecting = MockRpc::start(empty_ledger()).await;
+    let backup = MockRpc::start(empty_ledger()).await;
+    rejecting.fail([Failure {
+        status: 413,
+        retry_after: None,
+    }]);
+    let client = RpcClientWrapper::with_endpoints(vec![
+        EndpointConfig::new(rejecting.url.clone(), 1),
+        EndpointConfig::new(backup.url.clone(), 0),
+    ])
+    .unwrap();
+
+    let err = client.get_slot(None).await.unwrap_err();
+    assert!(
+        matches!(err, RpcError::NodeError { code: 413, .. }),
+        "{}",
+        err
+    );
+    assert!(!err.is_retryable());
+    assert!(backup.requests("getSlot").is_empty());
+    // only answered requests count as successful
+    let stats = client.endpoint_stats();
+    assert_eq!((stats[0].requests, stats[0].errors), (0, 0));
+    assert_eq!(stats[0].latency_ms, 0.0);
+
+    client.get_slot(None).await.unwrap();
+    assert_eq!(client.endpoint_stats()[0].requests, 1);
+}
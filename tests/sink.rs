@@ -0,0 +1,80 @@
+// Tests for the FileSink
+mod common;
+
+use serde_json::Value;
+use solana_indexer::sink::{
+    CommitStatus, EventBatch, EventSink, FileSink, RetractedTx, Retraction,
+};
+
+use common::tx_record;
+
+fn read_lines(path: &std::path::Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn batches_and_retractions_are_appended_as_json_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.jsonl");
+    let sink = FileSink::open(path.to_str().unwrap()).await.unwrap();
+    sink.write_batch(&EventBatch::new(
+        CommitStatus::Confirmed,
+        "prog".to_string(),
+        vec![tx_record("first-sig", 50), tx_record("second-sig", 51)],
+    ))
+    .await
+    .unwrap();
+    sink.retract(&Retraction {
+        program_addr: "prog".to_string(),
+        txs: vec![RetractedTx {
+            slot: 50,
+            signature: "first-sig".to_string(),
+        }],
+    })
+    .await
+    .unwrap();
+
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 3);
+    for (line, sig) in lines[..2].iter().zip(["first-sig", "second-sig"]) {
+        assert_eq!(line["commitment"], "confirmed");
+        assert_eq!(line["tx"]["signature"], sig);
+    }
+    assert_eq!(lines[0]["tx"]["slot"], 50);
+    assert_eq!(
+        lines[0]["tx"]["events"][0]["payload"]["decoded"]["name"],
+        "CountChangeEvent"
+    );
+    assert_eq!(lines[2]["commitment"], "retracted");
+    assert_eq!(lines[2]["tx"]["signature"], "first-sig");
+    assert_eq!(lines[2]["tx"]["slot"], 50);
+}
+
+#[tokio::test]
+async fn reopening_appends_to_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.jsonl");
+    for (sig, commitment) in [
+        ("first-sig", CommitStatus::Confirmed),
+        ("first-sig", CommitStatus::Finalized),
+    ] {
+        let sink = FileSink::open(path.to_str().unwrap()).await.unwrap();
+        sink.write_batch(&EventBatch::new(
+            commitment,
+            "prog".to_string(),
+            vec![tx_record(sig, 50)],
+        ))
+        .await
+        .unwrap();
+    }
+
+    let commitments = read_lines(&path)
+        .iter()
+        .map(|line| line["commitment"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(commitments, vec!["confirmed", "finalized"]);
+}
@@ -0,0 +1,304 @@
+// Tests for the SQLite sink, they run against an in-memory database
+mod common;
+
+use rusqlite::{params, Connection};
+use solana_indexer::{
+    checkpoint::{Checkpoint, CheckpointStore},
+    failure::{FailureKind, TxFailure},
+    sink::{CommitStatus, EventBatch, EventSink, RetractedTx, Retraction},
+    storage::sqlite::SqliteSink,
+    transaction::BalanceChange,
+};
+
+use common::{failed_tx_record, tx_record};
+
+fn count(sink: &SqliteSink, table: &str, sig: &str) -> i64 {
+    let conn = sink.connection();
+    let conn = conn.lock().unwrap();
+    conn.query_row(
+        format!("SELECT COUNT(*) FROM {} WHERE signature = ?1", table).as_str(),
+        params![sig],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+fn query<T: rusqlite::types::FromSql>(sink: &SqliteSink, sql: &str, sig: &str) -> T {
+    let conn = sink.connection();
+    let conn = conn.lock().unwrap();
+    conn.query_row(sql, params![sig], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn migrations_are_applied_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("index.db");
+    let path = path.to_str().unwrap();
+    SqliteSink::open(path).unwrap();
+    let version = |conn: &Connection| -> i64 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    };
+    let first = version(&Connection::open(path).unwrap());
+    assert!(first > 0);
+    // opening again must not re-apply migrations
+    let sink = SqliteSink::open(path).unwrap();
+    assert_eq!(version(&sink.connection().lock().unwrap()), first);
+}
+
+#[tokio::test]
+async fn rewriting_a_batch_is_idempotent() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let sig = "idempotent-sig";
+    let batch = EventBatch::new(
+        CommitStatus::Finalized,
+        "prog".to_string(),
+        vec![tx_record(sig, 10)],
+    );
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "transactions", sig), 1);
+    assert_eq!(count(&sink, "instructions", sig), 2);
+    assert_eq!(count(&sink, "logs", sig), 2);
+    assert_eq!(count(&sink, "events", sig), 2);
+    assert_eq!(count(&sink, "program_instructions", sig), 1);
+
+    let conn = sink.connection();
+    let conn = conn.lock().unwrap();
+    let (program_id, stack_height, parent_index): (String, i64, Option<i64>) = conn
+        .query_row(
+            "SELECT program_id, stack_height, parent_index FROM instructions WHERE signature = ?1 AND ix_index = 1",
+            params![sig],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(program_id, "prog");
+    assert_eq!(stack_height, 2);
+    assert_eq!(parent_index, Some(0));
+    let (name, label, decode_error): (Option<String>, Option<String>, Option<String>) = conn
+        .query_row(
+            "SELECT name, json_extract(data, '$.label'), decode_error FROM events WHERE signature = ?1 AND log_index = 1",
+            params![sig],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("CountChangeEvent"));
+    assert_eq!(label.as_deref(), Some("inc"));
+    assert_eq!(decode_error, None);
+    let (raw, decode_error, ix_index): (Option<String>, Option<String>, Option<i64>) = conn
+        .query_row(
+            "SELECT raw, decode_error, ix_index FROM events WHERE signature = ?1 AND log_index = 2",
+            params![sig],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(raw.as_deref(), Some("AAAA"));
+    assert_eq!(decode_error.as_deref(), Some("unknown discriminator"));
+    assert_eq!(ix_index, Some(1));
+}
+
+#[tokio::test]
+async fn finalized_transactions_are_not_downgraded() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let sig = "finalized-sig";
+    let tx = tx_record(sig, 20);
+    for commitment in [
+        CommitStatus::Confirmed,
+        CommitStatus::Finalized,
+        CommitStatus::Confirmed,
+    ] {
+        let batch = EventBatch::new(commitment, "prog".to_string(), vec![tx.clone()]);
+        sink.write_batch(&batch).await.unwrap();
+    }
+
+    let commitment: String = query(
+        &sink,
+        "SELECT commitment FROM transactions WHERE signature = ?1",
+        sig,
+    );
+    assert_eq!(commitment, "finalized");
+}
+
+#[tokio::test]
+async fn lookup_table_accounts_are_stored() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let sig = "v0-sig";
+    let tx = tx_record(sig, 25);
+    let batch = EventBatch::new(
+        CommitStatus::Confirmed,
+        "prog".to_string(),
+        vec![tx.clone()],
+    );
+    sink.write_batch(&batch).await.unwrap();
+
+    let accounts: String = query(
+        &sink,
+        "SELECT accounts FROM transactions WHERE signature = ?1",
+        sig,
+    );
+    let accounts: Vec<String> = serde_json::from_str(accounts.as_str()).unwrap();
+    assert_eq!(accounts, tx.accounts);
+}
+
+#[tokio::test]
+async fn failures_are_stored() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let sig = "failed-sig";
+    let mut tx = tx_record(sig, 26);
+    tx.err = Some("Error processing Instruction 0: custom program error: 0x1770".to_string());
+    tx.failure = Some(TxFailure {
+        kind: FailureKind::Anchor,
+        instruction_index: Some(0),
+        instruction: Some("increment".to_string()),
+        program_id: Some("prog".to_string()),
+        error_code: Some(6000),
+        error_name: Some("Overflow".to_string()),
+        error_message: Some("Counter overflow".to_string()),
+        account: None,
+    });
+    let batch = EventBatch::new(CommitStatus::Confirmed, "prog".to_string(), vec![tx]);
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "tx_failures", sig), 1);
+    let conn = sink.connection();
+    let conn = conn.lock().unwrap();
+    let (kind, instruction, error_code, error_name): (
+        String,
+        Option<String>,
+        Option<i64>,
+        Option<String>,
+    ) = conn
+        .query_row(
+            "SELECT kind, instruction, error_code, error_name FROM tx_failures WHERE signature = ?1",
+            params![sig],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap();
+    assert_eq!(kind, "anchor");
+    assert_eq!(instruction.as_deref(), Some("increment"));
+    assert_eq!(error_code, Some(6000));
+    assert_eq!(error_name.as_deref(), Some("Overflow"));
+}
+
+#[tokio::test]
+async fn incomplete_transactions_are_flagged() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let sig = "truncated-sig";
+    let mut tx = tx_record(sig, 27);
+    tx.incomplete = true;
+    tx.balance_changes = vec![
+        BalanceChange {
+            account: "payer".to_string(),
+            mint: None,
+            owner: None,
+            pre: 1_000_000,
+            post: 995_000,
+        },
+        BalanceChange {
+            account: "lookup".to_string(),
+            mint: Some("mint".to_string()),
+            owner: Some("payer".to_string()),
+            pre: 0,
+            post: u64::MAX,
+        },
+    ];
+    let batch = EventBatch::new(CommitStatus::Confirmed, "prog".to_string(), vec![tx]);
+    sink.write_batch(&batch).await.unwrap();
+    sink.write_batch(&batch).await.unwrap();
+
+    assert_eq!(count(&sink, "balance_changes", sig), 2);
+    let incomplete: bool = query(
+        &sink,
+        "SELECT incomplete FROM transactions WHERE signature = ?1",
+        sig,
+    );
+    assert!(incomplete);
+    let post: String = query(
+        &sink,
+        "SELECT post FROM balance_changes WHERE signature = ?1 AND kind = 'token'",
+        sig,
+    );
+    assert_eq!(post, u64::MAX.to_string());
+}
+
+#[tokio::test]
+async fn checkpoints_are_written_with_the_batch() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let program = "checkpoint-prog";
+    let checkpoint = Checkpoint::new(30, "head-sig".to_string(), 25, "tail-sig".to_string());
+    let batch = EventBatch::new(
+        CommitStatus::Confirmed,
+        program.to_string(),
+        vec![tx_record("checkpoint-sig", 30)],
+    )
+    .with_checkpoint(checkpoint.clone());
+    sink.write_batch(&batch).await.unwrap();
+    assert_eq!(sink.load(program).await.unwrap(), Some(checkpoint));
+
+    let checkpoint = Checkpoint::new(40, "head-sig-2".to_string(), 25, "tail-sig".to_string());
+    sink.save(program, &checkpoint).await.unwrap();
+    assert_eq!(sink.load(program).await.unwrap(), Some(checkpoint));
+    assert_eq!(sink.load("unknown-prog").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn only_confirmed_transactions_are_retracted() {
+    let sink = SqliteSink::in_memory().unwrap();
+    let (dropped, finalized) = ("retracted-sig", "kept-sig");
+    sink.write_batch(&EventBatch::new(
+        CommitStatus::Confirmed,
+        "prog".to_string(),
+        vec![
+            failed_tx_record(dropped, 50),
+            failed_tx_record(finalized, 51),
+        ],
+    ))
+    .await
+    .unwrap();
+    sink.write_batch(&EventBatch::new(
+        CommitStatus::Finalized,
+        "prog".to_string(),
+        vec![failed_tx_record(finalized, 51)],
+    ))
+    .await
+    .unwrap();
+    let retraction = Retraction {
+        program_addr: "prog".to_string(),
+        txs: [(dropped, 50), (finalized, 51)]
+            .iter()
+            .map(|(sig, slot)| RetractedTx {
+                slot: *slot,
+                signature: sig.to_string(),
+            })
+            .collect(),
+    };
+    sink.retract(&retraction).await.unwrap();
+
+    for (sig, expected) in [(dropped, "retracted"), (finalized, "finalized")] {
+        let tx_commitment: String = query(
+            &sink,
+            "SELECT commitment FROM transactions WHERE signature = ?1",
+            sig,
+        );
+        assert_eq!(tx_commitment, expected);
+        let event_commitments: String = query(
+            &sink,
+            "SELECT GROUP_CONCAT(DISTINCT commitment) FROM events WHERE signature = ?1",
+            sig,
+        );
+        assert_eq!(event_commitments, expected);
+        let ix_commitment: String = query(
+            &sink,
+            "SELECT commitment FROM program_instructions WHERE signature = ?1",
+            sig,
+        );
+        assert_eq!(ix_commitment, expected);
+    }
+    // the tables without a commitment only keep the rows of the finalized tx
+    for table in ["instructions", "logs", "tx_failures", "balance_changes"] {
+        assert_eq!(count(&sink, table, dropped), 0, "{}", table);
+        assert!(count(&sink, table, finalized) > 0, "{}", table);
+    }
+}
@@ -0,0 +1,197 @@
+// Tests for decoding fetched transactions into messages and instruction records
+use serde_json::json;
+use solana_indexer::transaction::{decode_message, instruction_records, TxDecodeError};
+use solana_sdk::{
+    hash::Hash,
+    instruction::CompiledInstruction,
+    message::{v0, MessageHeader, VersionedMessage},
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{
+    Encodable, EncodableWithMeta, EncodedTransaction, EncodedTransactionWithStatusMeta,
+    TransactionBinaryEncoding, UiTransactionEncoding,
+};
+
+// Keys are the static keys of the v0 fixture and the addresses it loads from a lookup table
+struct Keys {
+    payer: String,
+    program: String,
+    writable: String,
+    readonly: String,
+}
+
+// v0_tx is a v0 transaction calling the program with the payer, a writable and a readonly
+// address of a lookup table. The lookup table lists the readonly address first.
+fn v0_tx() -> (VersionedTransaction, Keys) {
+    let (payer, program) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        },
+        account_keys: vec![payer, program],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![CompiledInstruction {
+            program_id_index: 1,
+            accounts: vec![0, 2, 3],
+            data: vec![1, 2, 3],
+        }],
+        address_table_lookups: vec![v0::MessageAddressTableLookup {
+            account_key: Pubkey::new_unique(),
+            writable_indexes: vec![1],
+            readonly_indexes: vec![0],
+        }],
+    };
+    let tx = VersionedTransaction {
+        signatures: vec![Signature::default()],
+        message: VersionedMessage::V0(message),
+    };
+    let keys = Keys {
+        payer: payer.to_string(),
+        program: program.to_string(),
+        writable: Pubkey::new_unique().to_string(),
+        readonly: Pubkey::new_unique().to_string(),
+    };
+    (tx, keys)
+}
+
+// encode encodes a transaction the way the node returns it, json messages are not parsed
+fn encode(tx: &VersionedTransaction, encoding: UiTransactionEncoding) -> EncodedTransaction {
+    match encoding {
+        UiTransactionEncoding::Json => tx.json_encode(),
+        _ => tx.encode(encoding),
+    }
+}
+
+// with_meta adds a status meta with the loaded addresses and the given inner instructions
+fn with_meta(
+    transaction: EncodedTransaction,
+    keys: &Keys,
+    inner_instructions: serde_json::Value,
+) -> EncodedTransactionWithStatusMeta {
+    serde_json::from_value(json!({
+        "transaction": transaction,
+        "version": 0,
+        "meta": {
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "innerInstructions": inner_instructions,
+            "loadedAddresses": {"writable": [keys.writable], "readonly": [keys.readonly]},
+        },
+    }))
+    .unwrap()
+}
+
+#[test]
+fn loaded_addresses_follow_the_static_keys_writable_first() {
+    let (tx, keys) = v0_tx();
+    for encoding in [
+        UiTransactionEncoding::Json,
+        UiTransactionEncoding::Base64,
+        UiTransactionEncoding::Base58,
+    ] {
+        let message = decode_message(&with_meta(encode(&tx, encoding), &keys, json!([]))).unwrap();
+        assert_eq!(message.version, Some(0), "{:?}", encoding);
+        assert_eq!(
+            message.account_keys,
+            vec![
+                keys.payer.clone(),
+                keys.program.clone(),
+                keys.writable.clone(),
+                keys.readonly.clone(),
+            ],
+            "{:?}",
+            encoding
+        );
+        let ix = &message.instructions[0];
+        assert_eq!(ix.program_id, keys.program);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                keys.payer.clone(),
+                keys.writable.clone(),
+                keys.readonly.clone()
+            ]
+        );
+        assert_eq!(ix.data.as_deref(), Some([1, 2, 3].as_slice()));
+    }
+}
+
+#[test]
+fn binary_transactions_that_do_not_decode_are_rejected() {
+    let (_, keys) = v0_tx();
+    for encoding in [
+        TransactionBinaryEncoding::Base64,
+        TransactionBinaryEncoding::Base58,
+    ] {
+        let tx = with_meta(
+            EncodedTransaction::Binary("not a transaction".to_string(), encoding),
+            &keys,
+            json!([]),
+        );
+        assert_eq!(decode_message(&tx), Err(TxDecodeError::InvalidTransaction));
+    }
+}
+
+#[test]
+fn inner_instructions_are_attributed_to_their_caller_by_stack_height() {
+    let (tx, keys) = v0_tx();
+    let data = bs58::encode([7]).into_string();
+    let inner = |stack_height: Option<u32>| {
+        json!({
+            "programIdIndex": 1,
+            "accounts": [0],
+            "data": data,
+            "stackHeight": stack_height,
+        })
+    };
+    // the top-level instruction makes two CPIs, the first one makes two CPIs of which the first nests one more
+    let nested = json!([{
+        "index": 0,
+        "instructions": [inner(Some(2)), inner(Some(3)), inner(Some(4)), inner(Some(3)), inner(Some(2))],
+    }]);
+    let message = decode_message(&with_meta(
+        encode(&tx, UiTransactionEncoding::Base64),
+        &keys,
+        nested,
+    ))
+    .unwrap();
+    let records = instruction_records(&message);
+    let tree = records
+        .iter()
+        .map(|record| (record.index, record.stack_height, record.parent_index))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tree,
+        vec![
+            (0, 1, None),
+            (1, 2, Some(0)),
+            (2, 3, Some(1)),
+            (3, 4, Some(2)),
+            (4, 3, Some(1)),
+            (5, 2, Some(0)),
+        ]
+    );
+    assert_eq!(records[1].accounts, vec![keys.payer.clone()]);
+    assert_eq!(records[1].data.as_deref(), Some(data.as_str()));
+
+    // without stack heights every inner instruction is a CPI of the top-level instruction
+    let flat = json!([{"index": 0, "instructions": [inner(None), inner(None)]}]);
+    let message = decode_message(&with_meta(
+        encode(&tx, UiTransactionEncoding::Json),
+        &keys,
+        flat,
+    ))
+    .unwrap();
+    let parents = instruction_records(&message)
+        .iter()
+        .map(|record| (record.stack_height, record.parent_index))
+        .collect::<Vec<_>>();
+    assert_eq!(parents, vec![(1, None), (2, Some(0)), (2, Some(0))]);
+}